@@ -0,0 +1,266 @@
+use std::{
+    fmt, io,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, Receiver, Sender, error::TrySendError},
+};
+
+use crate::{
+    error::AppError,
+    webhook::{HttpTarget, has_port, split_url},
+};
+
+/// How long one delivery may take before the event is given up on.
+const SINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where access events are sent: `http(s)://host[:port]/path` receives one
+/// JSON `POST` per event, `udp://host:port` one JSON datagram per event (for syslog
+/// collectors that take JSON over UDP).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogSink {
+    Http(HttpTarget),
+    Udp(String),
+}
+
+impl FromStr for AccessLogSink {
+    type Err = AppError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidAccessLogSink(raw.to_owned());
+        let (scheme, host, path) = split_url(raw).ok_or_else(invalid)?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "http" | "https" => HttpTarget::parse(raw).map(Self::Http).ok_or_else(invalid),
+            "udp" if path == "/" && has_port(host) => Ok(Self::Udp(host.to_owned())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for AccessLogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(target) => target.fmt(f),
+            Self::Udp(address) => write!(f, "udp://{address}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessAction {
+    Create,
+    Read,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessResult {
+    Success,
+    Failure,
+}
+
+/// One access to a channel as the sink sees it. It identifies the request
+/// and never carries channel content or passwords.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessEvent {
+    /// Unix seconds.
+    pub timestamp: u64,
+    pub action: AccessAction,
+    /// Missing for creates that failed before an id was assigned.
+    pub id: Option<String>,
+    pub result: AccessResult,
+    pub status: u16,
+    /// Peer address of the connection.
+    pub ip: Option<String>,
+    /// `x-forwarded-for` as sent, for deployments behind a proxy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_for: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Hands access events to a background task that delivers them to the sink.
+/// Recording never waits: when `buffer` events are already queued the new one
+/// is dropped and counted instead.
+#[derive(Default)]
+pub struct AccessLog {
+    sender: Option<Sender<AccessEvent>>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    /// Spawns the delivery task, so this needs a Tokio runtime when `sink` is
+    /// set.
+    pub fn new(sink: Option<AccessLogSink>, buffer: usize) -> Self {
+        let Some(sink) = sink else {
+            return Self::default();
+        };
+        let (log, events) = Self::with_buffer(buffer);
+        tokio::spawn(deliver(sink, events));
+        log
+    }
+
+    fn with_buffer(buffer: usize) -> (Self, Receiver<AccessEvent>) {
+        let (sender, events) = mpsc::channel(buffer.max(1));
+        let log = Self {
+            sender: Some(sender),
+            dropped: AtomicU64::new(0),
+        };
+        (log, events)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn record(&self, event: AccessEvent) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::warn!(dropped, "access log buffer full, dropping event");
+            }
+        }
+    }
+
+    /// Events dropped because the buffer was full, since startup.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+async fn deliver(sink: AccessLogSink, mut events: Receiver<AccessEvent>) {
+    let mut socket = None;
+    while let Some(event) = events.recv().await {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to serialize access event");
+                continue;
+            }
+        };
+        let sent = match &sink {
+            AccessLogSink::Http(target) => {
+                tokio::time::timeout(SINK_TIMEOUT, post_event(target, &body)).await
+            }
+            AccessLogSink::Udp(address) => {
+                tokio::time::timeout(SINK_TIMEOUT, send_datagram(&mut socket, address, &body)).await
+            }
+        };
+        match sent {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                socket = None;
+                tracing::warn!(error = %err, %sink, "failed to deliver access event");
+            }
+            Err(_) => {
+                socket = None;
+                tracing::warn!(%sink, "access log sink timed out");
+            }
+        }
+    }
+}
+
+async fn post_event(target: &HttpTarget, body: &[u8]) -> io::Result<()> {
+    match target.post_json(body).await? {
+        code if (200..300).contains(&code) => Ok(()),
+        code => Err(io::Error::other(format!("sink answered {code}"))),
+    }
+}
+
+/// Reuses one connected socket until a send fails.
+async fn send_datagram(
+    socket: &mut Option<UdpSocket>,
+    address: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    if socket.is_none() {
+        let target = tokio::net::lookup_host(address)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::other("sink address did not resolve"))?;
+        let local = if target.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let bound = UdpSocket::bind(local).await?;
+        bound.connect(target).await?;
+        *socket = Some(bound);
+    }
+    let socket = socket.as_ref().expect("socket was just bound");
+    socket.send(body).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessAction, AccessEvent, AccessLog, AccessLogSink, AccessResult};
+    use crate::webhook::HttpTarget;
+
+    fn event() -> AccessEvent {
+        AccessEvent {
+            timestamp: 0,
+            action: AccessAction::Read,
+            id: Some("abc".into()),
+            result: AccessResult::Success,
+            status: 200,
+            ip: None,
+            forwarded_for: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn sink_urls_parse() {
+        assert_eq!(
+            "http://siem.internal/ingest"
+                .parse::<AccessLogSink>()
+                .unwrap(),
+            AccessLogSink::Http(HttpTarget {
+                url: "http://siem.internal/ingest".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            "https://siem.internal:8443/ingest"
+                .parse::<AccessLogSink>()
+                .unwrap()
+                .to_string(),
+            "https://siem.internal:8443/ingest"
+        );
+        assert_eq!(
+            "udp://127.0.0.1:514".parse::<AccessLogSink>().unwrap(),
+            AccessLogSink::Udp("127.0.0.1:514".into())
+        );
+        for invalid in [
+            "ftp://siem.internal",
+            "https://user@siem.internal",
+            "udp://collector",
+            "udp://collector:514/path",
+            "http://user@siem.internal",
+            "siem.internal",
+        ] {
+            assert!(invalid.parse::<AccessLogSink>().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn events_past_the_buffer_are_dropped_and_counted() {
+        let (log, mut events) = AccessLog::with_buffer(1);
+        log.record(event());
+        log.record(event());
+        log.record(event());
+        assert_eq!(log.dropped(), 2);
+        assert_eq!(events.recv().await.unwrap().id.as_deref(), Some("abc"));
+    }
+}
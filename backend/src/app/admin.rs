@@ -0,0 +1,333 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::{info, instrument};
+
+use super::extract::ChannelJson;
+use crate::{
+    channel::deserialize_channel,
+    error::AppError,
+    state::{SharedState, delete_channel_keys},
+};
+
+const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+/// Most matches one search request returns (a page may add a few more).
+const MAX_SEARCH_RESULTS: usize = 100;
+/// Keys one search request may visit before handing back a cursor.
+const SEARCH_SCAN_BUDGET: usize = 10_000;
+const SEARCH_PAGE_KEYS: usize = 500;
+
+/// Operator-only routes. They are only mounted when `ADMIN_TOKEN` is set and
+/// every request must present it in `x-admin-token`.
+pub fn admin_routes(state: SharedState) -> Router<SharedState> {
+    Router::new()
+        .route("/admin/channels/search", get(search_channels))
+        .route("/admin/channels/:id/raw", get(raw_channel))
+        .route("/admin/tags/:tag/channels", get(tagged_channels))
+        .route("/admin/evict", post(evict_channels))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+async fn require_admin(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let expected = state.config().admin_token.as_deref();
+    if !admin_token_matches(expected, request.headers()) {
+        return Err(AppError::InvalidAdminToken);
+    }
+    Ok(next.run(request).await)
+}
+
+fn admin_token_matches(expected: Option<&str>, headers: &HeaderMap) -> bool {
+    let Some(expected) = expected.filter(|token| !token.is_empty()) else {
+        return false;
+    };
+    let Some(provided) = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    expected.as_bytes().ct_eq(provided.as_bytes()).into()
+}
+
+/// Returns the exact value stored in Redis, password hash included, for
+/// diagnosing corrupt or legacy records. Bypasses channel passwords.
+#[instrument(level = "debug", skip(state))]
+pub async fn raw_channel(
+    Path(id): Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Response, AppError> {
+    let raw = state
+        .storage()
+        .get(&state.channel_key(&id))
+        .await?
+        .ok_or(AppError::ChannelNotFound)?;
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        raw,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChannelSearchQuery {
+    pub key: String,
+    pub value: String,
+    /// `next_cursor` of the previous response, to continue the scan.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelSearchResponse {
+    pub ids: Vec<String>,
+    pub scanned: usize,
+    /// Present while channels remain unscanned.
+    pub next_cursor: Option<String>,
+}
+
+/// Finds channels whose metadata has `key` set to exactly `value`. There is
+/// no index: every channel is loaded and checked, so one request visits at
+/// most `SEARCH_SCAN_BUDGET` keys and stops after about `limit` matches;
+/// follow `next_cursor` to continue.
+#[instrument(level = "debug", skip(state))]
+pub async fn search_channels(
+    Query(query): Query<ChannelSearchQuery>,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelSearchResponse>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_SEARCH_RESULTS)
+        .clamp(1, MAX_SEARCH_RESULTS);
+    let prefix = state.channel_key_prefix();
+    let mut cursor = query.cursor.clone();
+    let mut ids = Vec::new();
+    let mut scanned = 0;
+    loop {
+        let page = state
+            .storage()
+            .scan_keys(&prefix, cursor.as_deref(), SEARCH_PAGE_KEYS)
+            .await?;
+        for key in page.keys {
+            scanned += 1;
+            let Some(raw) = state.storage().get(&key).await? else {
+                continue;
+            };
+            let matches = deserialize_channel(raw)
+                .metadata
+                .is_some_and(|metadata| metadata.get(&query.key) == Some(&query.value));
+            if let (true, Some(id)) = (matches, key.strip_prefix(&prefix)) {
+                ids.push(id.to_owned());
+            }
+        }
+        cursor = page.next;
+        if cursor.is_none() || ids.len() >= limit || scanned >= SEARCH_SCAN_BUDGET {
+            break;
+        }
+    }
+
+    Ok(Json(ChannelSearchResponse {
+        ids,
+        scanned,
+        next_cursor: cursor,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaggedChannelsResponse {
+    pub ids: Vec<String>,
+}
+
+/// Lists the channels carrying `tag`, sorted, from the tag's index set rather
+/// than a scan. Ids whose channel has gone are left out and pruned from the
+/// set on the way.
+#[instrument(level = "debug", skip(state))]
+pub async fn tagged_channels(
+    Path(tag): Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<TaggedChannelsResponse>, AppError> {
+    let key = state.tag_key(&tag);
+    let mut ids = Vec::new();
+    let mut gone = Vec::new();
+    for id in state.storage().smembers(&key).await? {
+        if state.storage().exists(&state.channel_key(&id)).await? {
+            ids.push(id);
+        } else {
+            gone.push(id);
+        }
+    }
+    state.storage().srem(&key, &gone).await?;
+    ids.sort_unstable();
+    Ok(Json(TaggedChannelsResponse { ids }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EvictRequest {
+    /// Evict at most this many of the largest channels.
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// Only evict channels whose stored value is at least this many bytes.
+    #[serde(default)]
+    pub min_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct EvictedChannel {
+    pub id: String,
+    pub bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct EvictResponse {
+    pub scanned: usize,
+    pub evicted: Vec<EvictedChannel>,
+}
+
+/// Relieves memory pressure by deleting the largest channels. Sizes come from
+/// `STRLEN` over a cursor `SCAN`, so payloads are never loaded; only the
+/// current best candidates are kept in memory.
+#[instrument(level = "debug", skip(state))]
+pub async fn evict_channels(
+    State(state): State<SharedState>,
+    ChannelJson(request): ChannelJson<EvictRequest>,
+) -> Result<Json<EvictResponse>, AppError> {
+    if request.count.is_none() && request.min_bytes.is_none() {
+        return Err(AppError::InvalidEvictionRequest);
+    }
+
+    let prefix = state.channel_key_prefix();
+    let mut selector = EvictionSelector::new(request.count, request.min_bytes);
+    let mut scanned = 0;
+    state
+        .storage()
+        .scan_sizes(&prefix, &mut |key, bytes| {
+            scanned += 1;
+            if let Some(id) = key.strip_prefix(&prefix) {
+                selector.offer(id.to_owned(), bytes);
+            }
+        })
+        .await?;
+
+    let evicted = selector.finish();
+    for channel in &evicted {
+        let tags = match state.storage().get(&state.channel_key(&channel.id)).await? {
+            Some(raw) => deserialize_channel(raw).tags,
+            None => Vec::new(),
+        };
+        delete_channel_keys(&state, &channel.id, &tags).await?;
+        state.events().on_deleted(&channel.id);
+    }
+    info!(scanned, evicted = evicted.len(), "evicted largest channels");
+
+    Ok(Json(EvictResponse { scanned, evicted }))
+}
+
+/// Keeps the largest channels seen so far, bounded by `count` when given.
+struct EvictionSelector {
+    count: Option<usize>,
+    min_bytes: u64,
+    heap: BinaryHeap<Reverse<(u64, String)>>,
+}
+
+impl EvictionSelector {
+    fn new(count: Option<usize>, min_bytes: Option<u64>) -> Self {
+        Self {
+            count,
+            min_bytes: min_bytes.unwrap_or(0),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn offer(&mut self, id: String, bytes: u64) {
+        if bytes < self.min_bytes || self.count == Some(0) {
+            return;
+        }
+        self.heap.push(Reverse((bytes, id)));
+        if self.count.is_some_and(|count| self.heap.len() > count) {
+            self.heap.pop();
+        }
+    }
+
+    /// Selected channels, largest first.
+    fn finish(self) -> Vec<EvictedChannel> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((bytes, id))| EvictedChannel { id, bytes })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{EvictedChannel, EvictionSelector, admin_token_matches};
+
+    fn with_token(token: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", HeaderValue::from_static(token));
+        headers
+    }
+
+    #[test]
+    fn admin_token_must_match_exactly() {
+        assert!(admin_token_matches(Some("s3cret"), &with_token("s3cret")));
+        assert!(!admin_token_matches(Some("s3cret"), &with_token("s3cre")));
+        assert!(!admin_token_matches(Some("s3cret"), &HeaderMap::new()));
+    }
+
+    #[test]
+    fn admin_access_is_refused_without_a_configured_token() {
+        assert!(!admin_token_matches(None, &with_token("")));
+        assert!(!admin_token_matches(Some(""), &with_token("")));
+    }
+
+    fn select(count: Option<usize>, min_bytes: Option<u64>) -> Vec<EvictedChannel> {
+        let mut selector = EvictionSelector::new(count, min_bytes);
+        for (id, bytes) in [
+            ("small", 10),
+            ("huge", 9_000),
+            ("medium", 500),
+            ("large", 4_000),
+        ] {
+            selector.offer(id.to_owned(), bytes);
+        }
+        selector.finish()
+    }
+
+    #[test]
+    fn largest_channels_are_evicted_first() {
+        let evicted = select(Some(2), None);
+        let ids: Vec<_> = evicted.iter().map(|channel| channel.id.as_str()).collect();
+        assert_eq!(ids, ["huge", "large"]);
+        assert_eq!(evicted[0].bytes, 9_000);
+    }
+
+    #[test]
+    fn byte_threshold_selects_every_channel_over_it() {
+        let ids: Vec<_> = select(None, Some(500))
+            .into_iter()
+            .map(|channel| channel.id)
+            .collect();
+        assert_eq!(ids, ["huge", "large", "medium"]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+
+use axum::{extract::ConnectInfo, http::HeaderMap};
+use serde::Serialize;
+
+use super::middleware::FORWARDED_FOR_HEADER;
+use crate::{
+    channel::{ChannelData, channel_size},
+    error::AppError,
+    state::SharedState,
+};
+
+/// What `CREATE_APPROVAL_URL` is asked about a channel before it is stored.
+/// Describes the content without including any of it.
+#[derive(Debug, Serialize)]
+struct CreateApprovalRequest<'a> {
+    /// Decoded bytes of text and files together.
+    size: usize,
+    file_count: usize,
+    mime_types: Vec<&'a str>,
+    /// Peer address of the connection.
+    client_ip: Option<String>,
+    /// `x-forwarded-for` as sent, for deployments behind a proxy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forwarded_for: Option<&'a str>,
+}
+
+/// Asks the approval hook, when one is configured, whether `data` may be
+/// stored. Anything but a `200` within `CREATE_APPROVAL_TIMEOUT_MS`, including
+/// no answer at all, refuses the create with `403`.
+pub(super) async fn approve_creation(
+    state: &SharedState,
+    data: &ChannelData,
+    peer: Option<&ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> Result<(), AppError> {
+    let Some(target) = &state.config().create_approval_url else {
+        return Ok(());
+    };
+    let body = serde_json::to_vec(&CreateApprovalRequest {
+        size: channel_size(data).unwrap_or_default(),
+        file_count: data.files.len(),
+        mime_types: data
+            .files
+            .iter()
+            .map(|file| file.mime_type.as_str())
+            .collect(),
+        client_ip: peer.map(|ConnectInfo(addr)| addr.ip().to_string()),
+        forwarded_for: headers
+            .get(FORWARDED_FOR_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    })?;
+    let timeout = state.config().create_approval_timeout;
+    match tokio::time::timeout(timeout, target.post_json(&body)).await {
+        Ok(Ok(200)) => Ok(()),
+        Ok(Ok(status)) => {
+            tracing::info!(status, "create approval hook denied a channel");
+            Err(AppError::CreationDenied)
+        }
+        Ok(Err(err)) => {
+            tracing::warn!(error = %err, %target, "create approval hook failed");
+            Err(AppError::CreationDenied)
+        }
+        Err(_) => {
+            tracing::warn!(%target, "create approval hook timed out");
+            Err(AppError::CreationDenied)
+        }
+    }
+}
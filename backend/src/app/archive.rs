@@ -0,0 +1,189 @@
+use std::{collections::HashSet, io};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::Response,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use futures_util::stream;
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use super::{
+    files::{Disposition, content_disposition, sanitize_file_name},
+    handlers::{authorize_files, charge_read, encryption_key, load_authorized},
+};
+use crate::{
+    channel::{ChannelData, ChannelFile},
+    error::AppError,
+    state::{SharedState, refresh_ttl_on_read},
+};
+
+const TEXT_ENTRY_NAME: &str = "text.txt";
+/// Bytes buffered before a chunk is handed to the response body.
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Chunks that may wait for a slow client before the writer blocks.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Streams the channel's files (and its text as `text.txt`, when there is any)
+/// as an uncompressed tar. Entries are decoded and written one at a time on a
+/// blocking thread, so only a single file is held decoded at once.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn download_channel_archive(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Response, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    authorize_files(&record.data.files, &headers).await?;
+
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let data = std::mem::take(&mut record.data);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChunkWriter::new(sender.clone());
+        let result = write_archive(&mut writer, data).and_then(|()| writer.flush_chunk());
+        if let Err(err) = result {
+            tracing::warn!(error = %err, "channel archive aborted");
+            let _ = sender.blocking_send(Err(err));
+        }
+    });
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    let mut response = Response::new(Body::from_stream(body));
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-tar"),
+    );
+    response_headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition(Disposition::Attachment, &format!("{id}.tar")),
+    );
+    Ok(response)
+}
+
+fn write_archive(writer: &mut ChunkWriter, data: ChannelData) -> io::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    let mut names = EntryNames::default();
+    if !data.text.is_empty() {
+        let name = names.claim(TEXT_ENTRY_NAME);
+        append(&mut builder, &name, data.text.as_bytes())?;
+    }
+    for ChannelFile {
+        name, data_base64, ..
+    } in data.files
+    {
+        let bytes = BASE64_ENGINE
+            .decode(&data_base64)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        drop(data_base64);
+        let name = names.claim(&sanitize_file_name(&name));
+        append(&mut builder, &name, &bytes)?;
+    }
+    builder.finish()
+}
+
+fn append(
+    builder: &mut tar::Builder<&mut ChunkWriter>,
+    name: &str,
+    bytes: &[u8],
+) -> io::Result<()> {
+    let mut entry = tar::Header::new_gnu();
+    entry.set_size(bytes.len() as u64);
+    entry.set_mode(0o644);
+    entry.set_mtime(0);
+    entry.set_entry_type(tar::EntryType::Regular);
+    builder.append_data(&mut entry, name, bytes)
+}
+
+/// Hands out unique entry names: a repeated name gets ` (2)`, ` (3)`, ...
+/// before its extension, so no entry overwrites another on extraction.
+#[derive(Default)]
+struct EntryNames {
+    taken: HashSet<String>,
+}
+
+impl EntryNames {
+    fn claim(&mut self, name: &str) -> String {
+        let (stem, extension) = match name.rfind('.') {
+            Some(dot) if dot > 0 => name.split_at(dot),
+            _ => (name, ""),
+        };
+        let mut candidate = name.to_owned();
+        let mut n = 2;
+        while !self.taken.insert(candidate.clone()) {
+            candidate = format!("{stem} ({n}){extension}");
+            n += 1;
+        }
+        candidate
+    }
+}
+
+/// `io::Write` adapter that batches output into chunks for a streamed body.
+/// Writing fails once the client has gone away, which stops the archive.
+struct ChunkWriter {
+    buffer: Vec<u8>,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ChunkWriter {
+    fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(CHUNK_BYTES),
+            sender,
+        }
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_BYTES),
+        ));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= CHUNK_BYTES {
+            self.flush_chunk()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_chunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EntryNames;
+
+    #[test]
+    fn repeated_entry_names_are_made_unique() {
+        let mut names = EntryNames::default();
+        assert_eq!(names.claim("a.txt"), "a.txt");
+        assert_eq!(names.claim("a.txt"), "a (2).txt");
+        assert_eq!(names.claim("a.txt"), "a (3).txt");
+        assert_eq!(names.claim("notes"), "notes");
+        assert_eq!(names.claim("notes"), "notes (2)");
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+};
+use serde::Serialize;
+use tracing::instrument;
+
+use super::{
+    approval::approve_creation,
+    extract::ChannelJson,
+    handlers::{
+        CreateChannelRequest, encode_record, encryption_key, expires_at, prepare_new_channel,
+    },
+    password::{IssuedPassword, issue_password},
+};
+use crate::{
+    channel::{generate_channel_id, hash_channel_password_async, unix_now},
+    error::AppError,
+    state::{SharedState, grace_ttl, index_tags, track_expiry},
+};
+
+/// Outcome for one item of a bulk create, in request order.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BulkCreateResult {
+    Created {
+        id: String,
+        #[serde(flatten)]
+        password: IssuedPassword,
+        ttl_seconds: u64,
+        expires_at: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Creates several channels in one request. Every item is validated on its
+/// own, so one bad item fails alone; the valid ones are written together in a
+/// single batch per storage shard.
+#[instrument(level = "debug", skip_all)]
+pub async fn bulk_create_channels(
+    State(state): State<SharedState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ChannelJson(payloads): ChannelJson<Vec<CreateChannelRequest>>,
+) -> Result<Json<Vec<BulkCreateResult>>, AppError> {
+    let max = state.config().max_bulk_create;
+    if payloads.len() > max {
+        return Err(AppError::BatchTooLarge(max));
+    }
+    state.shed_create_if_slow()?;
+
+    let key = encryption_key(&headers);
+    let mut entries = Vec::with_capacity(payloads.len());
+    let mut prepared_items = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let prepared = match prepare_new_channel(&state, payload).await {
+            Ok((record, password)) => {
+                match approve_creation(&state, &record.data, peer.as_ref(), &headers).await {
+                    Ok(()) => {
+                        let hash = hash_channel_password_async(password.clone()).await;
+                        Ok((record, password, hash))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        };
+        let prepared = prepared.and_then(|(mut record, password, hash)| {
+            let id = generate_channel_id();
+            let ttl = record.ttl(state.channel_ttl());
+            record.password_hash = Some(hash);
+            record.created_at = Some(unix_now());
+            record.version = 1;
+            if let Some(key) = &key {
+                record.seal(key)?;
+            }
+            let grace_ttl = grace_ttl(&state, &record, ttl);
+            let tags = record.tags.clone();
+            let serialized = encode_record(&state, record)?;
+            if let Some(grace_ttl) = grace_ttl {
+                entries.push((state.grace_key(&id), serialized.clone(), grace_ttl));
+            }
+            entries.push((state.channel_key(&id), serialized, ttl));
+            Ok((id, password, ttl, tags))
+        });
+        prepared_items.push(prepared);
+    }
+
+    state.storage().set_many_ex(&entries).await?;
+    let mut results = Vec::with_capacity(prepared_items.len());
+    for prepared in prepared_items {
+        let result = match prepared {
+            Ok((id, password, ttl, tags)) => {
+                track_expiry(&state, &id, ttl).await?;
+                index_tags(&state, &id, &tags, ttl).await?;
+                state.events().on_created(&id);
+                BulkCreateResult::Created {
+                    password: issue_password(&state, &id, password, ttl).await?,
+                    id,
+                    ttl_seconds: ttl.as_secs(),
+                    expires_at: expires_at(ttl.as_secs() as i64),
+                }
+            }
+            Err(err) => BulkCreateResult::Failed {
+                error: err.to_string(),
+            },
+        };
+        results.push(result);
+    }
+    Ok(Json(results))
+}
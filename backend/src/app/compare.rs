@@ -0,0 +1,77 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::HeaderMap,
+};
+use serde::Serialize;
+use tracing::instrument;
+
+use super::handlers::{
+    encryption_key, load_authorized, load_record, mimic_password_check, password_rejected,
+};
+use crate::{
+    channel::{StoredChannel, compare_channel_data, verify_channel_password_async},
+    error::AppError,
+    state::{SharedState, missing_channel_error},
+};
+
+const OTHER_PASSWORD_HEADER: &str = "x-other-channel-password";
+const OTHER_ENCRYPTION_KEY_HEADER: &str = "x-other-channel-encryption-key";
+
+#[derive(Debug, Serialize)]
+pub struct ChannelEqualsResponse {
+    pub text_equal: bool,
+    pub files_equal: bool,
+    pub equal: bool,
+}
+
+/// Tells whether two channels hold the same content without returning any of
+/// it. The first channel authenticates like any other request; the second
+/// takes its password (and encryption key) from the `x-other-channel-*`
+/// headers. Comparing does not count as a read and leaves both TTLs alone.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_equals(
+    Path((id, other_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelEqualsResponse>, AppError> {
+    let mut left = load_authorized(&state, &id, &headers).await?;
+    left.unseal(encryption_key(&headers).as_deref())?;
+
+    let mut right = load_other(&state, &other_id, &headers).await?;
+    right.unseal(header_str(&headers, OTHER_ENCRYPTION_KEY_HEADER))?;
+
+    let (text_equal, files_equal) = compare_channel_data(&left.data, &right.data);
+    Ok(Json(ChannelEqualsResponse {
+        text_equal,
+        files_equal,
+        equal: text_equal && files_equal,
+    }))
+}
+
+async fn load_other(
+    state: &SharedState,
+    id: &str,
+    headers: &HeaderMap,
+) -> Result<StoredChannel, AppError> {
+    let password = header_str(headers, OTHER_PASSWORD_HEADER);
+    let Some(record) = load_record(state, id).await? else {
+        if state.config().hide_channel_existence {
+            mimic_password_check(password).await;
+        }
+        return Err(missing_channel_error(state, id).await?);
+    };
+    if !verify_channel_password_async(record.password_hash.clone(), password.map(str::to_owned))
+        .await
+    {
+        return Err(password_rejected(state));
+    }
+    Ok(record)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+}
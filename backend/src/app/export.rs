@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::{
+    approval::approve_creation,
+    extract::ChannelJson,
+    handlers::{
+        CreateChannelResponse, authorize_files, charge_read, encryption_key, load_authorized,
+        store_new_channel,
+    },
+};
+use crate::{
+    channel::{
+        ChannelData, StoredChannel, apply_file_id_policy, check_content_requirement,
+        generate_channel_password, validate_channel_data,
+    },
+    error::AppError,
+    state::{SharedState, refresh_ttl_on_read},
+};
+
+/// Bump when the shape of the exported `ChannelData` changes incompatibly.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// A channel's whole content as one opaque blob: base64 over the JSON of its
+/// `ChannelData`. Access settings (password, read limits) are not included.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelExport {
+    pub format_version: u32,
+    pub data: String,
+}
+
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn export_channel(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelExport>, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    authorize_files(&record.data.files, &headers).await?;
+
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    Ok(Json(ChannelExport {
+        format_version: EXPORT_FORMAT_VERSION,
+        data: BASE64_ENGINE.encode(serde_json::to_vec(&record.data)?),
+    }))
+}
+
+/// Recreates an exported channel under a fresh id, password and TTL.
+#[instrument(level = "debug", skip(state, headers, payload))]
+pub async fn import_channel(
+    State(state): State<SharedState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ChannelJson(payload): ChannelJson<ChannelExport>,
+) -> Result<(StatusCode, Json<CreateChannelResponse>), AppError> {
+    state.shed_create_if_slow()?;
+    let mut data = decode_export(&payload)?;
+    apply_file_id_policy(&mut data, state.config().file_id_policy)?;
+    validate_channel_data(&data)?;
+    check_content_requirement(&data, state.config().content_requirement)?;
+    approve_creation(&state, &data, peer.as_ref(), &headers).await?;
+
+    let record = StoredChannel {
+        data,
+        ..StoredChannel::default()
+    };
+    store_new_channel(&state, record, generate_channel_password(), &headers).await
+}
+
+fn decode_export(export: &ChannelExport) -> Result<ChannelData, AppError> {
+    if export.format_version != EXPORT_FORMAT_VERSION {
+        return Err(AppError::InvalidExport);
+    }
+    let json = BASE64_ENGINE
+        .decode(&export.data)
+        .map_err(|_| AppError::InvalidExport)?;
+    serde_json::from_slice(&json).map_err(|_| AppError::InvalidExport)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+
+    use super::{ChannelExport, EXPORT_FORMAT_VERSION, decode_export};
+    use crate::error::AppError;
+
+    #[test]
+    fn unknown_versions_and_garbage_are_rejected() {
+        let future = ChannelExport {
+            format_version: EXPORT_FORMAT_VERSION + 1,
+            data: BASE64_ENGINE.encode(br#"{"text":"hi"}"#),
+        };
+        assert!(matches!(
+            decode_export(&future),
+            Err(AppError::InvalidExport)
+        ));
+
+        let garbage = ChannelExport {
+            format_version: EXPORT_FORMAT_VERSION,
+            data: BASE64_ENGINE.encode(b"not json"),
+        };
+        assert!(matches!(
+            decode_export(&garbage),
+            Err(AppError::InvalidExport)
+        ));
+    }
+}
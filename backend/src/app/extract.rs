@@ -0,0 +1,190 @@
+use std::io::Read;
+
+use axum::{
+    Json, async_trait,
+    body::{Body, Bytes, to_bytes},
+    extract::{
+        FromRequest, Request,
+        rejection::{BytesRejection, JsonRejection},
+    },
+    http::{HeaderMap, StatusCode, header},
+};
+use flate2::read::GzDecoder;
+use serde::de::DeserializeOwned;
+
+use super::middleware::is_json;
+use crate::{error::AppError, state::SharedState};
+
+/// `Json<T>` that, with `STRICT_JSON` enabled, refuses bodies carrying fields
+/// `T` does not know, so a typo like `txt` fails loudly instead of producing an
+/// empty channel. Outside strict mode it parses like `Json<T>`. Either way a
+/// `Content-Encoding: gzip` body is inflated first, and a rejected body is
+/// answered with an `AppError` rather than axum's plain-text rejection.
+pub struct ChannelJson<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequest<SharedState> for ChannelJson<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let request = inflate_gzip(request, state.config().max_request_bytes).await?;
+        if !state.config().strict_json {
+            let Json(value) = Json::<T>::from_request(request, state)
+                .await
+                .map_err(json_rejection)?;
+            return Ok(Self(value));
+        }
+
+        if !is_json(request.headers()) {
+            return Err(AppError::JsonContentTypeRequired);
+        }
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(bytes_rejection)?;
+        deserialize_strict(&bytes).map(Self)
+    }
+}
+
+/// The `AppError` for each way `Json<T>` can refuse a body, keeping the status
+/// axum would have used.
+fn json_rejection(rejection: JsonRejection) -> AppError {
+    match rejection {
+        JsonRejection::JsonDataError(err) => AppError::InvalidJsonBody(err.body_text()),
+        JsonRejection::JsonSyntaxError(err) => AppError::MalformedJson(err.body_text()),
+        JsonRejection::MissingJsonContentType(_) => AppError::JsonContentTypeRequired,
+        JsonRejection::BytesRejection(err) => bytes_rejection(err),
+        _ => AppError::UnreadableBody,
+    }
+}
+
+/// A body that could not be buffered: over the body limit (which a chunked
+/// upload only hits while streaming) or cut off.
+fn bytes_rejection(rejection: BytesRejection) -> AppError {
+    if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::RequestTooLarge
+    } else {
+        AppError::UnreadableBody
+    }
+}
+
+/// Swaps a gzip-encoded body for its inflated content. Inflating stops just
+/// past `max_bytes`, the same limit plain bodies get, so a small
+/// decompression bomb is refused without ever being expanded in full.
+async fn inflate_gzip(request: Request, max_bytes: usize) -> Result<Request, AppError> {
+    if !is_gzip(request.headers()) {
+        return Ok(request);
+    }
+    let (mut parts, body) = request.into_parts();
+    // The body-limit layer already caps the compressed size.
+    let compressed = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| AppError::RequestTooLarge)?;
+    let inflated = inflate(&compressed, max_bytes)?;
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Request::from_parts(parts, Body::from(inflated)))
+}
+
+fn is_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|encoding| {
+            let encoding = encoding.trim();
+            encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip")
+        })
+}
+
+fn inflate(compressed: &[u8], max_bytes: usize) -> Result<Vec<u8>, AppError> {
+    let mut inflated = Vec::new();
+    GzDecoder::new(compressed)
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|_| AppError::InvalidContentEncoding)?;
+    if inflated.len() > max_bytes {
+        return Err(AppError::RequestTooLarge);
+    }
+    Ok(inflated)
+}
+
+/// Parses `bytes` straight into `T`, refusing the first field `T` ignored.
+/// No intermediate `serde_json::Value` is built, so caps applied while
+/// deserializing (like the `files` one) bound memory in strict mode too.
+fn deserialize_strict<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, AppError> {
+    let mut unknown = None;
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_ignored::deserialize(&mut deserializer, |path| {
+        unknown.get_or_insert_with(|| path.to_string());
+    })
+    .map_err(strict_json_error)?;
+    deserializer.end().map_err(strict_json_error)?;
+
+    match unknown {
+        Some(field) => Err(AppError::UnknownField(field)),
+        None => Ok(value),
+    }
+}
+
+/// Same statuses and wording as `Json<T>`: broken syntax is a `400`, well-formed
+/// JSON of the wrong shape a `422`.
+fn strict_json_error(err: serde_json::Error) -> AppError {
+    if err.is_data() {
+        AppError::InvalidJsonBody(format!(
+            "Failed to deserialize the JSON body into the target type: {err}"
+        ))
+    } else {
+        AppError::MalformedJson(format!("Failed to parse the request body as JSON: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::deserialize_strict;
+    use crate::{
+        app::{CreateChannelRequest, UpdateChannelRequest},
+        config::MAX_FILES_PER_CHANNEL,
+        error::AppError,
+    };
+
+    fn strict<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, AppError> {
+        deserialize_strict(value.to_string().as_bytes())
+    }
+
+    #[test]
+    fn strict_parsing_names_the_unknown_field() {
+        let result = strict::<UpdateChannelRequest>(json!({ "text": "", "txt": "typo" }));
+        let Err(AppError::UnknownField(field)) = result else {
+            panic!("typo'd field should be rejected");
+        };
+        assert_eq!(field, "txt");
+    }
+
+    #[test]
+    fn strict_parsing_accepts_known_fields() {
+        let parsed = strict::<UpdateChannelRequest>(json!({ "text": "hi" }));
+        assert!(matches!(parsed, Ok(request) if request.text == "hi"));
+    }
+
+    #[test]
+    fn strict_parsing_caps_an_oversized_files_array() {
+        let file = json!({
+            "id": "f", "name": "a", "mime_type": "text/plain", "size": 0, "data_base64": ""
+        });
+        let files = vec![file; MAX_FILES_PER_CHANNEL * 40];
+        let Ok(request) = strict::<CreateChannelRequest>(json!({ "files": files })) else {
+            panic!("an oversized files array is still well-formed");
+        };
+        assert_eq!(request.files.len(), MAX_FILES_PER_CHANNEL + 1);
+    }
+
+    #[test]
+    fn strict_parsing_rejects_trailing_garbage() {
+        let result = deserialize_strict::<UpdateChannelRequest>(br#"{"text": "hi"} ]"#);
+        assert!(matches!(result, Err(AppError::MalformedJson(_))));
+    }
+}
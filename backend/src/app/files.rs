@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    response::Response,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::{
+    extract::ChannelJson,
+    handlers::{
+        authorize_files, charge_read, encryption_key, load_authorized, load_authorized_with_query,
+    },
+};
+use crate::{
+    channel::mime_essence,
+    config::AppConfig,
+    error::AppError,
+    state::{SharedState, count_download, refresh_ttl_on_read},
+};
+
+const FALLBACK_FILE_NAME: &str = "download";
+const FALLBACK_MIME_TYPE: &str = "application/octet-stream";
+/// Lists (percent-encoded, comma-separated) requested ids the channel lacks.
+const MISSING_FILE_IDS_HEADER: HeaderName = HeaderName::from_static("x-missing-file-ids");
+
+/// Types a browser renders without running script, so they may be shown inline.
+const INLINE_SAFE_MIME_TYPES: &[&str] = &[
+    "application/pdf",
+    "image/avif",
+    "image/bmp",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    Inline,
+    #[default]
+    Attachment,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct RawFileQuery {
+    #[serde(default)]
+    pub disposition: Disposition,
+    /// Channel password, honoured only with `ALLOW_PASSWORD_QUERY`.
+    #[serde(default)]
+    pub pw: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawFilesRequest {
+    pub file_ids: Vec<String>,
+}
+
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn download_channel_file(
+    Path((id, file_id)): Path<(String, String)>,
+    Query(query): Query<RawFileQuery>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Response, AppError> {
+    let query_password = query
+        .pw
+        .as_deref()
+        .filter(|_| state.config().allow_password_query);
+    let mut record = load_authorized_with_query(&state, &id, &headers, query_password).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    let Some(file) = record.data.files.iter().find(|file| file.id == file_id) else {
+        return Err(AppError::ChannelFileNotFound);
+    };
+    authorize_files([file], &headers).await?;
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    let index = record
+        .data
+        .files
+        .iter()
+        .position(|file| file.id == file_id)
+        .ok_or(AppError::ChannelFileNotFound)?;
+    let file = record.data.files.swap_remove(index);
+
+    let bytes = BASE64_ENGINE
+        .decode(&file.data_base64)
+        .map_err(|_| AppError::InvalidFileData)?;
+
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    count_download(&state, &id, &file.id);
+    let (disposition, content_type) =
+        served_type(state.config(), query.disposition, &file.mime_type);
+
+    let mut response = Response::new(Body::from(bytes));
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CONTENT_TYPE, content_type);
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    response_headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition(disposition, &file.name),
+    );
+    Ok(response)
+}
+
+/// Several files' raw bytes in one `multipart/mixed` response, one part per
+/// file in the requested order, each with its own `Content-Type`,
+/// `Content-Disposition` and `Content-ID` (the file id). Requested ids the
+/// channel lacks are listed in `x-missing-file-ids`; if none match, `404`.
+/// Counts as a single read.
+#[instrument(level = "debug", skip(state, headers, payload))]
+pub async fn download_channel_files(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    ChannelJson(payload): ChannelJson<RawFilesRequest>,
+) -> Result<Response, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    if !payload
+        .file_ids
+        .iter()
+        .any(|file_id| record.data.files.iter().any(|file| &file.id == file_id))
+    {
+        return Err(AppError::ChannelFileNotFound);
+    }
+    let requested = record
+        .data
+        .files
+        .iter()
+        .filter(|file| payload.file_ids.contains(&file.id));
+    authorize_files(requested, &headers).await?;
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let boundary = format!("lynkc-{}", uuid::Uuid::new_v4().simple());
+    let mut body = Vec::new();
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    for file_id in &payload.file_ids {
+        if !seen.insert(file_id.as_str()) {
+            continue;
+        }
+        let Some(file) = record.data.files.iter().find(|file| &file.id == file_id) else {
+            missing.push(percent_encode(file_id));
+            continue;
+        };
+        let bytes = BASE64_ENGINE
+            .decode(&file.data_base64)
+            .map_err(|_| AppError::InvalidFileData)?;
+        count_download(&state, &id, &file.id);
+        let (disposition, content_type) =
+            served_type(state.config(), Disposition::Attachment, &file.mime_type);
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        for (name, value) in [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(disposition, &file.name),
+            ),
+        ] {
+            body.extend_from_slice(name.as_str().as_bytes());
+            body.extend_from_slice(b": ");
+            body.extend_from_slice(value.as_bytes());
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(
+            format!("content-id: <{}>\r\n\r\n", percent_encode(file_id)).as_bytes(),
+        );
+        body.extend_from_slice(&bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    let mut response = Response::new(Body::from(body));
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&format!("multipart/mixed; boundary={boundary}"))
+            .expect("boundary is ASCII"),
+    );
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if !missing.is_empty() {
+        response_headers.insert(
+            MISSING_FILE_IDS_HEADER,
+            HeaderValue::from_str(&missing.join(",")).expect("percent-encoded ids are ASCII"),
+        );
+    }
+    Ok(response)
+}
+
+/// The disposition and `Content-Type` a file is served with: its declared type
+/// unless that is one of the `RISKY_MIME_TYPES`, which go out as
+/// `application/octet-stream` attachments.
+fn served_type(
+    config: &AppConfig,
+    requested: Disposition,
+    mime_type: &str,
+) -> (Disposition, HeaderValue) {
+    if is_risky_mime_type(&config.risky_mime_types, mime_type) {
+        return (
+            Disposition::Attachment,
+            HeaderValue::from_static(FALLBACK_MIME_TYPE),
+        );
+    }
+    (
+        effective_disposition(requested, mime_type),
+        HeaderValue::from_str(mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static(FALLBACK_MIME_TYPE)),
+    )
+}
+
+/// Inline is only honored for types that cannot carry active content; anything
+/// else is always served as an attachment.
+fn effective_disposition(requested: Disposition, mime_type: &str) -> Disposition {
+    let essence = mime_essence(mime_type);
+    if requested == Disposition::Inline && INLINE_SAFE_MIME_TYPES.contains(&essence.as_str()) {
+        Disposition::Inline
+    } else {
+        Disposition::Attachment
+    }
+}
+
+pub(super) fn content_disposition(disposition: Disposition, name: &str) -> HeaderValue {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+    let name = sanitize_file_name(name);
+    let ascii: String = name
+        .chars()
+        .map(|ch| if ch.is_ascii() { ch } else { '_' })
+        .collect();
+    let value = format!(
+        "{kind}; filename=\"{ascii}\"; filename*=UTF-8''{}",
+        percent_encode(&name)
+    );
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+/// Whether `mime_type` is one of the configured `RISKY_MIME_TYPES`, which are
+/// never served under their declared type.
+fn is_risky_mime_type(risky: &[String], mime_type: &str) -> bool {
+    let essence = mime_essence(mime_type);
+    risky.contains(&essence)
+}
+
+/// Strips path components, quotes, and control characters so the name can
+/// neither escape the download directory nor break out of the header value.
+pub(super) fn sanitize_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|ch| !ch.is_control() && !matches!(ch, '"' | ';'))
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+    if cleaned.is_empty() {
+        FALLBACK_FILE_NAME.to_string()
+    } else {
+        cleaned
+    }
+}
+
+pub(super) fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Disposition, content_disposition, effective_disposition, is_risky_mime_type,
+        sanitize_file_name,
+    };
+    use crate::config::AppConfig;
+
+    #[test]
+    fn inline_is_honored_for_safe_types() {
+        assert_eq!(
+            effective_disposition(Disposition::Inline, "image/png"),
+            Disposition::Inline
+        );
+        assert_eq!(
+            effective_disposition(Disposition::Inline, "application/pdf"),
+            Disposition::Inline
+        );
+        let header = content_disposition(Disposition::Inline, "photo.png");
+        assert!(header.to_str().unwrap().starts_with("inline;"));
+    }
+
+    #[test]
+    fn attachment_is_default_and_forced_for_risky_types() {
+        assert_eq!(
+            effective_disposition(Disposition::Attachment, "image/png"),
+            Disposition::Attachment
+        );
+        assert_eq!(
+            effective_disposition(Disposition::Inline, "text/html"),
+            Disposition::Attachment
+        );
+        let header = content_disposition(Disposition::Attachment, "page.html");
+        assert!(header.to_str().unwrap().starts_with("attachment;"));
+    }
+
+    #[test]
+    fn risky_types_match_by_essence() {
+        let risky = AppConfig::default().risky_mime_types;
+        assert!(is_risky_mime_type(&risky, "image/svg+xml"));
+        assert!(is_risky_mime_type(&risky, "Text/HTML; charset=utf-8"));
+        assert!(!is_risky_mime_type(&risky, "image/png"));
+        assert!(!is_risky_mime_type(&[], "text/html"));
+    }
+
+    #[test]
+    fn file_names_are_sanitized() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("C:\\temp\\a\"b;.txt"), "ab.txt");
+        assert_eq!(sanitize_file_name(""), "download");
+        assert_eq!(sanitize_file_name(".."), "download");
+
+        let header = content_disposition(Disposition::Attachment, "résumé.pdf");
+        let value = header.to_str().unwrap();
+        assert!(value.contains("filename=\"r_sum_.pdf\""));
+        assert!(value.contains("filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"));
+    }
+}
@@ -1,78 +1,700 @@
 use axum::{
     Json,
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
 };
-use redis::AsyncCommands;
+use base64::Engine;
+use base64::display::Base64Display;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
 use tracing::instrument;
 
+use super::{
+    approval::approve_creation,
+    extract::ChannelJson,
+    password::{IssuedPassword, issue_password},
+};
 use crate::{
     channel::{
-        ChannelData, ChannelFile, StoredChannel, deserialize_channel, generate_channel_id,
-        generate_channel_password, hash_channel_password, serialize_channel, validate_channel_data,
-        verify_channel_password,
+        BlobCompression, CHANNEL_FORMAT_VERSION, ChannelData, ChannelFile, FileCategory,
+        StoredChannel, apply_file_id_policy, channel_size, check_content_requirement,
+        compress_blobs, decompress_blobs, deserialize_capped_files, deserialize_channel,
+        deserialize_channel_strict, generate_channel_id, generate_channel_password, gzip_blob,
+        hash_channel_password_async, mimic_password_verification_async, name_unnamed_files,
+        normalize_tags, preview_text, protect_files, remove_files, resolve_channel_ttl,
+        run_password_work, serialize_channel, unix_now, validate_channel_data, validate_channel_id,
+        validate_max_reads, validate_metadata, verify_channel_password,
+        verify_channel_password_async, verify_stored_files,
+    },
+    config::{AppConfig, PublicBaseUrl},
+    error::{AppError, retry_after_header},
+    events::{ChannelEventCounts, ChannelEventTotals},
+    state::{
+        RenameOutcome, SharedState, consume_read, delete_channel_keys, grace_ttl, index_tags,
+        keep_grace_copy, keep_previous_text, migrate_legacy_channel, missing_channel_error,
+        refresh_ttl, refresh_ttl_on_read, rename_channel, store_if_absent, track_expiry,
+        with_write_lock,
     },
-    error::AppError,
-    state::{SharedState, refresh_ttl},
+    storage::CircuitState,
 };
 
 const CHANNEL_PASSWORD_HEADER: &str = "x-channel-password";
+const CHANNEL_ENCRYPTION_KEY_HEADER: &str = "x-channel-encryption-key";
+const FILE_PASSWORD_HEADER: &str = "x-file-password";
+const SERVED_STALE_HEADER: &str = "x-served-stale";
+const BASE_URL_HEADER: &str = "x-base-url";
+const CHANNEL_TTL_HEADER: &str = "x-channel-ttl";
+const EXPIRED_GRACE_HEADER: &str = "x-expired-grace";
+const REGENERATE_ID_ATTEMPTS: usize = 5;
+/// Methods served on `/api/channels/:id`; keep in sync with the router.
+const CHANNEL_ALLOWED_METHODS: &str = "GET, HEAD, PUT, DELETE, OPTIONS";
+
+/// The base URL links in a response are generated under: the one `x-base-url`
+/// names if it is `PUBLIC_BASE_URL` or on `PUBLIC_BASE_URL_ALLOWLIST`, else
+/// `PUBLIC_BASE_URL`. Any other `x-base-url` is refused rather than ignored,
+/// so links never point somewhere the operator did not list.
+fn public_base_url<'a>(
+    config: &'a AppConfig,
+    headers: &HeaderMap,
+) -> Result<Option<&'a PublicBaseUrl>, AppError> {
+    let Some(requested) = headers.get(BASE_URL_HEADER) else {
+        return Ok(config.public_base_url.as_ref());
+    };
+    let requested: PublicBaseUrl = requested
+        .to_str()
+        .map_err(|_| AppError::BaseUrlNotAllowed)?
+        .parse()
+        .map_err(|_| AppError::BaseUrlNotAllowed)?;
+    config
+        .public_base_url
+        .iter()
+        .chain(&config.public_base_url_allowlist)
+        .find(|allowed| **allowed == requested)
+        .map(Some)
+        .ok_or(AppError::BaseUrlNotAllowed)
+}
+
+/// Link to the channel's view page, when a public base URL is configured.
+fn view_url(config: &AppConfig, headers: &HeaderMap, id: &str) -> Result<Option<String>, AppError> {
+    Ok(public_base_url(config, headers)?.map(|base| format!("{base}/api/channels/{id}/view")))
+}
+
+/// The caller's channel password. `x-channel-password` takes precedence; a
+/// `Authorization: Basic` header is accepted as a fallback for clients that
+/// cannot set custom headers (the username part is ignored). Other
+/// authorization schemes are ignored, but a malformed Basic header is an error.
+/// The password header must be printable ASCII, and neither source may carry
+/// more than `max_bytes`, so odd input never reaches the hasher.
+fn provided_password(headers: &HeaderMap, max_bytes: usize) -> Result<Option<String>, AppError> {
+    if let Some(value) = headers.get(CHANNEL_PASSWORD_HEADER) {
+        let password = value.as_bytes();
+        if password.len() > max_bytes || !password.iter().all(|byte| (b' '..=b'~').contains(byte)) {
+            return Err(AppError::InvalidPasswordHeader(max_bytes));
+        }
+        return Ok(Some(String::from_utf8_lossy(password).into_owned()));
+    }
+
+    let Some(authorization) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let authorization = authorization
+        .to_str()
+        .map_err(|_| AppError::MalformedAuthorization)?;
+    let Some((scheme, credentials)) = authorization.trim().split_once(' ') else {
+        return Ok(None);
+    };
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return Ok(None);
+    }
+
+    let decoded = BASE64_ENGINE
+        .decode(credentials.trim())
+        .map_err(|_| AppError::MalformedAuthorization)?;
+    let decoded = String::from_utf8(decoded).map_err(|_| AppError::MalformedAuthorization)?;
+    let (_username, password) = decoded
+        .split_once(':')
+        .ok_or(AppError::MalformedAuthorization)?;
+    if password.len() > max_bytes {
+        return Err(AppError::InvalidPasswordHeader(max_bytes));
+    }
+    Ok(Some(password.to_owned()))
+}
+
+/// Decodes a stored value, restoring any compressed file blobs.
+pub(super) fn decode_record(state: &SharedState, raw: String) -> Result<StoredChannel, AppError> {
+    let mut record = if state.config().strict_deserialize {
+        deserialize_channel_strict(&raw).inspect_err(|error| {
+            tracing::error!(%error, "stored channel is not a JSON record, not serving it as text")
+        })?
+    } else {
+        deserialize_channel(raw)
+    };
+    decompress_blobs(&mut record.data)?;
+    Ok(record)
+}
+
+/// Encodes a record for storage, compressing large blobs when enabled.
+pub(super) fn encode_record(
+    state: &SharedState,
+    mut record: StoredChannel,
+) -> Result<String, AppError> {
+    let config = state.config();
+    if config.channel_compress_blobs {
+        compress_blobs(&mut record.data, config.compress_blob_threshold_bytes)?;
+    }
+    serialize_channel(&record)
+}
+
+/// Passphrase for server-side encryption of the channel content. It is only
+/// ever held for the duration of the request.
+pub(super) fn encryption_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CHANNEL_ENCRYPTION_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|key| !key.is_empty())
+        .map(str::to_owned)
+}
+
+/// Which copy of a channel a read may be served from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum ReadFrom {
+    Primary,
+    /// A read replica where configured. It may lag behind the primary, so
+    /// records read from it must never be the base of a write.
+    Replica,
+}
+
+pub(super) async fn load_record(
+    state: &SharedState,
+    id: &str,
+) -> Result<Option<StoredChannel>, AppError> {
+    load_record_from(state, id, ReadFrom::Primary).await
+}
+
+pub(super) async fn load_record_from(
+    state: &SharedState,
+    id: &str,
+    from: ReadFrom,
+) -> Result<Option<StoredChannel>, AppError> {
+    let key = state.channel_key(id);
+    let mut raw = match from {
+        ReadFrom::Primary => state.storage().get(&key).await?,
+        ReadFrom::Replica => state.storage().get_replica(&key).await?,
+    };
+    if raw.is_none() && migrate_legacy_channel(state, id).await? {
+        raw = state.storage().get(&key).await?;
+    }
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+    if !state.config().upgrade_format_on_read {
+        return decode_record(state, raw).map(Some);
+    }
+    let record = decode_record(state, raw.clone())?;
+    if record
+        .format
+        .is_some_and(|format| format < CHANNEL_FORMAT_VERSION)
+    {
+        upgrade_format(state, &key, &raw, &record).await;
+    }
+    Ok(Some(record))
+}
+
+/// Rewrites `raw` in the current format, unless it changed since it was read.
+/// The read goes ahead either way; a failure only means another try next time.
+async fn upgrade_format(state: &SharedState, key: &str, raw: &str, record: &StoredChannel) {
+    let upgraded = async {
+        let encoded = encode_record(state, record.clone())?;
+        state.storage().replace_if_equals(key, raw, &encoded).await
+    };
+    match upgraded.await {
+        Ok(_) => tracing::debug!(%key, from = ?record.format, "upgraded channel format"),
+        Err(err) => tracing::warn!(%key, error = ?err, "failed to upgrade channel format"),
+    }
+}
+
+/// Checks `x-file-password` against every file among `files` that has a
+/// password of its own. Call it, after the channel password, wherever file
+/// bytes are about to be served; files without one need nothing extra.
+pub(super) async fn authorize_files<'a>(
+    files: impl IntoIterator<Item = &'a ChannelFile>,
+    headers: &HeaderMap,
+) -> Result<(), AppError> {
+    let hashes: Vec<String> = files
+        .into_iter()
+        .filter_map(|file| file.password_hash.clone())
+        .collect();
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    let password = headers
+        .get(FILE_PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let authorized = run_password_work(move || {
+        hashes
+            .iter()
+            .all(|hash| verify_channel_password(Some(hash), password.as_deref()))
+    })
+    .await;
+    if !authorized {
+        return Err(AppError::InvalidFilePassword);
+    }
+    Ok(())
+}
+
+/// Checks the password from `headers`, falling back to `query_password` only
+/// when the headers carry none.
+pub(super) async fn authorize(
+    state: &SharedState,
+    record: &StoredChannel,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+) -> Result<(), AppError> {
+    let password = provided_password(headers, state.config().max_password_bytes)?
+        .or_else(|| query_password.map(str::to_owned));
+    if !verify_channel_password_async(record.password_hash.clone(), password).await {
+        return Err(password_rejected(state));
+    }
+    Ok(())
+}
+
+/// The error for a wrong password: with `HIDE_CHANNEL_EXISTENCE` it is the
+/// same `404` an unknown id gets.
+pub(super) fn password_rejected(state: &SharedState) -> AppError {
+    if state.config().hide_channel_existence {
+        AppError::ChannelNotFound
+    } else {
+        AppError::InvalidChannelPassword
+    }
+}
+
+/// Hashes `password` as if checking it against a protected channel, so a
+/// missing channel costs the same time as a wrong password.
+pub(super) async fn mimic_password_check(password: Option<&str>) {
+    mimic_password_verification_async(password.map(str::to_owned)).await;
+}
+
+/// Loads the channel stored under `id` and checks the caller's password.
+pub(super) async fn load_authorized(
+    state: &SharedState,
+    id: &str,
+    headers: &HeaderMap,
+) -> Result<StoredChannel, AppError> {
+    load_authorized_with_query(state, id, headers, None).await
+}
+
+pub(super) async fn load_authorized_with_query(
+    state: &SharedState,
+    id: &str,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+) -> Result<StoredChannel, AppError> {
+    load_authorized_from(state, id, headers, query_password, ReadFrom::Primary).await
+}
+
+pub(super) async fn load_authorized_from(
+    state: &SharedState,
+    id: &str,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+    from: ReadFrom,
+) -> Result<StoredChannel, AppError> {
+    let Some(record) = load_record_from(state, id, from).await? else {
+        if state.config().hide_channel_existence {
+            let password = provided_password(headers, state.config().max_password_bytes)?
+                .or_else(|| query_password.map(str::to_owned));
+            mimic_password_check(password.as_deref()).await;
+        }
+        return Err(missing_channel_error(state, id).await?);
+    };
+    authorize(state, &record, headers, query_password).await?;
+    Ok(record)
+}
+
+/// Charges one content read against the channel's `max_reads` budget, if it
+/// has one, and reports the read to the event sinks. Only authenticated reads
+/// count; the script re-reads the payload so a concurrent reader can never be
+/// served past the budget. A `burn_after_read` channel is taken out of storage
+/// by the read, so only one of several concurrent readers gets it.
+pub(super) async fn charge_read(
+    state: &SharedState,
+    id: &str,
+    record: StoredChannel,
+) -> Result<StoredChannel, AppError> {
+    if record.burn_after_read {
+        let Some(raw) = state.storage().get_del(&state.channel_key(id)).await? else {
+            return Err(AppError::ChannelNotFound);
+        };
+        let record = decode_record(state, raw)?;
+        delete_channel_keys(state, id, &record.tags).await?;
+        state.events().on_read(id);
+        state.events().on_deleted(id);
+        return Ok(record);
+    }
+    let Some(max_reads) = record.max_reads else {
+        state.events().on_read(id);
+        return Ok(record);
+    };
+    let Some(raw) = consume_read(state, id, max_reads).await? else {
+        return Err(missing_channel_error(state, id).await?);
+    };
+    let record = decode_record(state, raw)?;
+    state.events().on_read(id);
+    Ok(record)
+}
+
+/// Writes `record` under `id`, resetting the TTL, encrypted under
+/// `encryption_key` when one is given. Callers validate the data.
+pub(super) async fn store_channel(
+    state: &SharedState,
+    id: &str,
+    mut record: StoredChannel,
+    encryption_key: Option<&str>,
+) -> Result<(), AppError> {
+    record.version += 1;
+    if let Some(key) = encryption_key {
+        record.seal(key)?;
+    }
+    let key = state.channel_key(id);
+    let mut ttl = record.ttl(state.channel_ttl());
+    if record.sticky_ttl {
+        // Writes must not extend a sticky channel's life either.
+        let remaining = state.storage().ttl(&key).await?;
+        if remaining > 0 {
+            ttl = Duration::from_secs(remaining as u64);
+        }
+    }
+    let grace_ttl = grace_ttl(state, &record, ttl);
+    let serialized = encode_record(state, record)?;
+    state.storage().set_ex(&key, &serialized, ttl).await?;
+    // The old content must not come back from this instance's stale copy.
+    state.stale_cache().forget(id);
+    track_expiry(state, id, ttl).await?;
+    keep_grace_copy(state, id, &serialized, grace_ttl).await
+}
+
+/// Remaining TTL as shown to clients: never above the channel's own TTL, which
+/// refresh timing can briefly overshoot, and zero instead of Redis' `-1`/`-2`
+/// for keys without an expiry or already gone.
+pub(super) fn reported_ttl(remaining: i64, cap: Duration) -> i64 {
+    remaining.clamp(0, cap.as_secs() as i64)
+}
+
+/// Unix timestamp (seconds) at which a channel with `ttl_seconds` left expires,
+/// by the server clock. Negative TTLs (missing key / no expiry) count as zero.
+pub(super) fn expires_at(ttl_seconds: i64) -> u64 {
+    unix_now() + ttl_seconds.max(0) as u64
+}
 
 #[instrument(skip_all)]
 pub async fn health_check() -> &'static str {
     "ok"
 }
 
+#[derive(Debug, Serialize)]
+pub struct ReadinessResponse {
+    pub status: &'static str,
+    pub circuit: CircuitState,
+    /// Access events dropped because the sink fell behind, with
+    /// `ACCESS_LOG_SINK` set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_dropped: Option<u64>,
+    /// Background work dropped at `MAX_BACKGROUND_TASKS`, once there is any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_tasks_dropped: Option<u64>,
+    /// Channel lifecycle events since startup, with `COUNT_CHANNEL_EVENTS` set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_events: Option<ChannelEventTotals>,
+}
+
+/// Readiness: 200 once the storage backend answers, 503 with a `Retry-After`
+/// otherwise. The body also reports the storage circuit breaker's state and
+/// any dropped access events and background work, and event totals when they
+/// are counted.
+pub async fn readiness_check(
+    State(state): State<SharedState>,
+) -> (
+    StatusCode,
+    Option<[(HeaderName, HeaderValue); 1]>,
+    Json<ReadinessResponse>,
+) {
+    let (code, retry_after, status) = if state.ready().await {
+        (StatusCode::OK, None, "ready")
+    } else {
+        let retry_after = retry_after_header(state.config().retry_after);
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Some([retry_after]),
+            "unavailable",
+        )
+    };
+    let circuit = state.circuit_state();
+    let background_tasks_dropped = Some(state.tasks().dropped()).filter(|&dropped| dropped > 0);
+    let access_log_dropped = state
+        .access_log()
+        .is_enabled()
+        .then(|| state.access_log().dropped());
+    let channel_events = state.event_counts().map(ChannelEventCounts::totals);
+    (
+        code,
+        retry_after,
+        Json(ReadinessResponse {
+            status,
+            circuit,
+            access_log_dropped,
+            background_tasks_dropped,
+            channel_events,
+        }),
+    )
+}
+
+/// Plain `OPTIONS` (not a CORS preflight, which the CORS layer answers) so
+/// clients can discover what a channel route supports.
+pub async fn channel_options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [(header::ALLOW, CHANNEL_ALLOWED_METHODS)],
+    )
+}
+
 #[derive(Deserialize, Default)]
 pub struct CreateChannelRequest {
     #[serde(default)]
     pub text: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_capped_files")]
     pub files: Vec<ChannelFile>,
     #[serde(default)]
     pub password: Option<String>,
+    #[serde(default)]
+    pub immutable: bool,
+    #[serde(default)]
+    pub max_reads: Option<u32>,
+    /// Shorter lifetime than the server default, within the allowed range.
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    /// Expire on schedule even while being read.
+    #[serde(default)]
+    pub sticky_ttl: bool,
+    /// Delete the channel as soon as it has been read once.
+    #[serde(default)]
+    pub burn_after_read: bool,
+    /// Small key-value pairs for integrators, kept out of the shared content.
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+    /// Labels for finding the channel again through the admin API.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Serialize)]
 pub struct CreateChannelResponse {
     pub id: String,
-    pub password: String,
+    #[serde(flatten)]
+    pub password: IssuedPassword,
     pub ttl_seconds: u64,
+    pub expires_at: u64,
+    /// Shareable link to the channel's view page; see `PUBLIC_BASE_URL`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_url: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct ChannelPayloadResponse {
     pub id: String,
     pub text: String,
-    pub files: Vec<ChannelFile>,
+    pub files: Vec<ChannelFileResponse>,
+    /// Text bytes plus decoded file bytes, whether or not bodies are included.
+    pub total_bytes: u64,
+    pub file_count: usize,
     pub ttl_seconds: i64,
+    pub expires_at: u64,
+}
+
+/// A file as returned to readers. `data_base64` is left out when the caller
+/// only asked for metadata.
+#[derive(Serialize)]
+pub struct ChannelFileResponse {
+    pub id: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_base64: Option<FileBody>,
+    /// Set when `data_base64` holds a compressed body, which the reader
+    /// decompresses after decoding the base64.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<BlobCompression>,
+    /// The body is only served with this file's `x-file-password`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub password_protected: bool,
+    /// Derived from `mime_type` to help UIs pick a preview; never stored.
+    pub category: FileCategory,
+    pub previewable: bool,
+}
+
+impl ChannelFileResponse {
+    pub(super) fn new(file: ChannelFile, include_body: bool) -> Self {
+        let category = FileCategory::of(&file.mime_type);
+        Self {
+            id: file.id,
+            name: file.name,
+            mime_type: file.mime_type,
+            size: file.size,
+            data_base64: include_body.then_some(FileBody::Encoded(file.data_base64)),
+            content_encoding: file.compression.filter(|_| include_body),
+            password_protected: file.password_hash.is_some(),
+            category,
+            previewable: category.previewable(),
+        }
+    }
+}
+
+/// A file body in a JSON response: base64 as stored, or raw bytes that are
+/// encoded straight into the response as it is serialized, without building
+/// the base64 string first.
+pub enum FileBody {
+    Encoded(String),
+    Raw(Vec<u8>),
+}
+
+impl Serialize for FileBody {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Encoded(encoded) => serializer.serialize_str(encoded),
+            Self::Raw(bytes) => serializer.collect_str(&Base64Display::new(bytes, &BASE64_ENGINE)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct FetchChannelQuery {
+    #[serde(default)]
+    pub include_files: Option<bool>,
+    /// Channel password, honoured only with `ALLOW_PASSWORD_QUERY`.
+    #[serde(default)]
+    pub pw: Option<String>,
+    /// `gzip` returns compressible file bodies gzipped before base64.
+    #[serde(default)]
+    pub encoded: Option<BlobCompression>,
+    /// Answer a channel without text or files with `204` and its TTL in
+    /// `x-channel-ttl`, instead of an empty payload.
+    #[serde(default)]
+    pub empty204: bool,
+}
+
+impl FetchChannelQuery {
+    fn include_file_bodies(&self, omit_by_default: bool) -> bool {
+        self.include_files.unwrap_or(!omit_by_default)
+    }
 }
 
 #[derive(Deserialize)]
 pub struct UpdateChannelRequest {
     pub text: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_capped_files")]
     pub files: Vec<ChannelFile>,
 }
 
-#[instrument(level = "debug", skip(state, payload))]
+#[derive(Deserialize)]
+pub struct DeleteFilesRequest {
+    pub file_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteFilesResponse {
+    pub removed: Vec<String>,
+    pub not_found: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChannelMetadataResponse {
+    pub metadata: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TouchChannelResponse {
+    pub ttl_seconds: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelPreviewResponse {
+    pub id: String,
+    pub text: String,
+    pub truncated: bool,
+    pub file_count: usize,
+}
+
+#[derive(Serialize)]
+pub struct RegenerateIdResponse {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct UpdateChannelQuery {
+    /// Create the channel at the requested id when it does not exist yet.
+    #[serde(default)]
+    pub upsert: bool,
+}
+
+#[instrument(level = "debug", skip(state, headers, payload))]
 pub async fn create_channel(
     State(state): State<SharedState>,
-    Json(payload): Json<CreateChannelRequest>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ChannelJson(payload): ChannelJson<CreateChannelRequest>,
 ) -> Result<(StatusCode, Json<CreateChannelResponse>), AppError> {
-    let id = generate_channel_id();
+    state.shed_create_if_slow()?;
+    let (record, password) = prepare_new_channel(&state, payload).await?;
+    approve_creation(&state, &record.data, peer.as_ref(), &headers).await?;
+    store_new_channel(&state, record, password, &headers).await
+}
+
+/// Validates a create request and turns it into the record to store, along
+/// with the password it will be protected by.
+pub(super) async fn prepare_new_channel(
+    state: &SharedState,
+    payload: CreateChannelRequest,
+) -> Result<(StoredChannel, String), AppError> {
     let CreateChannelRequest {
         text,
         files,
         password,
+        immutable,
+        max_reads,
+        ttl_seconds,
+        sticky_ttl,
+        burn_after_read,
+        metadata,
+        tags,
     } = payload;
-    let data = ChannelData {
+    let mut data = ChannelData {
         text: text.unwrap_or_default(),
         files,
     };
+    if state.config().auto_name_files {
+        name_unnamed_files(&mut data);
+    }
+    apply_file_id_policy(&mut data, state.config().file_id_policy)?;
+    protect_files(&mut data).await;
 
     validate_channel_data(&data)?;
+    check_content_requirement(&data, state.config().content_requirement)?;
+    validate_max_reads(max_reads)?;
+    validate_metadata(metadata.as_ref(), state.config().max_metadata_entries)?;
+    let tags = normalize_tags(tags, state.config().max_channel_tags)?;
+    let ttl_seconds = resolve_channel_ttl(
+        ttl_seconds,
+        state.config().min_channel_ttl.as_secs(),
+        state.channel_ttl().as_secs(),
+        state.config().ttl_policy,
+    )?;
     let password = password
         .and_then(|candidate| {
             let trimmed = candidate.trim();
@@ -83,150 +705,756 @@ pub async fn create_channel(
             }
         })
         .unwrap_or_else(generate_channel_password);
-    let password_hash = hash_channel_password(&password);
+    // A password too long for the header could never be used to read it back.
+    let max_password_bytes = state.config().max_password_bytes;
+    if password.len() > max_password_bytes {
+        return Err(AppError::InvalidPasswordHeader(max_password_bytes));
+    }
     let record = StoredChannel {
-        password_hash: Some(password_hash),
+        immutable,
+        max_reads,
+        ttl_seconds,
+        sticky_ttl,
+        burn_after_read,
+        metadata,
+        tags,
         data,
+        ..StoredChannel::default()
     };
-    let serialized = serialize_channel(&record)?;
+    Ok((record, password))
+}
 
-    let key = state.channel_key(&id);
-    let mut conn = state.redis();
-    let _: () = conn.set_ex(&key, serialized, state.ttl_seconds()).await?;
+/// Stores `record` under a fresh id, protected by `password` and encrypted when
+/// the request carries an encryption key. Callers validate the data.
+pub(super) async fn store_new_channel(
+    state: &SharedState,
+    mut record: StoredChannel,
+    password: String,
+    headers: &HeaderMap,
+) -> Result<(StatusCode, Json<CreateChannelResponse>), AppError> {
+    let id = generate_channel_id();
+    let view_url = view_url(state.config(), headers, &id)?;
+    let ttl = record.ttl(state.channel_ttl()).as_secs();
+    record.password_hash = Some(hash_channel_password_async(password.clone()).await);
+    record.created_at = Some(unix_now());
+    let tags = record.tags.clone();
+    store_channel(state, &id, record, encryption_key(headers).as_deref()).await?;
+    index_tags(state, &id, &tags, Duration::from_secs(ttl)).await?;
+    state.events().on_created(&id);
+    let password = issue_password(state, &id, password, Duration::from_secs(ttl)).await?;
 
     Ok((
         StatusCode::CREATED,
         Json(CreateChannelResponse {
             id,
             password,
-            ttl_seconds: state.channel_ttl().as_secs(),
+            ttl_seconds: ttl,
+            expires_at: expires_at(ttl as i64),
+            view_url,
         }),
     ))
 }
 
-#[instrument(level = "debug", skip(state))]
+/// Whether the channel is still there, with its remaining TTL in
+/// `x-channel-ttl`, for clients that poll. Nothing is read, so this neither
+/// counts against `max_reads` nor refreshes the TTL. With
+/// `HIDE_CHANNEL_EXISTENCE` the password is checked first, as on a fetch.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_exists(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Response, AppError> {
+    if state.config().hide_channel_existence {
+        load_authorized(&state, &id, &headers).await?;
+    }
+    let key = state.channel_key(&id);
+    let mut remaining = state.storage().ttl(&key).await?;
+    if remaining == -2 && migrate_legacy_channel(&state, &id).await? {
+        remaining = state.storage().ttl(&key).await?;
+    }
+    if remaining == -2 {
+        return Err(missing_channel_error(&state, &id).await?);
+    }
+    Ok([(CHANNEL_TTL_HEADER, HeaderValue::from(remaining.max(0)))].into_response())
+}
+
+/// Serves the channel's content. Each fetch resets the TTL unless
+/// `REFRESH_TTL_ON_READ` is off; `ttl_seconds` is what was left before it.
+#[instrument(level = "debug", skip(state, query))]
 pub async fn fetch_channel(
     Path(id): Path<String>,
+    Query(query): Query<FetchChannelQuery>,
     State(state): State<SharedState>,
     headers: HeaderMap,
-) -> Result<Json<ChannelPayloadResponse>, AppError> {
-    let provided_password = headers
-        .get(CHANNEL_PASSWORD_HEADER)
-        .and_then(|value| value.to_str().ok())
-        .map(str::to_owned);
-
-    let key = state.channel_key(&id);
-    let mut conn = state.redis();
+) -> Result<Response, AppError> {
+    let query_password = query
+        .pw
+        .as_deref()
+        .filter(|_| state.config().allow_password_query);
+    match fetch_fresh(&state, &id, &query, &headers, query_password).await {
+        Err(err) if err.is_storage_failure() && state.config().serve_stale_on_error => {
+            match serve_stale(&state, &id, &query, &headers, query_password).await? {
+                Some(response) => Ok(response),
+                None => Err(err),
+            }
+        }
+        Err(AppError::ChannelNotFound) if state.config().expired_grace.is_some() => {
+            match serve_grace(&state, &id, &query, &headers, query_password).await? {
+                Some(response) => Ok(response),
+                None => Err(AppError::ChannelNotFound),
+            }
+        }
+        fetched => fetched,
+    }
+}
 
-    let raw: Option<String> = conn.get(&key).await?;
-    let Some(raw) = raw else {
-        return Err(AppError::ChannelNotFound);
+/// Answers, once, from the grace copy of a channel that expired within
+/// `EXPIRED_GRACE_SECONDS`, checking the password and key against it like a
+/// fresh fetch. The copy is deleted by the read that gets it.
+async fn serve_grace(
+    state: &SharedState,
+    id: &str,
+    query: &FetchChannelQuery,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+) -> Result<Option<Response>, AppError> {
+    // With `HIDE_CHANNEL_EXISTENCE` a wrong password is a 404 as well, and the
+    // copy of a live channel must not answer for it.
+    if state.storage().exists(&state.channel_key(id)).await? {
+        return Ok(None);
+    }
+    let grace_key = state.grace_key(id);
+    let Some(raw) = state.storage().get(&grace_key).await? else {
+        return Ok(None);
     };
-
-    let record = deserialize_channel(raw);
-    if !verify_channel_password(
-        record.password_hash.as_deref(),
-        provided_password.as_deref(),
-    ) {
-        return Err(AppError::InvalidChannelPassword);
+    let mut record = decode_record(state, raw.clone())?;
+    authorize(state, &record, headers, query_password).await?;
+    record.unseal(encryption_key(headers).as_deref())?;
+    if query.include_file_bodies(state.config().omit_file_bodies_by_default) {
+        authorize_files(&record.data.files, headers).await?;
+    }
+    if !state.storage().delete_if_equals(&grace_key, &raw).await? {
+        return Ok(None);
     }
+    tracing::info!(%id, "serving an expired channel within its grace window");
+    state.events().on_read(id);
 
-    let data = record.data;
+    let mut response = payload_response(state.config(), id, record, query, 0)?;
+    response
+        .headers_mut()
+        .insert(EXPIRED_GRACE_HEADER, HeaderValue::from_static("true"));
+    Ok(Some(response))
+}
+
+async fn fetch_fresh(
+    state: &SharedState,
+    id: &str,
+    query: &FetchChannelQuery,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+) -> Result<Response, AppError> {
+    let mut record =
+        load_authorized_from(state, id, headers, query_password, ReadFrom::Replica).await?;
+    let loaded = state.config().serve_stale_on_error.then(|| record.clone());
+    // Decrypt before charging so a wrong key never spends a read.
+    let key = encryption_key(headers);
+    record.unseal(key.as_deref())?;
+    if query.include_file_bodies(state.config().omit_file_bodies_by_default) {
+        authorize_files(&record.data.files, headers).await?;
+    }
 
-    let ttl_seconds = conn
-        .ttl(&key)
+    let ttl_seconds = state
+        .storage()
+        .ttl(&state.channel_key(id))
         .await
         .unwrap_or(state.channel_ttl().as_secs() as i64);
+    let ttl_seconds = reported_ttl(ttl_seconds, record.ttl(state.channel_ttl()));
+
+    if state.config().validate_on_read {
+        verify_stored_files(&record.data)?;
+    }
 
-    refresh_ttl(&state, &key).await?;
+    let mut record = charge_read(state, id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(state, id, &record).await?;
 
-    Ok(Json(ChannelPayloadResponse {
+    if let Some(loaded) = loaded {
+        let remaining = Duration::from_secs(ttl_seconds.max(0) as u64);
+        state.stale_cache().remember(id, &loaded, remaining);
+    }
+    payload_response(state.config(), id, record, query, ttl_seconds)
+}
+
+/// Answers from the copy this instance kept of the channel, if it still has
+/// one, checking the password and key against it like a fresh fetch. Nothing
+/// is written, so the TTL is left alone.
+async fn serve_stale(
+    state: &SharedState,
+    id: &str,
+    query: &FetchChannelQuery,
+    headers: &HeaderMap,
+    query_password: Option<&str>,
+) -> Result<Option<Response>, AppError> {
+    let Some((mut record, remaining)) = state.stale_cache().recall(id) else {
+        return Ok(None);
+    };
+    authorize(state, &record, headers, query_password).await?;
+    record.unseal(encryption_key(headers).as_deref())?;
+    if query.include_file_bodies(state.config().omit_file_bodies_by_default) {
+        authorize_files(&record.data.files, headers).await?;
+    }
+    tracing::warn!(%id, "serving a stale channel while storage is failing");
+    state.events().on_read(id);
+
+    let mut response = payload_response(
+        state.config(),
         id,
+        record,
+        query,
+        remaining.as_secs() as i64,
+    )?;
+    let response_headers = response.headers_mut();
+    response_headers.insert(SERVED_STALE_HEADER, HeaderValue::from_static("true"));
+    response_headers.insert(
+        header::WARNING,
+        HeaderValue::from_static("110 - \"Response is Stale\""),
+    );
+    Ok(Some(response))
+}
+
+fn payload_response(
+    config: &AppConfig,
+    id: &str,
+    record: StoredChannel,
+    query: &FetchChannelQuery,
+    ttl_seconds: i64,
+) -> Result<Response, AppError> {
+    let total_bytes = channel_size(&record.data).ok_or(AppError::IntegrityError)? as u64;
+    let file_count = record.data.files.len();
+    let include_bodies = query.include_file_bodies(config.omit_file_bodies_by_default);
+    let gzip_bodies = include_bodies && query.encoded == Some(BlobCompression::Gzip);
+    let cache_control = cache_control(config, &record, ttl_seconds);
+    let etag = record
+        .immutable
+        .then(|| content_etag(&record.data, include_bodies, gzip_bodies))
+        .transpose()?;
+    let data = record.data;
+    if query.empty204 && data.text.is_empty() && data.files.is_empty() {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let response_headers = response.headers_mut();
+        response_headers.insert(header::CACHE_CONTROL, cache_control);
+        response_headers.insert(CHANNEL_TTL_HEADER, HeaderValue::from(ttl_seconds));
+        return Ok(response);
+    }
+    let files = data
+        .files
+        .into_iter()
+        .map(|file| {
+            // Skips formats that are compressed already and bodies that would
+            // not shrink; those go out as plain base64 without a marker.
+            let compressed = if gzip_bodies {
+                gzip_blob(&file, 0)?
+            } else {
+                None
+            };
+            let mut response = ChannelFileResponse::new(file, include_bodies);
+            if let Some(compressed) = compressed {
+                response.data_base64 = Some(FileBody::Raw(compressed));
+                response.content_encoding = Some(BlobCompression::Gzip);
+            }
+            Ok(response)
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    let mut response = Json(ChannelPayloadResponse {
+        id: id.to_owned(),
         text: data.text,
-        files: data.files,
+        files,
+        total_bytes,
+        file_count,
         ttl_seconds,
-    }))
+        expires_at: expires_at(ttl_seconds),
+    })
+    .into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CACHE_CONTROL, cache_control);
+    if let Some(etag) = etag {
+        response_headers.insert(header::ETAG, etag);
+    }
+    Ok(response)
+}
+
+/// Immutable channels may be cached until they expire, capped at
+/// `IMMUTABLE_CACHE_MAX_AGE_SECONDS`. Everything else is revalidated on every
+/// use, as is a channel with a read budget or a password-protected file, since
+/// a cached copy would bypass those.
+fn cache_control(config: &AppConfig, record: &StoredChannel, ttl_seconds: i64) -> HeaderValue {
+    let max_age = config
+        .immutable_cache_max_age
+        .as_secs()
+        .min(ttl_seconds.max(0) as u64);
+    let protected_files = record
+        .data
+        .files
+        .iter()
+        .any(|file| file.password_hash.is_some());
+    if !record.immutable
+        || record.max_reads.is_some()
+        || record.burn_after_read
+        || protected_files
+        || max_age == 0
+    {
+        return HeaderValue::from_static("no-cache");
+    }
+    let scope = if config.immutable_cache_public {
+        "public"
+    } else {
+        "private"
+    };
+    HeaderValue::from_str(&format!("{scope}, max-age={max_age}"))
+        .expect("cache-control value is ASCII")
+}
+
+/// Strong validator for an immutable channel's payload: its content never
+/// changes, so a hash of it (and of whether bodies were included, and
+/// gzipped) identifies the representation.
+fn content_etag(
+    data: &ChannelData,
+    include_bodies: bool,
+    gzip_bodies: bool,
+) -> Result<HeaderValue, AppError> {
+    let mut hasher = Sha256::new();
+    hasher.update([u8::from(include_bodies), u8::from(gzip_bodies)]);
+    hasher.update(serde_json::to_vec(data)?);
+    let etag = format!("\"{}\"", hex::encode(hasher.finalize()));
+    Ok(HeaderValue::from_str(&etag).expect("etag value is ASCII"))
 }
 
 #[instrument(level = "debug", skip(state, payload, headers))]
 pub async fn update_channel(
     Path(id): Path<String>,
+    Query(query): Query<UpdateChannelQuery>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    ChannelJson(payload): ChannelJson<UpdateChannelRequest>,
+) -> Result<Response, AppError> {
+    with_write_lock(&state, &id, async {
+        let mut data = ChannelData {
+            text: payload.text,
+            files: payload.files,
+        };
+        if state.config().auto_name_files {
+            name_unnamed_files(&mut data);
+        }
+        apply_file_id_policy(&mut data, state.config().file_id_policy)?;
+        protect_files(&mut data).await;
+        validate_channel_data(&data)?;
+        check_content_requirement(&data, state.config().content_requirement)?;
+        let key = encryption_key(&headers);
+
+        let mut record = match load_record(&state, &id).await? {
+            Some(record) => record,
+            None if query.upsert => {
+                validate_channel_id(&id)?;
+                // Creating goes through the same gates as `create_channel`.
+                state.shed_create_if_slow()?;
+                approve_creation(&state, &data, peer.as_ref(), &headers).await?;
+                // With upsert the caller may pick the password up front;
+                // otherwise one is generated exactly as on create.
+                let password = provided_password(&headers, state.config().max_password_bytes)?
+                    .filter(|candidate| !candidate.trim().is_empty())
+                    .unwrap_or_else(generate_channel_password);
+                let view_url = view_url(state.config(), &headers, &id)?;
+                let mut record = StoredChannel {
+                    password_hash: Some(hash_channel_password_async(password.clone()).await),
+                    created_at: Some(unix_now()),
+                    version: 1,
+                    data: data.clone(),
+                    ..StoredChannel::default()
+                };
+                if let Some(key) = &key {
+                    record.seal(key)?;
+                }
+                let grace_ttl = grace_ttl(&state, &record, state.channel_ttl());
+                let serialized = encode_record(&state, record)?;
+                if store_if_absent(&state, &id, serialized.clone()).await? {
+                    keep_grace_copy(&state, &id, &serialized, grace_ttl).await?;
+                    state.events().on_created(&id);
+                    let password =
+                        issue_password(&state, &id, password, state.channel_ttl()).await?;
+                    let created = CreateChannelResponse {
+                        id: id.clone(),
+                        password,
+                        ttl_seconds: state.channel_ttl().as_secs(),
+                        expires_at: expires_at(state.channel_ttl().as_secs() as i64),
+                        view_url,
+                    };
+                    return Ok((StatusCode::CREATED, Json(created)).into_response());
+                }
+
+                // Lost a race with a concurrent creator: treat it as an update.
+                load_record(&state, &id)
+                    .await?
+                    .ok_or(AppError::ChannelNotFound)?
+            }
+            None => return Err(AppError::ChannelNotFound),
+        };
+        authorize(&state, &record, &headers, None).await?;
+        record.ensure_mutable()?;
+
+        let previous = (record.sealed.is_none() && key.is_none())
+            .then(|| (record.version, std::mem::take(&mut record.data.text)));
+        let ttl = record.ttl(state.channel_ttl());
+        record.data = data;
+        record.sealed = None;
+        store_channel(&state, &id, record, key.as_deref()).await?;
+        let previous = previous
+            .as_ref()
+            .map(|(version, text)| (*version, text.as_str()));
+        keep_previous_text(&state, &id, previous, ttl).await?;
+        state.events().on_updated(&id);
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    })
+    .await
+}
+
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn delete_channel_file(
+    Path((id, file_id)): Path<(String, String)>,
     headers: HeaderMap,
     State(state): State<SharedState>,
-    Json(payload): Json<UpdateChannelRequest>,
 ) -> Result<StatusCode, AppError> {
-    let provided_password = headers
-        .get(CHANNEL_PASSWORD_HEADER)
-        .and_then(|value| value.to_str().ok())
-        .map(str::to_owned);
+    with_write_lock(&state, &id, async {
+        let mut record = load_authorized(&state, &id, &headers).await?;
+        record.ensure_mutable()?;
+        let key = encryption_key(&headers).filter(|_| record.sealed.is_some());
+        record.unseal(key.as_deref())?;
 
-    let key = state.channel_key(&id);
-    let mut conn = state.redis();
+        let before = record.data.files.len();
+        record.data.files.retain(|file| file.id != file_id);
 
-    let raw: Option<String> = conn.get(&key).await?;
-    let Some(raw) = raw else {
-        return Err(AppError::ChannelNotFound);
-    };
-    let mut record = deserialize_channel(raw);
-    if !verify_channel_password(
-        record.password_hash.as_deref(),
-        provided_password.as_deref(),
-    ) {
-        return Err(AppError::InvalidChannelPassword);
-    }
+        if before == record.data.files.len() {
+            return Err(AppError::ChannelFileNotFound);
+        }
 
-    let data = ChannelData {
-        text: payload.text,
-        files: payload.files,
-    };
+        validate_channel_data(&record.data)?;
+        store_channel(&state, &id, record, key.as_deref()).await?;
+        state.events().on_updated(&id);
 
-    validate_channel_data(&data)?;
-    record.data = data;
-    let serialized = serialize_channel(&record)?;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
 
-    let _: () = conn.set_ex(&key, serialized, state.ttl_seconds()).await?;
+/// Deletes the whole channel straight away rather than waiting for its TTL.
+/// Needs the channel password like an update, and an immutable channel stays.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn delete_channel(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<StatusCode, AppError> {
+    with_write_lock(&state, &id, async {
+        let record = load_authorized(&state, &id, &headers).await?;
+        record.ensure_mutable()?;
+        delete_channel_keys(&state, &id, &record.tags).await?;
+        state.events().on_deleted(&id);
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
 
-    Ok(StatusCode::NO_CONTENT)
+#[instrument(level = "debug", skip(state, headers, payload))]
+pub async fn delete_channel_files(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    ChannelJson(payload): ChannelJson<DeleteFilesRequest>,
+) -> Result<Json<DeleteFilesResponse>, AppError> {
+    with_write_lock(&state, &id, async {
+        let mut record = load_authorized(&state, &id, &headers).await?;
+        record.ensure_mutable()?;
+        let key = encryption_key(&headers).filter(|_| record.sealed.is_some());
+        record.unseal(key.as_deref())?;
+
+        let (removed, not_found) = remove_files(&mut record.data, &payload.file_ids);
+        if !removed.is_empty() {
+            validate_channel_data(&record.data)?;
+            store_channel(&state, &id, record, key.as_deref()).await?;
+            state.events().on_updated(&id);
+        }
+
+        Ok(Json(DeleteFilesResponse { removed, not_found }))
+    })
+    .await
 }
 
+/// The start of a channel's text and how many files it holds, for listing UIs.
+/// Counts as a read, since it reveals content.
 #[instrument(level = "debug", skip(state, headers))]
-pub async fn delete_channel_file(
-    Path((id, file_id)): Path<(String, String)>,
+pub async fn preview_channel(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelPreviewResponse>, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let (text, truncated) = preview_text(&record.data.text, state.config().preview_chars);
+    Ok(Json(ChannelPreviewResponse {
+        text: text.to_owned(),
+        truncated,
+        file_count: record.data.files.len(),
+        id,
+    }))
+}
+
+/// The metadata set at creation. It is not part of the content, so reading it
+/// neither counts as a read nor refreshes the TTL.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_metadata(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelMetadataResponse>, AppError> {
+    let record = load_authorized_from(&state, &id, &headers, None, ReadFrom::Replica).await?;
+    Ok(Json(ChannelMetadataResponse {
+        metadata: record.metadata.unwrap_or_default(),
+        tags: record.tags,
+    }))
+}
+
+/// Keep-alive for clients holding a channel open: resets the TTL without
+/// sending any content back.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn touch_channel(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<TouchChannelResponse>, AppError> {
+    let record = load_authorized(&state, &id, &headers).await?;
+    refresh_ttl(&state, &id, &record).await?;
+
+    let ttl_seconds = state.storage().ttl(&state.channel_key(&id)).await?;
+    let ttl_seconds = reported_ttl(ttl_seconds, record.ttl(state.channel_ttl()));
+    Ok(Json(TouchChannelResponse {
+        ttl_seconds: ttl_seconds.max(0) as u64,
+        expires_at: expires_at(ttl_seconds),
+    }))
+}
+
+/// Checks the caller's password without sending content back, charging a
+/// read, or refreshing the TTL: `204` if it opens the channel, `401` if not.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn check_channel_password(
+    Path(id): Path<String>,
     headers: HeaderMap,
     State(state): State<SharedState>,
 ) -> Result<StatusCode, AppError> {
-    let provided_password = headers
-        .get(CHANNEL_PASSWORD_HEADER)
-        .and_then(|value| value.to_str().ok())
-        .map(str::to_owned);
+    load_authorized(&state, &id, &headers).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let key = state.channel_key(&id);
-    let mut conn = state.redis();
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn regenerate_channel_id(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<RegenerateIdResponse>, AppError> {
+    with_write_lock(&state, &id, async {
+        let record = load_authorized(&state, &id, &headers).await?;
 
-    let raw: Option<String> = conn.get(&key).await?;
-    let Some(raw) = raw else {
-        return Err(AppError::ChannelNotFound);
+        for _ in 0..REGENERATE_ID_ATTEMPTS {
+            let new_id = generate_id_on_shard(&state, state.shard_for(&id));
+            match rename_channel(&state, &id, &new_id).await? {
+                RenameOutcome::Renamed => {
+                    for tag in &record.tags {
+                        let old = std::slice::from_ref(&id);
+                        state.storage().srem(&state.tag_key(tag), old).await?;
+                    }
+                    let ttl = record.ttl(state.channel_ttl());
+                    index_tags(&state, &new_id, &record.tags, ttl).await?;
+                    return Ok(Json(RegenerateIdResponse { id: new_id }));
+                }
+                RenameOutcome::SourceMissing => return Err(AppError::ChannelNotFound),
+                RenameOutcome::TargetTaken => continue,
+            }
+        }
+
+        Err(AppError::ChannelIdExhausted)
+    })
+    .await
+}
+
+/// RENAME cannot cross Redis instances, so a regenerated id has to hash onto
+/// the same shard as the one it replaces.
+fn generate_id_on_shard(state: &SharedState, shard: usize) -> String {
+    loop {
+        let candidate = generate_channel_id();
+        if state.shard_for(&candidate) == shard {
+            return candidate;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    use super::{
+        ChannelFileResponse, FetchChannelQuery, FileBody, channel_options, expires_at,
+        provided_password, reported_ttl,
     };
+    use crate::{channel::ChannelFile, error::AppError};
+
+    const MAX_BYTES: usize = 64;
 
-    let mut record = deserialize_channel(raw);
-    if !verify_channel_password(
-        record.password_hash.as_deref(),
-        provided_password.as_deref(),
-    ) {
-        return Err(AppError::InvalidChannelPassword);
+    fn sample_file() -> ChannelFile {
+        ChannelFile {
+            id: "f1".into(),
+            name: "notes.txt".into(),
+            mime_type: "text/plain".into(),
+            size: 5,
+            data_base64: "aGVsbG8=".into(),
+            ..ChannelFile::default()
+        }
     }
 
-    let before = record.data.files.len();
-    record.data.files.retain(|file| file.id != file_id);
+    #[test]
+    fn file_bodies_follow_configured_default() {
+        let query = FetchChannelQuery::default();
+        assert!(query.include_file_bodies(false));
+        assert!(!query.include_file_bodies(true));
+    }
 
-    if before == record.data.files.len() {
-        return Err(AppError::ChannelFileNotFound);
+    #[test]
+    fn explicit_include_files_overrides_default() {
+        let include = FetchChannelQuery {
+            include_files: Some(true),
+            ..FetchChannelQuery::default()
+        };
+        let omit = FetchChannelQuery {
+            include_files: Some(false),
+            ..FetchChannelQuery::default()
+        };
+        assert!(include.include_file_bodies(true));
+        assert!(!omit.include_file_bodies(false));
     }
 
-    validate_channel_data(&record.data)?;
-    let serialized = serialize_channel(&record)?;
+    #[test]
+    fn omitted_file_body_is_not_serialized() {
+        let metadata =
+            serde_json::to_value(ChannelFileResponse::new(sample_file(), false)).unwrap();
+        assert!(metadata.get("data_base64").is_none());
+        assert_eq!(metadata["size"], 5);
+        assert_eq!(metadata["category"], "text");
+        assert_eq!(metadata["previewable"], true);
 
-    let _: () = conn.set_ex(&key, serialized, state.ttl_seconds()).await?;
+        let full = serde_json::to_value(ChannelFileResponse::new(sample_file(), true)).unwrap();
+        assert_eq!(full["data_base64"], "aGVsbG8=");
+    }
 
-    Ok(StatusCode::NO_CONTENT)
+    #[test]
+    fn raw_bodies_are_base64_encoded_while_serializing() {
+        use base64::Engine;
+        let bytes: Vec<u8> = (0..3 * 1024 * 1024 + 1).map(|i| (i % 251) as u8).collect();
+        let response = ChannelFileResponse {
+            data_base64: Some(FileBody::Raw(bytes.clone())),
+            ..ChannelFileResponse::new(sample_file(), false)
+        };
+        let json = serde_json::to_value(response).unwrap();
+        assert_eq!(
+            json["data_base64"],
+            base64::engine::general_purpose::STANDARD.encode(&bytes)
+        );
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn basic_auth_supplies_password() {
+        // "abc123:s3cret:with-colon"
+        let basic = headers(&[(
+            header::AUTHORIZATION.as_str(),
+            "Basic YWJjMTIzOnMzY3JldDp3aXRoLWNvbG9u",
+        )]);
+        assert_eq!(
+            provided_password(&basic, MAX_BYTES).unwrap().as_deref(),
+            Some("s3cret:with-colon")
+        );
+    }
+
+    #[test]
+    fn password_header_takes_precedence_over_basic_auth() {
+        let both = headers(&[
+            ("x-channel-password", "from-header"),
+            (header::AUTHORIZATION.as_str(), "Basic OmZyb20tYmFzaWM="),
+        ]);
+        assert_eq!(
+            provided_password(&both, MAX_BYTES).unwrap().as_deref(),
+            Some("from-header")
+        );
+
+        let bearer = headers(&[(header::AUTHORIZATION.as_str(), "Bearer token")]);
+        assert_eq!(provided_password(&bearer, MAX_BYTES).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_basic_auth_is_rejected() {
+        for value in ["Basic !!!not-base64", "Basic bm8tY29sb24="] {
+            let malformed = headers(&[(header::AUTHORIZATION.as_str(), value)]);
+            assert!(matches!(
+                provided_password(&malformed, MAX_BYTES),
+                Err(AppError::MalformedAuthorization)
+            ));
+        }
+    }
+
+    #[test]
+    fn reported_ttl_stays_within_zero_and_the_channel_ttl() {
+        let cap = Duration::from_secs(900);
+        assert_eq!(reported_ttl(600, cap), 600);
+        assert_eq!(reported_ttl(901, cap), 900);
+        assert_eq!(reported_ttl(-1, cap), 0);
+        assert_eq!(reported_ttl(-2, cap), 0);
+    }
+
+    #[test]
+    fn expires_at_is_now_plus_ttl() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expiry = expires_at(900);
+        assert!((now + 900..=now + 901).contains(&expiry));
+        assert!((now..=now + 1).contains(&expires_at(-2)));
+    }
+
+    #[tokio::test]
+    async fn options_lists_supported_channel_methods() {
+        let response = channel_options().await.into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get(header::ALLOW).unwrap(),
+            "GET, HEAD, PUT, DELETE, OPTIONS"
+        );
+    }
 }
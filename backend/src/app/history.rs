@@ -0,0 +1,99 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::HeaderMap,
+};
+use serde::Serialize;
+use tracing::instrument;
+
+use super::handlers::{
+    authorize, charge_read, encryption_key, load_authorized, load_record, store_channel,
+};
+use crate::{
+    channel::{HistoryEntry, check_content_requirement, validate_channel_data},
+    error::AppError,
+    state::{SharedState, keep_previous_text, refresh_ttl_on_read, with_write_lock},
+};
+
+#[derive(Debug, Serialize)]
+pub struct ChannelHistoryResponse {
+    /// The version the channel is at now.
+    pub version: u64,
+    /// Earlier versions of the text, newest first.
+    pub versions: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevertChannelResponse {
+    /// The new version holding the restored text.
+    pub version: u64,
+}
+
+async fn load_history(state: &SharedState, id: &str) -> Result<Vec<HistoryEntry>, AppError> {
+    state
+        .storage()
+        .lrange_all(&state.history_key(id))
+        .await?
+        .iter()
+        .map(|raw| serde_json::from_str(raw).map_err(AppError::from))
+        .collect()
+}
+
+/// The earlier versions of the channel text kept under `HISTORY_VERSIONS`,
+/// each with the time it was replaced. Encrypted channels keep none. Counts as
+/// a read.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_history(
+    Path(id): Path<String>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<ChannelHistoryResponse>, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    let record = charge_read(&state, &id, record).await?;
+    let versions = load_history(&state, &id).await?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+    Ok(Json(ChannelHistoryResponse {
+        version: record.version,
+        versions,
+    }))
+}
+
+/// Restores the text of `version` from the channel's history, keeping its
+/// files. The restore is an edit like any other: it gets a new version and
+/// puts the text it replaces at the front of the history.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn revert_channel(
+    Path((id, version)): Path<(String, u64)>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<RevertChannelResponse>, AppError> {
+    with_write_lock(&state, &id, async {
+        let Some(mut record) = load_record(&state, &id).await? else {
+            return Err(AppError::ChannelNotFound);
+        };
+        authorize(&state, &record, &headers, None).await?;
+        record.ensure_mutable()?;
+        // Encrypted channels keep no history, so there is nothing to find.
+        let entry = load_history(&state, &id)
+            .await?
+            .into_iter()
+            .find(|entry| entry.version == version)
+            .filter(|_| record.sealed.is_none())
+            .ok_or(AppError::VersionNotInHistory(version))?;
+
+        let previous_version = record.version;
+        let previous = std::mem::replace(&mut record.data.text, entry.text);
+        validate_channel_data(&record.data)?;
+        check_content_requirement(&record.data, state.config().content_requirement)?;
+        let ttl = record.ttl(state.channel_ttl());
+        store_channel(&state, &id, record, None).await?;
+        keep_previous_text(&state, &id, Some((previous_version, &previous)), ttl).await?;
+        state.events().on_updated(&id);
+        Ok(Json(RevertChannelResponse {
+            version: previous_version + 1,
+        }))
+    })
+    .await
+}
@@ -0,0 +1,460 @@
+use axum::{
+    extract::{
+        Path, Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
+    },
+    http::HeaderMap,
+    response::Response,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{Instant, Interval},
+};
+use tracing::instrument;
+
+use super::handlers::{
+    charge_read, decode_record, encode_record, encryption_key, load_authorized_with_query,
+    load_record,
+};
+use crate::{
+    channel::{
+        ChannelData, check_content_requirement, validate_channel_data,
+        verify_channel_password_async,
+    },
+    error::AppError,
+    hub::LiveText,
+    state::{
+        SharedState, SubscriberSlot, count_download, grace_ttl, keep_grace_copy,
+        keep_previous_text, missing_channel_error, refresh_ttl,
+    },
+};
+
+/// Compare-and-swap attempts before an edit gives up on a busy channel.
+const MAX_SWAP_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Deserialize, Default)]
+pub struct LiveQuery {
+    /// Channel password, honoured only with `ALLOW_PASSWORD_QUERY`; browsers
+    /// cannot set headers on a WebSocket handshake.
+    #[serde(default)]
+    pub pw: Option<String>,
+}
+
+/// Messages a client sends over the socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveClientMessage {
+    /// Replace the text, based on the state at `version`.
+    Update { version: u64, text: String },
+    /// Send the file's bytes as binary frames, then a `file_complete`
+    /// message. Needs `LIVE_FILE_CHUNK_BYTES`, and `password` for a file that
+    /// has one of its own.
+    GetFile {
+        id: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+/// Messages the server sends over the socket.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveServerMessage {
+    /// The current text, sent on connect and after every accepted edit.
+    State(LiveText),
+    /// An edit was based on a stale version; rebase onto this state.
+    Conflict(LiveText),
+    /// Every binary frame of the requested file has been sent.
+    FileComplete {
+        id: String,
+        size: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Collaborative editing of a channel's text over a WebSocket. Clients send
+/// the full text with the version it was based on; the first edit against a
+/// version wins and is broadcast to every session, later ones get a conflict
+/// with the current state. Connecting counts as one read.
+#[instrument(level = "debug", skip(state, headers, query, upgrade))]
+pub async fn channel_socket(
+    Path(id): Path<String>,
+    Query(query): Query<LiveQuery>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let query_password = query
+        .pw
+        .as_deref()
+        .filter(|_| state.config().allow_password_query);
+    let mut record = load_authorized_with_query(&state, &id, &headers, query_password).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+
+    let slot = SubscriberSlot::acquire(&state, &id).await?;
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    let initial = LiveText {
+        version: record.version,
+        text: std::mem::take(&mut record.data.text),
+    };
+
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        run_session(&state, &id, key.as_deref(), socket, initial).await;
+        if let Err(err) = slot.release().await {
+            tracing::warn!(error = ?err, "failed to release subscriber slot");
+        }
+    }))
+}
+
+async fn run_session(
+    state: &SharedState,
+    id: &str,
+    key: Option<&str>,
+    mut socket: WebSocket,
+    initial: LiveText,
+) {
+    let mut updates = state.hub().subscribe(id);
+    let mut budget = MessageBudget::new(state.config().live_message_rate);
+    let mut refresh = state.config().live_ttl_refresh.map(|period| {
+        let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker
+    });
+    if send(&mut socket, &LiveServerMessage::State(initial))
+        .await
+        .is_ok()
+    {
+        loop {
+            let reply = tokio::select! {
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) if !budget.admit() => {
+                        tracing::debug!(%id, "live connection exceeded its message rate");
+                        close_rate_limited(&mut socket).await;
+                        break;
+                    }
+                    Some(Ok(Message::Text(raw))) => match serde_json::from_str(&raw) {
+                        Ok(LiveClientMessage::Update { version, text }) => {
+                            handle_update(state, id, key, version, text).await
+                        }
+                        Ok(LiveClientMessage::GetFile { id: file_id, password }) => {
+                            let password = password.as_deref();
+                            let sent = send_file(state, id, key, &mut socket, &file_id, password);
+                            if sent.await.is_err() {
+                                break;
+                            }
+                            None
+                        }
+                        Err(err) => Some(LiveServerMessage::Error {
+                            message: format!("invalid message: {err}"),
+                        }),
+                    },
+                    Some(Ok(_)) => None,
+                },
+                update = updates.recv() => match update {
+                    Ok(update) => Some(LiveServerMessage::State(update)),
+                    // Skipped updates are superseded by the current state.
+                    Err(RecvError::Lagged(_)) => Some(current_state(state, id, key).await),
+                    Err(RecvError::Closed) => break,
+                },
+                () = next_tick(&mut refresh) => {
+                    keep_alive(state, id).await;
+                    None
+                }
+            };
+            if let Some(reply) = reply {
+                if send(&mut socket, &reply).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    drop(updates);
+    state.hub().release(id);
+}
+
+async fn handle_update(
+    state: &SharedState,
+    id: &str,
+    key: Option<&str>,
+    version: u64,
+    text: String,
+) -> Option<LiveServerMessage> {
+    match apply_update(state, id, key, version, text).await {
+        // The sender hears about its own edit through the broadcast.
+        Ok(Applied::Accepted(update)) => {
+            state.hub().publish(id, update);
+            None
+        }
+        Ok(Applied::Conflict(current)) => Some(LiveServerMessage::Conflict(current)),
+        Err(err) => Some(LiveServerMessage::Error {
+            message: err.to_string(),
+        }),
+    }
+}
+
+/// Streams one of the channel's files in binary frames of at most
+/// `LIVE_FILE_CHUNK_BYTES`, then confirms with `file_complete`. A file that
+/// cannot be sent is reported as an `error`; only a failed send is returned.
+/// The request itself is charged against the connection's message rate.
+async fn send_file(
+    state: &SharedState,
+    id: &str,
+    key: Option<&str>,
+    socket: &mut WebSocket,
+    file_id: &str,
+    password: Option<&str>,
+) -> Result<(), axum::Error> {
+    let (bytes, chunk_bytes) = match load_file(state, id, key, file_id, password).await {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            let message = err.to_string();
+            return send(socket, &LiveServerMessage::Error { message }).await;
+        }
+    };
+    for chunk in bytes.chunks(chunk_bytes) {
+        socket.send(Message::Binary(chunk.to_vec())).await?;
+    }
+    count_download(state, id, file_id);
+    let complete = LiveServerMessage::FileComplete {
+        id: file_id.to_owned(),
+        size: bytes.len() as u64,
+    };
+    send(socket, &complete).await
+}
+
+/// The decoded bytes of `file_id` and the chunk size to send them in.
+async fn load_file(
+    state: &SharedState,
+    id: &str,
+    key: Option<&str>,
+    file_id: &str,
+    password: Option<&str>,
+) -> Result<(Vec<u8>, usize), AppError> {
+    let config = state.config();
+    let chunk_bytes = config
+        .live_file_chunk_bytes
+        .ok_or(AppError::LiveFilesDisabled)?;
+    let Some(mut record) = load_record(state, id).await? else {
+        return Err(missing_channel_error(state, id).await?);
+    };
+    record.unseal(key)?;
+    let file = record
+        .data
+        .files
+        .iter()
+        .find(|file| file.id == file_id)
+        .ok_or(AppError::ChannelFileNotFound)?;
+    if !verify_channel_password_async(file.password_hash.clone(), password.map(str::to_owned)).await
+    {
+        return Err(AppError::InvalidFilePassword);
+    }
+    if file.size > config.live_max_file_bytes as u64 {
+        return Err(AppError::LiveFileTooLarge(config.live_max_file_bytes));
+    }
+    let bytes = BASE64_ENGINE
+        .decode(&file.data_base64)
+        .map_err(|_| AppError::InvalidFileData)?;
+    Ok((bytes, chunk_bytes))
+}
+
+enum Applied {
+    Accepted(LiveText),
+    Conflict(LiveText),
+}
+
+/// Writes `text` if the channel is still at `version`, bumping the version.
+/// The write is a compare-and-swap against the exact stored value, so a
+/// concurrent writer (over the socket or the REST API) can never be lost.
+async fn apply_update(
+    state: &SharedState,
+    id: &str,
+    key: Option<&str>,
+    version: u64,
+    text: String,
+) -> Result<Applied, AppError> {
+    let storage_key = state.channel_key(id);
+    for _ in 0..MAX_SWAP_ATTEMPTS {
+        let Some(raw) = state.storage().get(&storage_key).await? else {
+            return Err(missing_channel_error(state, id).await?);
+        };
+        let mut record = decode_record(state, raw.clone())?;
+        record.unseal(key)?;
+        if record.version != version {
+            return Ok(Applied::Conflict(LiveText {
+                version: record.version,
+                text: record.data.text,
+            }));
+        }
+        record.ensure_mutable()?;
+
+        let data = ChannelData {
+            text: text.clone(),
+            files: std::mem::take(&mut record.data.files),
+        };
+        let previous = std::mem::take(&mut record.data.text);
+        validate_channel_data(&data)?;
+        check_content_requirement(&data, state.config().content_requirement)?;
+        record.data = data;
+        record.version += 1;
+        let update = LiveText {
+            version: record.version,
+            text: text.clone(),
+        };
+        if let Some(key) = key {
+            record.seal(key)?;
+        }
+        let encoded = encode_record(state, record.clone())?;
+        if state
+            .storage()
+            .replace_if_equals(&storage_key, &raw, &encoded)
+            .await?
+        {
+            state.stale_cache().forget(id);
+            let grace_ttl = grace_ttl(state, &record, record.ttl(state.channel_ttl()));
+            keep_grace_copy(state, id, &encoded, grace_ttl).await?;
+            let ttl = record.ttl(state.channel_ttl());
+            let previous = key.is_none().then_some((version, previous.as_str()));
+            keep_previous_text(state, id, previous, ttl).await?;
+            refresh_ttl(state, id, &record).await?;
+            state.events().on_updated(id);
+            return Ok(Applied::Accepted(update));
+        }
+    }
+    Err(AppError::ChannelLocked)
+}
+
+async fn current_state(state: &SharedState, id: &str, key: Option<&str>) -> LiveServerMessage {
+    let loaded = async {
+        let Some(raw) = state.storage().get(&state.channel_key(id)).await? else {
+            return Err(missing_channel_error(state, id).await?);
+        };
+        let mut record = decode_record(state, raw)?;
+        record.unseal(key)?;
+        Ok(LiveText {
+            version: record.version,
+            text: record.data.text,
+        })
+    };
+    match loaded.await {
+        Ok(current) => LiveServerMessage::State(current),
+        Err(err) => LiveServerMessage::Error {
+            message: err.to_string(),
+        },
+    }
+}
+
+async fn next_tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Pushes the channel's expiry out while a session is open, within any
+/// `MAX_LIFETIME_SECONDS` cap. Each session refreshes on its own timer, so
+/// refreshing stops once the last one disconnects.
+async fn keep_alive(state: &SharedState, id: &str) {
+    let refreshed = async {
+        match load_record(state, id).await? {
+            Some(record) => refresh_ttl(state, id, &record).await,
+            None => Ok(()),
+        }
+    };
+    if let Err(err) = refreshed.await {
+        tracing::warn!(%id, error = ?err, "failed to refresh live channel ttl");
+    }
+}
+
+/// Token bucket for one connection's incoming messages: `rate` tokens refill
+/// per second, up to a burst of `rate`.
+struct MessageBudget {
+    rate: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl MessageBudget {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: f64::from(rate),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn admit(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let rate = f64::from(self.rate);
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Closes with 1008 (policy violation) so the client can tell it was
+/// throttled rather than dropped.
+async fn close_rate_limited(socket: &mut WebSocket) {
+    let frame = CloseFrame {
+        code: close_code::POLICY,
+        reason: "message rate exceeded".into(),
+    };
+    let _ = socket.send(Message::Close(Some(frame))).await;
+}
+
+async fn send(socket: &mut WebSocket, message: &LiveServerMessage) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(message).expect("live messages serialize");
+    socket.send(Message::Text(json)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::MessageBudget;
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_allows_a_burst_then_refills_over_time() {
+        let mut budget = MessageBudget::new(4);
+        for _ in 0..4 {
+            assert!(budget.admit());
+        }
+        assert!(!budget.admit());
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert!(budget.admit());
+        assert!(!budget.admit());
+
+        // Idle time never banks more than one second's burst.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        for _ in 0..4 {
+            assert!(budget.admit());
+        }
+        assert!(!budget.admit());
+    }
+
+    #[test]
+    fn zero_rate_disables_the_budget() {
+        let mut budget = MessageBudget::new(0);
+        for _ in 0..100 {
+            assert!(budget.admit());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::HeaderMap,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::{
+    extract::ChannelJson,
+    handlers::{
+        ChannelFileResponse, authorize_files, charge_read, encryption_key, load_authorized,
+    },
+};
+use crate::{
+    channel::{ChannelData, ChannelFile, sha256_hex},
+    error::AppError,
+    state::{SharedState, refresh_ttl_on_read},
+};
+
+#[derive(Debug, Serialize)]
+pub struct ChannelManifest {
+    pub version: u64,
+    pub files: Vec<ManifestFile>,
+    pub text_sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    pub id: String,
+    pub name: String,
+    /// Decoded size in bytes.
+    pub size: u64,
+    /// Hex SHA-256 of the decoded content.
+    pub sha256: String,
+    /// Times the file was fetched through the raw download endpoints.
+    pub downloads: u64,
+}
+
+/// What a syncing client already holds, as taken from an earlier manifest.
+#[derive(Debug, Deserialize)]
+pub struct ChannelDeltaRequest {
+    pub version: u64,
+    /// Hex SHA-256 of each known file, by file id.
+    #[serde(default)]
+    pub file_hashes: HashMap<String, String>,
+    /// Hash of the known text. Without it the text counts as changed whenever
+    /// `version` is out of date.
+    #[serde(default)]
+    pub text_sha256: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChannelDelta {
+    pub version: u64,
+    /// New files and files whose content changed, with their bodies.
+    pub files: Vec<ChannelFileResponse>,
+    /// Known ids the channel no longer holds.
+    pub deleted: Vec<String>,
+    pub text_changed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// What a syncing client needs to tell which parts of a channel changed: the
+/// content version plus a hash per file and of the text. Nothing of the content
+/// itself is returned, so this neither counts as a read nor refreshes the TTL.
+/// Also carries each file's download count, for the channel's editors.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_manifest(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ChannelManifest>, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    record.unseal(encryption_key(&headers).as_deref())?;
+    let downloads = state
+        .storage()
+        .hget_counts(&state.download_count_key(&id))
+        .await?;
+    Ok(Json(build_manifest(
+        record.version,
+        &record.data,
+        &downloads,
+    )?))
+}
+
+/// The parts of a channel that differ from what the client holds, so a sync
+/// only transfers what changed. Content is returned, so like a fetch this
+/// counts as a read and refreshes the TTL.
+#[instrument(level = "debug", skip(state, headers, known))]
+pub async fn channel_delta(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    ChannelJson(known): ChannelJson<ChannelDeltaRequest>,
+) -> Result<Json<ChannelDelta>, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    authorize_files(&record.data.files, &headers).await?;
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+    Ok(Json(build_delta(record.version, record.data, &known)?))
+}
+
+fn build_delta(
+    version: u64,
+    data: ChannelData,
+    known: &ChannelDeltaRequest,
+) -> Result<ChannelDelta, AppError> {
+    let mut deleted: Vec<String> = known
+        .file_hashes
+        .keys()
+        .filter(|id| !data.files.iter().any(|file| &file.id == *id))
+        .cloned()
+        .collect();
+    deleted.sort();
+    let mut files = Vec::new();
+    for file in data.files {
+        let sha256 = file_sha256(&file)?;
+        if known.file_hashes.get(&file.id) != Some(&sha256) {
+            files.push(ChannelFileResponse::new(file, true));
+        }
+    }
+    let text_changed = match &known.text_sha256 {
+        Some(hash) => *hash != sha256_hex(data.text.as_bytes()),
+        None => known.version != version,
+    };
+    Ok(ChannelDelta {
+        version,
+        files,
+        deleted,
+        text_changed,
+        text: text_changed.then_some(data.text),
+    })
+}
+
+fn file_sha256(file: &ChannelFile) -> Result<String, AppError> {
+    let bytes = BASE64_ENGINE
+        .decode(&file.data_base64)
+        .map_err(|_| AppError::IntegrityError)?;
+    Ok(sha256_hex(&bytes))
+}
+
+fn build_manifest(
+    version: u64,
+    data: &ChannelData,
+    downloads: &HashMap<String, u64>,
+) -> Result<ChannelManifest, AppError> {
+    let files = data
+        .files
+        .iter()
+        .map(|file| {
+            let bytes = BASE64_ENGINE
+                .decode(&file.data_base64)
+                .map_err(|_| AppError::IntegrityError)?;
+            Ok(ManifestFile {
+                id: file.id.clone(),
+                name: file.name.clone(),
+                size: bytes.len() as u64,
+                sha256: sha256_hex(&bytes),
+                downloads: downloads.get(&file.id).copied().unwrap_or(0),
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+    Ok(ChannelManifest {
+        version,
+        files,
+        text_sha256: sha256_hex(data.text.as_bytes()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+
+    use std::collections::HashMap;
+
+    use super::build_manifest;
+    use crate::channel::{ChannelData, ChannelFile};
+
+    #[test]
+    fn manifest_hashes_decoded_content() {
+        let data = ChannelData {
+            text: String::new(),
+            files: vec![ChannelFile {
+                id: "f1".into(),
+                name: "a.txt".into(),
+                mime_type: "text/plain".into(),
+                size: 3,
+                data_base64: BASE64_ENGINE.encode(b"abc"),
+                ..ChannelFile::default()
+            }],
+        };
+        let downloads = HashMap::from([("f1".to_owned(), 2)]);
+        let manifest = build_manifest(7, &data, &downloads).unwrap();
+        assert_eq!(manifest.version, 7);
+        assert_eq!(manifest.files[0].size, 3);
+        assert_eq!(manifest.files[0].downloads, 2);
+        assert_eq!(
+            manifest.files[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            manifest.text_sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
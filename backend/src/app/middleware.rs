@@ -0,0 +1,565 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    body::{Body, HttpBody, to_bytes},
+    extract::{ConnectInfo, MatchedPath, Query, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use md5::Md5;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use tower_http::request_id::RequestId;
+
+use super::UpdateChannelQuery;
+use crate::{
+    access_log::{AccessAction, AccessEvent, AccessResult},
+    channel::unix_now,
+    config::{ClientVersion, HSTS_MAX_AGE_SECONDS},
+    error::{AppError, ErrorResponse, REQUEST_ID},
+    rate_limit::Endpoint,
+    state::SharedState,
+};
+
+const CONTENT_MD5_HEADER: &str = "content-md5";
+const CONTENT_SHA256_HEADER: &str = "x-content-sha256";
+const FORWARDED_PROTO_HEADER: &str = "x-forwarded-proto";
+const CLIENT_VERSION_HEADER: &str = "x-client-version";
+pub(super) const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+/// Routes that create channels rather than address an existing one.
+const CREATE_ROUTES: &[&str] = &[
+    "/api/channels",
+    "/api/channels/bulk",
+    "/api/channels/import",
+];
+/// Routes that take a POST body but only read the channel.
+const READ_ONLY_POSTS: &[&str] = &[
+    "/api/channels/:id/auth",
+    "/api/channels/:id/delta",
+    "/api/channels/:id/files/raw",
+];
+/// Longest bare error body `json_error_bodies` keeps as the message.
+const MAX_BARE_ERROR_BYTES: usize = 1024;
+/// Query parameters whose values must never reach the logs.
+const REDACTED_QUERY_PARAMS: &[&str] = &["pw"];
+
+/// Request span for `TraceLayer`, matching its default one except that secret
+/// query parameters such as `?pw=` are masked in the logged URI.
+pub fn request_span(request: &Request) -> tracing::Span {
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %redact_query(request.uri()),
+        version = ?request.version(),
+        request_id = request_id(request).unwrap_or_default(),
+    )
+}
+
+/// Makes the request id available to `AppError` responses for the rest of
+/// the request, including errors raised by the layers inside this one.
+pub async fn scope_request_id(request: Request, next: Next) -> Response {
+    let id = request_id(&request).unwrap_or_default().to_owned();
+    REQUEST_ID.scope(id, next.run(request)).await
+}
+
+/// Rewrites error responses that arrive without a JSON body, such as the
+/// plain-text `413` from the body limit, as `ErrorResponse` JSON. Runs inside
+/// `scope_request_id`, so they carry the request id like handler errors do.
+pub async fn json_error_bodies(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) || is_json(response.headers()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let message = to_bytes(body, MAX_BARE_ERROR_BYTES)
+        .await
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+        .map(|text| text.trim().to_owned())
+        .filter(|text| !text.is_empty())
+        .unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("request failed")
+                .to_owned()
+        });
+    let body = serde_json::to_vec(&ErrorResponse::new(message)).expect("error bodies serialize");
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_ENCODING);
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn request_id(request: &Request) -> Option<&str> {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+}
+
+fn redact_query(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let redacted: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if REDACTED_QUERY_PARAMS.contains(&name) => format!("{name}=***"),
+            _ => pair.to_owned(),
+        })
+        .collect();
+    format!("{}?{}", uri.path(), redacted.join("&"))
+}
+
+/// Applied with `MIN_CLIENT_VERSION`: refuses writes from clients whose
+/// `x-client-version` is older, unreadable or missing with `426`, so operators
+/// can retire a broken client. Reads and admin routes are never refused.
+pub async fn require_client_version(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(min) = &state.config().min_client_version else {
+        return Ok(next.run(request).await);
+    };
+    if is_client_write(&request) {
+        let version = request
+            .headers()
+            .get(CLIENT_VERSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<ClientVersion>().ok());
+        if version.is_none_or(|version| version < *min) {
+            return Err(AppError::ClientTooOld(min.to_string()));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+fn is_client_write(request: &Request) -> bool {
+    let Some(route) = request.extensions().get::<MatchedPath>() else {
+        return false;
+    };
+    let route = route.as_str();
+    if !route.starts_with("/api/") {
+        return false;
+    }
+    match *request.method() {
+        Method::PUT | Method::DELETE => true,
+        Method::POST => !READ_ONLY_POSTS.contains(&route),
+        _ => false,
+    }
+}
+
+/// Applied with `GLOBAL_RATE_LIMIT`: sheds requests past the instance-wide
+/// rate with `503` before they cost any work. Health and readiness probes are
+/// always let through.
+pub async fn shed_global_excess(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let probe = matches!(request.uri().path(), "/health" | "/ready");
+    if let (Some(limiter), false) = (state.global_limiter(), probe) {
+        limiter.check()?;
+    }
+    Ok(next.run(request).await)
+}
+
+/// Applied with any `RATE_LIMIT_*`: takes each limited request from its
+/// bucket before anything else looks at it, answering `429` with a
+/// `Retry-After` once the bucket is empty.
+pub async fn rate_limit(
+    State(state): State<SharedState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(endpoint) = rate_limited_endpoint(&request) else {
+        return Ok(next.run(request).await);
+    };
+    let channel = request
+        .uri()
+        .path()
+        .strip_prefix("/api/channels/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|_| endpoint != Endpoint::Create);
+    let ip = peer.map(|ConnectInfo(addr)| addr.ip());
+    state
+        .limiter()
+        .check(state.storage(), endpoint, ip, channel)
+        .await?;
+    Ok(next.run(request).await)
+}
+
+fn rate_limited_endpoint(request: &Request) -> Option<Endpoint> {
+    let route = request.extensions().get::<MatchedPath>()?.as_str();
+    let endpoint = match (request.method().clone(), route) {
+        (Method::POST, route) if CREATE_ROUTES.contains(&route) => Endpoint::Create,
+        // An upsert may create, so it is held to the create limit.
+        (Method::PUT, "/api/channels/:id") if is_upsert(request.uri()) => Endpoint::Create,
+        (
+            Method::GET | Method::HEAD,
+            "/api/channels/:id" | "/api/channels/:id/text" | "/api/channels/:id/view",
+        ) => Endpoint::Fetch,
+        (Method::PUT | Method::DELETE, "/api/channels/:id") => Endpoint::Update,
+        (Method::GET | Method::HEAD, "/api/channels/:id/files/:file_id")
+        | (Method::GET | Method::HEAD, "/api/channels/:id/archive.tar")
+        | (Method::POST, "/api/channels/:id/files/raw") => Endpoint::Download,
+        _ => return None,
+    };
+    Some(endpoint)
+}
+
+fn is_upsert(uri: &Uri) -> bool {
+    Query::<UpdateChannelQuery>::try_from_uri(uri).is_ok_and(|Query(query)| query.upsert)
+}
+
+/// Applied with `ACCESS_LOG_SINK`: records every create, read, update and
+/// delete of a channel, with its outcome, for the access log. Only the route
+/// and response status are looked at; bodies are read solely to find the ids
+/// of newly created channels.
+pub async fn log_access(
+    State(state): State<SharedState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(action) = access_action(&request) else {
+        return next.run(request).await;
+    };
+    let id = request
+        .uri()
+        .path()
+        .strip_prefix("/api/channels/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|_| action != AccessAction::Create)
+        .map(str::to_owned);
+    let ip = peer.map(|ConnectInfo(addr)| addr.ip().to_string());
+    let forwarded_for = request
+        .headers()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let request_id = request_id(&request).map(str::to_owned);
+
+    let response = next.run(request).await;
+    let status = response.status();
+    let (response, ids) = if action == AccessAction::Create && status.is_success() {
+        created_ids(response).await
+    } else {
+        (response, vec![id])
+    };
+    let failed = status.is_client_error() || status.is_server_error();
+    for id in ids {
+        // A bulk create answers 200 even for the items it refused.
+        let result = if failed || (action == AccessAction::Create && id.is_none()) {
+            AccessResult::Failure
+        } else {
+            AccessResult::Success
+        };
+        state.access_log().record(AccessEvent {
+            timestamp: unix_now(),
+            action,
+            id,
+            result,
+            status: status.as_u16(),
+            ip: ip.clone(),
+            forwarded_for: forwarded_for.clone(),
+            request_id: request_id.clone(),
+        });
+    }
+    response
+}
+
+fn access_action(request: &Request) -> Option<AccessAction> {
+    let route = request.extensions().get::<MatchedPath>()?.as_str();
+    if !route.starts_with("/api/channels") {
+        return None;
+    }
+    let action = match *request.method() {
+        Method::POST if CREATE_ROUTES.contains(&route) => AccessAction::Create,
+        Method::POST if READ_ONLY_POSTS.contains(&route) => AccessAction::Read,
+        Method::POST if route.ends_with("/files/delete") => AccessAction::Delete,
+        Method::POST | Method::PUT => AccessAction::Update,
+        Method::DELETE => AccessAction::Delete,
+        Method::GET => AccessAction::Read,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// The ids in a single or bulk create response, which is put back together
+/// unchanged. Refused bulk items, and a body that cannot be read, have no id.
+async fn created_ids(response: Response) -> (Response, Vec<Option<String>>) {
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return (Response::from_parts(parts, Body::empty()), vec![None]);
+    };
+    let ids = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Array(results)) => results
+            .iter()
+            .map(|result| {
+                result
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .map(str::to_owned)
+            })
+            .collect(),
+        Ok(created) => vec![
+            created
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(str::to_owned),
+        ],
+        Err(_) => vec![None],
+    };
+    (Response::from_parts(parts, Body::from(bytes)), ids)
+}
+
+/// Applied with `REQUIRE_HTTPS`: rejects requests a proxy reports as plain
+/// HTTP, so a misconfigured client fails loudly instead of sending passwords
+/// in the clear, and asks browsers to stick to HTTPS. Requests without
+/// `x-forwarded-proto` (probes talking to the server directly) pass.
+pub async fn require_https(request: Request, next: Next) -> Result<Response, AppError> {
+    if !forwarded_as_https(request.headers()) {
+        return Err(AppError::HttpsRequired);
+    }
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        header::STRICT_TRANSPORT_SECURITY,
+        HeaderValue::from_str(&format!("max-age={HSTS_MAX_AGE_SECONDS}"))
+            .expect("hsts value is ASCII"),
+    );
+    Ok(response)
+}
+
+/// The first `x-forwarded-proto` entry is the one the client used.
+fn forwarded_as_https(headers: &HeaderMap) -> bool {
+    let Some(proto) = headers.get(FORWARDED_PROTO_HEADER) else {
+        return true;
+    };
+    proto
+        .to_str()
+        .ok()
+        .and_then(|value| value.split(',').next())
+        .is_some_and(|first| first.trim().eq_ignore_ascii_case("https"))
+}
+
+/// Holds back `401`, `404` and `410` responses until `AUTH_FAILURE_DELAY_MS`
+/// (plus random jitter) has passed since the request arrived. Every failure
+/// then takes about the same time, whether or not a channel was found and a
+/// password hashed.
+pub async fn pad_auth_failures(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(floor) = state.config().auth_failure_delay else {
+        return next.run(request).await;
+    };
+    let started = Instant::now();
+    let response = next.run(request).await;
+    if is_auth_failure(response.status()) {
+        tokio::time::sleep_until(started + floor + jitter(floor)).await;
+    }
+    response
+}
+
+fn is_auth_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::UNAUTHORIZED | StatusCode::NOT_FOUND | StatusCode::GONE
+    )
+}
+
+fn jitter(floor: Duration) -> Duration {
+    rand::thread_rng().gen_range(Duration::ZERO..=floor / 2)
+}
+
+/// Applied with `RESPONSE_ENVELOPE`: rewraps every JSON body as
+/// `{"data": ...}`, or `{"error": ...}` for error statuses. Other bodies
+/// (raw files, archives, streams) pass through untouched.
+pub async fn wrap_in_envelope(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if !is_json(response.headers()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to buffer response for envelope");
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let field = if parts.status.is_client_error() || parts.status.is_server_error() {
+        "error"
+    } else {
+        "data"
+    };
+    let wrapped =
+        serde_json::to_vec(&serde_json::json!({ field: value })).expect("json values serialize");
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(wrapped))
+}
+
+pub(super) fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Refuses a request whose `Content-Length` (or known body size) is over
+/// `MAX_REQUEST_BYTES` before any of the body is buffered. Bodies without a
+/// declared length are cut off by the body-limit layer while streaming
+/// instead.
+pub async fn reject_oversized_requests(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or_default()
+        .max(request.body().size_hint().lower());
+    if declared > state.config().max_request_bytes as u64 {
+        return Err(AppError::RequestTooLarge);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Verifies an optional `Content-MD5` (base64) or `x-content-sha256` (hex)
+/// header against the received body before any handler sees it. Requests
+/// without either header pass through untouched.
+pub async fn verify_content_checksum(request: Request, next: Next) -> Result<Response, AppError> {
+    if !request.headers().contains_key(CONTENT_MD5_HEADER)
+        && !request.headers().contains_key(CONTENT_SHA256_HEADER)
+    {
+        return Ok(next.run(request).await);
+    }
+
+    let (parts, body) = request.into_parts();
+    // The body-limit layer outside this one already caps the size.
+    let bytes = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| AppError::RequestTooLarge)?;
+    verify_checksum(&parts.headers, &bytes)?;
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
+
+fn verify_checksum(headers: &HeaderMap, body: &[u8]) -> Result<(), AppError> {
+    if let Some(expected) = headers.get(CONTENT_SHA256_HEADER) {
+        let expected = expected
+            .to_str()
+            .ok()
+            .and_then(|value| hex::decode(value.trim()).ok())
+            .ok_or(AppError::ChecksumMismatch)?;
+        if Sha256::digest(body).as_slice() != expected.as_slice() {
+            return Err(AppError::ChecksumMismatch);
+        }
+    }
+
+    if let Some(expected) = headers.get(CONTENT_MD5_HEADER) {
+        let expected = expected
+            .to_str()
+            .ok()
+            .and_then(|value| BASE64_ENGINE.decode(value.trim()).ok())
+            .ok_or(AppError::ChecksumMismatch)?;
+        if Md5::digest(body).as_slice() != expected.as_slice() {
+            return Err(AppError::ChecksumMismatch);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, Uri};
+
+    use super::{forwarded_as_https, redact_query, verify_checksum};
+    use crate::error::AppError;
+
+    const BODY: &[u8] = br#"{"text":"hello"}"#;
+
+    #[test]
+    fn first_forwarded_proto_decides() {
+        assert!(forwarded_as_https(&HeaderMap::new()));
+        assert!(forwarded_as_https(&header("x-forwarded-proto", "HTTPS")));
+        assert!(forwarded_as_https(&header(
+            "x-forwarded-proto",
+            "https, http"
+        )));
+        assert!(!forwarded_as_https(&header("x-forwarded-proto", "http")));
+        assert!(!forwarded_as_https(&header(
+            "x-forwarded-proto",
+            "http,https"
+        )));
+    }
+
+    #[test]
+    fn password_query_parameter_is_redacted() {
+        let uri: Uri = "/api/channels/abc?include_files=false&pw=hunter2"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            redact_query(&uri),
+            "/api/channels/abc?include_files=false&pw=***"
+        );
+        let plain: Uri = "/api/channels/abc?include_files=true".parse().unwrap();
+        assert_eq!(redact_query(&plain), "/api/channels/abc?include_files=true");
+    }
+
+    fn header(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn matching_checksums_are_accepted() {
+        let sha = header(
+            "x-content-sha256",
+            "cbbbdcd27692344de5dbab3abcaba413fb0f45307267de7081401576df1cb176",
+        );
+        let md5 = header("content-md5", "dK7KYFCeJC/ugZk8TRONBg==");
+        assert!(verify_checksum(&sha, BODY).is_ok());
+        assert!(verify_checksum(&md5, BODY).is_ok());
+        assert!(verify_checksum(&HeaderMap::new(), BODY).is_ok());
+    }
+
+    #[test]
+    fn mismatched_checksums_are_rejected() {
+        let sha = header("x-content-sha256", &"00".repeat(32));
+        assert!(matches!(
+            verify_checksum(&sha, BODY),
+            Err(AppError::ChecksumMismatch)
+        ));
+
+        let md5 = header("content-md5", "not base64!");
+        assert!(matches!(
+            verify_checksum(&md5, BODY),
+            Err(AppError::ChecksumMismatch)
+        ));
+    }
+}
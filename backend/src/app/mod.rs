@@ -1,41 +1,197 @@
+mod admin;
+mod approval;
+mod archive;
+mod bulk;
+mod compare;
+mod export;
+mod extract;
+mod files;
 mod handlers;
+mod history;
+mod live;
+mod manifest;
+mod middleware;
+mod password;
+#[cfg(test)]
+mod tests;
+mod text;
+mod view;
 
+pub use admin::{
+    EvictRequest, EvictResponse, EvictedChannel, TaggedChannelsResponse, admin_routes,
+    evict_channels, raw_channel, tagged_channels,
+};
+pub use archive::download_channel_archive;
+pub use bulk::{BulkCreateResult, bulk_create_channels};
+pub use compare::{ChannelEqualsResponse, channel_equals};
+pub use export::{ChannelExport, EXPORT_FORMAT_VERSION, export_channel, import_channel};
+pub use extract::ChannelJson;
+pub use files::{
+    Disposition, RawFileQuery, RawFilesRequest, download_channel_file, download_channel_files,
+};
 pub use handlers::{
-    ChannelPayloadResponse, CreateChannelRequest, CreateChannelResponse, UpdateChannelRequest,
-    create_channel, delete_channel_file, fetch_channel, health_check, update_channel,
+    ChannelFileResponse, ChannelMetadataResponse, ChannelPayloadResponse, ChannelPreviewResponse,
+    CreateChannelRequest, CreateChannelResponse, DeleteFilesRequest, DeleteFilesResponse,
+    FetchChannelQuery, ReadinessResponse, RegenerateIdResponse, TouchChannelResponse,
+    UpdateChannelQuery, UpdateChannelRequest, channel_exists, channel_metadata, channel_options,
+    check_channel_password, create_channel, delete_channel, delete_channel_file,
+    delete_channel_files, fetch_channel, health_check, preview_channel, readiness_check,
+    regenerate_channel_id, touch_channel, update_channel,
+};
+pub use history::{ChannelHistoryResponse, RevertChannelResponse, channel_history, revert_channel};
+pub use live::{LiveClientMessage, LiveQuery, LiveServerMessage, channel_socket};
+pub use manifest::{
+    ChannelDelta, ChannelDeltaRequest, ChannelManifest, ManifestFile, channel_delta,
+    channel_manifest,
+};
+pub use middleware::{
+    json_error_bodies, log_access, pad_auth_failures, rate_limit, reject_oversized_requests,
+    request_span, require_client_version, require_https, scope_request_id, shed_global_excess,
+    verify_content_checksum, wrap_in_envelope,
 };
+pub use password::{
+    IssuedPassword, PasswordTokenQuery, RevealedPasswordResponse, reveal_channel_password,
+};
+pub use text::{TextQuery, channel_diff, channel_text};
+pub use view::{ViewQuery, view_channel};
 
 use axum::{
     Router,
     extract::DefaultBodyLimit,
-    routing::{delete, get, post},
+    routing::{get, post},
+};
+use tower_http::{
+    cors::CorsLayer,
+    limit::RequestBodyLimitLayer,
+    normalize_path::NormalizePath,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
 };
-use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer, trace::TraceLayer};
 
-use crate::{config::MAX_REQUEST_BYTES, state::SharedState};
+use crate::state::SharedState;
 
 pub fn build_router(state: SharedState) -> Router {
-    Router::new()
+    let mut router = Router::new()
         .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
         .route("/api/channels", post(create_channel))
-        .route("/api/channels/:id", get(fetch_channel).put(update_channel))
+        .route("/api/channels/bulk", post(bulk_create_channels))
+        .route("/api/channels/import", post(import_channel))
         .route(
-            "/api/channels/:id/files/:file_id",
-            delete(delete_channel_file),
+            "/api/channels/:id",
+            get(fetch_channel)
+                .head(channel_exists)
+                .put(update_channel)
+                .delete(delete_channel)
+                .options(channel_options),
+        )
+        .route("/api/channels/:id/equals/:other_id", get(channel_equals))
+        .route(
+            "/api/channels/:id/archive.tar",
+            get(download_channel_archive),
+        )
+        .route("/api/channels/:id/auth", post(check_channel_password))
+        .route("/api/channels/:id/delta", post(channel_delta))
+        .route("/api/channels/:id/diff", get(channel_diff))
+        .route("/api/channels/:id/export", get(export_channel))
+        .route("/api/channels/:id/history", get(channel_history))
+        .route("/api/channels/:id/live", get(channel_socket))
+        .route("/api/channels/:id/manifest", get(channel_manifest))
+        .route("/api/channels/:id/metadata", get(channel_metadata))
+        .route("/api/channels/:id/password", get(reveal_channel_password))
+        .route("/api/channels/:id/preview", get(preview_channel))
+        .route("/api/channels/:id/text", get(channel_text))
+        .route("/api/channels/:id/revert/:version", post(revert_channel))
+        .route("/api/channels/:id/touch", post(touch_channel))
+        .route("/api/channels/:id/view", get(view_channel))
+        .route(
+            "/api/channels/:id/regenerate-id",
+            post(regenerate_channel_id),
         )
+        .route("/api/channels/:id/files/delete", post(delete_channel_files))
+        .route("/api/channels/:id/files/raw", post(download_channel_files))
+        .route(
+            "/api/channels/:id/files/:file_id",
+            get(download_channel_file).delete(delete_channel_file),
+        );
+
+    if state.config().admin_token.is_some() {
+        router = router.merge(admin_routes(state.clone()));
+    }
+    if state.limiter().is_enabled() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit,
+        ));
+    }
+    if state.config().auth_failure_delay.is_some() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            pad_auth_failures,
+        ));
+    }
+    if state.config().min_client_version.is_some() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_client_version,
+        ));
+    }
+    if state.config().require_https {
+        router = router.layer(axum::middleware::from_fn(require_https));
+    }
+    if state.config().access_log_sink.is_some() {
+        // Outside the checks above, so the requests they refuse are logged too.
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            log_access,
+        ));
+    }
+
+    let request_id_header = state.config().request_id_header.clone();
+    let max_request_bytes = state.config().max_request_bytes;
+    let strict_trailing_slash = state.config().strict_trailing_slash;
+    let mut router = router
         .layer(
             CorsLayer::new()
                 .allow_methods([
                     axum::http::Method::GET,
                     axum::http::Method::POST,
                     axum::http::Method::PUT,
+                    axum::http::Method::DELETE,
                     axum::http::Method::OPTIONS,
                 ])
                 .allow_origin(tower_http::cors::Any)
                 .allow_headers(tower_http::cors::Any),
         )
+        .layer(axum::middleware::from_fn(verify_content_checksum))
         .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(MAX_REQUEST_BYTES))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state)
+        .layer(RequestBodyLimitLayer::new(max_request_bytes))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            reject_oversized_requests,
+        ));
+    if state.global_limiter().is_some() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            shed_global_excess,
+        ));
+    }
+    router = router.layer(axum::middleware::from_fn(json_error_bodies));
+    if state.config().response_envelope {
+        // Outside the layers above, so their errors are wrapped as well.
+        router = router.layer(axum::middleware::from_fn(wrap_in_envelope));
+    }
+    let router = router
+        .layer(axum::middleware::from_fn(scope_request_id))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(PropagateRequestIdLayer::new(request_id_header.clone()))
+        .layer(SetRequestIdLayer::new(request_id_header, MakeRequestUuid))
+        .with_state(state);
+
+    if strict_trailing_slash {
+        return router;
+    }
+    // Layers added with `Router::layer` only run once a route has matched, so
+    // the path is trimmed by a service wrapped around the whole router.
+    Router::new().fallback_service(NormalizePath::trim_trailing_slash(router))
 }
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{channel::sha256_hex, error::AppError, state::SharedState};
+
+const NONCE_LENGTH: usize = 12;
+/// Keeps the key derived from a token apart from the token's stored hash.
+const TOKEN_KEY_CONTEXT: &[u8] = b"lynkc password token key\0";
+
+/// How a freshly created channel's password reaches its creator: in the
+/// response itself, or, with `PASSWORD_TOKEN_TTL_SECONDS`, as a token that
+/// `/api/channels/:id/password` exchanges for it once.
+#[derive(Debug, Serialize)]
+pub struct IssuedPassword {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_token: Option<String>,
+}
+
+/// Stored under the token key until redeemed. The password is encrypted with
+/// ChaCha20-Poly1305 under a key derived from the token, and only the token's
+/// hash is kept, so the stored value alone reveals neither.
+#[derive(Serialize, Deserialize)]
+struct PendingPassword {
+    token_hash: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl PendingPassword {
+    fn seal(token: &str, password: &str) -> Result<Self, AppError> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = token_cipher(token)
+            .encrypt(Nonce::from_slice(&nonce), password.as_bytes())
+            .map_err(|_| AppError::InvalidPasswordToken)?;
+        Ok(Self {
+            token_hash: sha256_hex(token.as_bytes()),
+            nonce: BASE64_ENGINE.encode(nonce),
+            ciphertext: BASE64_ENGINE.encode(ciphertext),
+        })
+    }
+
+    /// The password, or `InvalidPasswordToken` for any token but the one it
+    /// was sealed with.
+    fn open(&self, token: &str) -> Result<String, AppError> {
+        if self.token_hash != sha256_hex(token.as_bytes()) {
+            return Err(AppError::InvalidPasswordToken);
+        }
+        let decode = |value: &str| {
+            BASE64_ENGINE
+                .decode(value)
+                .map_err(|_| AppError::InvalidPasswordToken)
+        };
+        let nonce = decode(&self.nonce)?;
+        if nonce.len() != NONCE_LENGTH {
+            return Err(AppError::InvalidPasswordToken);
+        }
+        let password = token_cipher(token)
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                decode(&self.ciphertext)?.as_slice(),
+            )
+            .map_err(|_| AppError::InvalidPasswordToken)?;
+        String::from_utf8(password).map_err(|_| AppError::InvalidPasswordToken)
+    }
+}
+
+/// Tokens are random UUIDs, so one SHA-256 pass is enough to turn them into a
+/// key; no slow hash is needed to resist guessing.
+fn token_cipher(token: &str) -> ChaCha20Poly1305 {
+    let digest = Sha256::new()
+        .chain_update(TOKEN_KEY_CONTEXT)
+        .chain_update(token.as_bytes())
+        .finalize();
+    ChaCha20Poly1305::new(Key::from_slice(&digest))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordTokenQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevealedPasswordResponse {
+    pub password: String,
+}
+
+/// Hands out the password of channel `id`, which lives `ttl`. With tokens
+/// enabled the password is parked under a single-use token that expires with
+/// the token TTL or the channel, whichever comes first.
+pub(super) async fn issue_password(
+    state: &SharedState,
+    id: &str,
+    password: String,
+    ttl: Duration,
+) -> Result<IssuedPassword, AppError> {
+    let Some(token_ttl) = state.config().password_token_ttl else {
+        return Ok(IssuedPassword {
+            password: Some(password),
+            password_token: None,
+        });
+    };
+    let token = Uuid::new_v4().simple().to_string();
+    let pending = serde_json::to_string(&PendingPassword::seal(&token, &password)?)?;
+    state
+        .storage()
+        .set_ex(&state.password_token_key(id), &pending, token_ttl.min(ttl))
+        .await?;
+    Ok(IssuedPassword {
+        password: None,
+        password_token: Some(token),
+    })
+}
+
+/// Exchanges a `password_token` for the channel's password. The first
+/// successful call consumes the token; reuse, a wrong token and an expired one
+/// all get the same `404`.
+#[instrument(level = "debug", skip(state, query))]
+pub async fn reveal_channel_password(
+    Path(id): Path<String>,
+    Query(query): Query<PasswordTokenQuery>,
+    State(state): State<SharedState>,
+) -> Result<impl IntoResponse, AppError> {
+    let key = state.password_token_key(&id);
+    let Some(raw) = state.storage().get(&key).await? else {
+        return Err(AppError::InvalidPasswordToken);
+    };
+    // Entries in an older layout cannot be opened; they expire on their own.
+    let pending: PendingPassword =
+        serde_json::from_str(&raw).map_err(|_| AppError::InvalidPasswordToken)?;
+    let password = pending.open(&query.token)?;
+    // Of two concurrent redemptions only the one that deletes the key wins.
+    if !state.storage().delete_if_equals(&key, &raw).await? {
+        return Err(AppError::InvalidPasswordToken);
+    }
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(RevealedPasswordResponse { password }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PendingPassword;
+    use crate::error::AppError;
+
+    #[test]
+    fn pending_passwords_are_stored_encrypted() {
+        let pending = PendingPassword::seal("the-token", "hunter2-hunter2").unwrap();
+        let stored = serde_json::to_string(&pending).unwrap();
+        assert!(!stored.contains("hunter2"));
+        assert_eq!(pending.open("the-token").unwrap(), "hunter2-hunter2");
+        assert!(matches!(
+            pending.open("another-token"),
+            Err(AppError::InvalidPasswordToken)
+        ));
+    }
+}
@@ -0,0 +1,3173 @@
+//! End-to-end handler tests against the in-memory storage backend.
+
+use std::{io::Read, time::Duration};
+
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, header};
+use axum_test::{TestServer, TestServerConfig, WsMessage};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64_ENGINE};
+use serde_json::{Value, json};
+
+use super::build_router;
+use crate::{
+    channel::{
+        CHANNEL_FORMAT_VERSION, ChannelData, StoredChannel, hash_channel_password,
+        serialize_channel,
+    },
+    config::{AppConfig, ContentRequirement, FileIdPolicy, PublicBaseUrl, TtlPolicy},
+    events::{EventSink, EventSinks},
+    rate_limit::RateLimits,
+    state::{AppState, shared},
+    storage::{MemoryStorage, Storage},
+    webhook::HttpTarget,
+};
+
+fn server_with(config: AppConfig) -> TestServer {
+    let state = shared(AppState::new(config, Box::new(MemoryStorage::new())));
+    TestServer::new(build_router(state)).expect("test server")
+}
+
+fn server() -> TestServer {
+    server_with(AppConfig::default())
+}
+
+fn password(value: &str) -> (HeaderName, HeaderValue) {
+    (
+        HeaderName::from_static("x-channel-password"),
+        HeaderValue::from_str(value).unwrap(),
+    )
+}
+
+async fn create(server: &TestServer, body: Value) -> (String, String) {
+    let response = server.post("/api/channels").json(&body).await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+    let created: Value = response.json();
+    (
+        created["id"].as_str().unwrap().to_owned(),
+        created["password"].as_str().unwrap_or_default().to_owned(),
+    )
+}
+
+#[tokio::test]
+async fn created_channel_can_be_fetched_and_updated() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "hello" })).await;
+    let (name, value) = password(&pass);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(fetched["text"], "hello");
+    assert!(fetched["ttl_seconds"].as_i64().unwrap() > 0);
+
+    let updated = server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .json(&json!({ "text": "bye" }))
+        .await;
+    assert_eq!(updated.status_code(), StatusCode::NO_CONTENT);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "bye");
+}
+
+#[tokio::test]
+async fn wrong_password_and_unknown_ids_are_rejected() {
+    let server = server();
+    let (id, _) = create(&server, json!({ "text": "secret" })).await;
+    let (name, value) = password("nope");
+
+    let response = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let response = server.get("/api/channels/missing").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn hidden_existence_answers_wrong_passwords_like_unknown_ids() {
+    let server = server_with(AppConfig {
+        hide_channel_existence: true,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&server, json!({ "text": "secret" })).await;
+    let (read_once, read_once_pass) =
+        create(&server, json!({ "text": "once", "max_reads": 1 })).await;
+    let (name, value) = password(&read_once_pass);
+    server
+        .get(&format!("/api/channels/{read_once}"))
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+
+    let (name, value) = password("nope");
+    let wrong = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    let missing = server
+        .get("/api/channels/missing")
+        .add_header(name.clone(), value.clone())
+        .await;
+    let (_, right) = password(&pass);
+    let read_out = server
+        .get(&format!("/api/channels/{read_once}"))
+        .add_header(name, right)
+        .await;
+    for response in [&wrong, &missing, &read_out] {
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        // Bodies differ only in their request id.
+        let mut body: Value = response.json();
+        body["request_id"].take();
+        assert_eq!(
+            body,
+            json!({ "message": "channel not found", "request_id": null })
+        );
+    }
+}
+
+#[tokio::test]
+async fn auth_failures_take_at_least_the_configured_delay() {
+    let floor = Duration::from_millis(200);
+    let server = server_with(AppConfig {
+        auth_failure_delay: Some(floor),
+        ..AppConfig::default()
+    });
+    let (id, _) = create(&server, json!({ "text": "secret" })).await;
+    let (name, value) = password("nope");
+
+    for path in [
+        format!("/api/channels/{id}"),
+        "/api/channels/missing".into(),
+    ] {
+        let started = std::time::Instant::now();
+        let response = server
+            .get(&path)
+            .add_header(name.clone(), value.clone())
+            .await;
+        assert!(response.status_code().is_client_error());
+        assert!(started.elapsed() >= floor, "{path} answered early");
+    }
+}
+
+#[tokio::test]
+async fn read_limited_channel_is_gone_after_its_last_read() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "once", "max_reads": 1 })).await;
+    let (name, value) = password(&pass);
+
+    let first = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert_eq!(first.status_code(), StatusCode::OK);
+
+    let second = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(second.status_code(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn burn_after_read_channels_are_served_exactly_once() {
+    let server = server();
+    let (id, pass) = create(
+        &server,
+        json!({ "text": "one-time secret", "burn_after_read": true }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+    let url = format!("/api/channels/{id}");
+
+    let reads = futures_util::future::join_all((0..5).map(|_| {
+        let request = server.get(&url).add_header(name.clone(), value.clone());
+        async move { request.await }
+    }))
+    .await;
+    let served: Vec<_> = reads
+        .iter()
+        .filter(|read| read.status_code() == StatusCode::OK)
+        .collect();
+    assert_eq!(served.len(), 1);
+    assert_eq!(served[0].json::<Value>()["text"], "one-time secret");
+    assert!(
+        reads
+            .iter()
+            .all(|read| read.status_code() != StatusCode::GONE)
+    );
+
+    server
+        .get(&url)
+        .add_header(name, value)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(start_paused = true)]
+async fn head_reports_existence_and_ttl_without_reading() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "polled", "max_reads": 1 })).await;
+    let url = format!("/api/channels/{id}");
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    let head = server.method(Method::HEAD, &url).await;
+    head.assert_status_ok();
+    assert!(head.as_bytes().is_empty());
+    assert_eq!(head.header("x-channel-ttl"), "840");
+    tokio::time::advance(Duration::from_secs(60)).await;
+    let head = server.method(Method::HEAD, &url).await;
+    assert_eq!(head.header("x-channel-ttl"), "780");
+
+    // Neither counted as the one allowed read nor refreshing the TTL.
+    let (name, value) = password(&pass);
+    server
+        .get(&url)
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+    server
+        .method(Method::HEAD, "/api/channels/missing")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn files_can_be_downloaded_and_deleted() {
+    let server = server();
+    let body = json!({
+        "files": [{
+            "id": "f1",
+            "name": "notes.txt",
+            "mime_type": "text/plain",
+            "size": 5,
+            "data_base64": BASE64_ENGINE.encode(b"hello"),
+        }],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+
+    let download = server
+        .get(&format!("/api/channels/{id}/files/f1"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert_eq!(download.status_code(), StatusCode::OK);
+    assert_eq!(download.as_bytes().as_ref(), b"hello");
+
+    let deleted = server
+        .delete(&format!("/api/channels/{id}/files/f1"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert!(deleted.status_code().is_success());
+
+    let missing = server
+        .get(&format!("/api/channels/{id}/files/f1"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn regenerated_id_moves_the_channel() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "moving" })).await;
+    let (name, value) = password(&pass);
+
+    let regenerated: Value = server
+        .post(&format!("/api/channels/{id}/regenerate-id"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    let new_id = regenerated["id"].as_str().unwrap();
+    assert_ne!(new_id, id);
+
+    let old = server.get(&format!("/api/channels/{id}")).await;
+    assert_eq!(old.status_code(), StatusCode::NOT_FOUND);
+    let fetched: Value = server
+        .get(&format!("/api/channels/{new_id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "moving");
+}
+
+#[tokio::test]
+async fn upsert_creates_a_channel_at_the_given_id() {
+    let server = server();
+    let created = server
+        .put("/api/channels/my-channel")
+        .add_query_param("upsert", true)
+        .json(&json!({ "text": "fresh" }))
+        .await;
+    assert_eq!(created.status_code(), StatusCode::CREATED);
+    let pass = created.json::<Value>()["password"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let (name, value) = password(&pass);
+    let fetched: Value = server
+        .get("/api/channels/my-channel")
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "fresh");
+}
+
+#[tokio::test]
+async fn admin_routes_require_the_token() {
+    let server = server_with(AppConfig {
+        admin_token: Some("admin".into()),
+        ..AppConfig::default()
+    });
+    let (id, _) = create(&server, json!({ "text": "raw" })).await;
+
+    let denied = server.get(&format!("/admin/channels/{id}/raw")).await;
+    assert_eq!(denied.status_code(), StatusCode::UNAUTHORIZED);
+
+    let raw: Value = server
+        .get(&format!("/admin/channels/{id}/raw"))
+        .add_header(
+            HeaderName::from_static("x-admin-token"),
+            HeaderValue::from_static("admin"),
+        )
+        .await
+        .json();
+    assert_eq!(raw["text"], "raw");
+}
+
+#[tokio::test]
+async fn encrypted_channel_needs_its_key_to_be_read() {
+    let server = server_with(AppConfig {
+        admin_token: Some("admin".into()),
+        ..AppConfig::default()
+    });
+    let encryption = (
+        HeaderName::from_static("x-channel-encryption-key"),
+        HeaderValue::from_static("correct horse"),
+    );
+    let created: Value = server
+        .post("/api/channels")
+        .add_header(encryption.0.clone(), encryption.1.clone())
+        .json(&json!({ "text": "eyes only" }))
+        .await
+        .json();
+    let id = created["id"].as_str().unwrap();
+    let (name, value) = password(created["password"].as_str().unwrap());
+
+    let raw = server
+        .get(&format!("/admin/channels/{id}/raw"))
+        .add_header(
+            HeaderName::from_static("x-admin-token"),
+            HeaderValue::from_static("admin"),
+        )
+        .await
+        .text();
+    assert!(!raw.contains("eyes only"));
+
+    let without_key = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert_eq!(without_key.status_code(), StatusCode::UNAUTHORIZED);
+
+    let wrong_key = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .add_header(encryption.0.clone(), HeaderValue::from_static("nope"))
+        .await;
+    assert_eq!(wrong_key.status_code(), StatusCode::UNAUTHORIZED);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .add_header(encryption.0, encryption.1)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "eyes only");
+}
+
+#[tokio::test]
+async fn readiness_reports_a_reachable_backend() {
+    let response = server().get("/ready").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(
+        response.json::<Value>(),
+        json!({ "status": "ready", "circuit": "closed" })
+    );
+}
+
+#[tokio::test]
+async fn exported_channel_imports_under_a_fresh_id() {
+    let server = server();
+    let body = json!({
+        "text": "backup me",
+        "files": [{
+            "id": "f1",
+            "name": "notes.txt",
+            "mime_type": "text/plain",
+            "size": 5,
+            "data_base64": BASE64_ENGINE.encode(b"hello"),
+        }],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+
+    let export: Value = server
+        .get(&format!("/api/channels/{id}/export"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(export["format_version"], 1);
+
+    let imported = server.post("/api/channels/import").json(&export).await;
+    assert_eq!(imported.status_code(), StatusCode::CREATED);
+    let imported: Value = imported.json();
+    let new_id = imported["id"].as_str().unwrap();
+    assert_ne!(new_id, id);
+    assert_ne!(imported["password"], pass.as_str());
+
+    let (name, value) = password(imported["password"].as_str().unwrap());
+    let fetched: Value = server
+        .get(&format!("/api/channels/{new_id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "backup me");
+    assert_eq!(fetched["files"][0]["name"], "notes.txt");
+    assert_eq!(
+        fetched["files"][0]["data_base64"],
+        BASE64_ENGINE.encode(b"hello")
+    );
+}
+
+#[tokio::test]
+async fn requested_ttl_below_the_floor_is_rejected_or_clamped() {
+    let rejected = server()
+        .post("/api/channels")
+        .json(&json!({ "text": "brief", "ttl_seconds": 1 }))
+        .await;
+    assert_eq!(rejected.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        rejected.json::<Value>()["message"],
+        "ttl_seconds must be between 60 and 900"
+    );
+
+    let clamping = server_with(AppConfig {
+        ttl_policy: TtlPolicy::Clamp,
+        ..AppConfig::default()
+    });
+    let created: Value = clamping
+        .post("/api/channels")
+        .json(&json!({ "text": "brief", "ttl_seconds": 1 }))
+        .await
+        .json();
+    assert_eq!(created["ttl_seconds"], 60);
+}
+
+#[tokio::test]
+async fn preview_truncates_text_and_counts_files() {
+    let server = server_with(AppConfig {
+        preview_chars: 4,
+        ..AppConfig::default()
+    });
+    let body = json!({
+        "text": "naïve café",
+        "files": [{
+            "id": "f1",
+            "name": "notes.txt",
+            "mime_type": "text/plain",
+            "size": 5,
+            "data_base64": BASE64_ENGINE.encode(b"hello"),
+        }],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+
+    let preview: Value = server
+        .get(&format!("/api/channels/{id}/preview"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(preview["text"], "naïv");
+    assert_eq!(preview["truncated"], true);
+    assert_eq!(preview["file_count"], 1);
+    assert!(preview.get("files").is_none());
+}
+
+#[tokio::test]
+async fn typo_fields_are_rejected_only_in_strict_mode() {
+    let body = json!({ "txt": "typo" });
+
+    let lenient = server().post("/api/channels").json(&body).await;
+    assert_eq!(lenient.status_code(), StatusCode::CREATED);
+
+    let strict = server_with(AppConfig {
+        strict_json: true,
+        ..AppConfig::default()
+    })
+    .post("/api/channels")
+    .json(&body)
+    .await;
+    assert_eq!(strict.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(strict.json::<Value>()["message"], "unknown field `txt`");
+}
+
+#[tokio::test]
+async fn channels_compare_by_content() {
+    let server = server();
+    let file = |bytes: &[u8]| {
+        json!({
+            "id": "f1",
+            "name": "notes.txt",
+            "mime_type": "text/plain",
+            "size": bytes.len(),
+            "data_base64": BASE64_ENGINE.encode(bytes),
+        })
+    };
+    let (id, pass) = create(&server, json!({ "text": "a", "files": [file(b"x")] })).await;
+    let (copy, copy_pass) = create(&server, json!({ "text": "a", "files": [file(b"x")] })).await;
+    let (text_differs, text_pass) =
+        create(&server, json!({ "text": "b", "files": [file(b"x")] })).await;
+    let (files_differ, files_pass) =
+        create(&server, json!({ "text": "a", "files": [file(b"y")] })).await;
+
+    let compare = |other: String, other_pass: String| {
+        let (name, value) = password(&pass);
+        server
+            .get(&format!("/api/channels/{id}/equals/{other}"))
+            .add_header(name, value)
+            .add_header(
+                HeaderName::from_static("x-other-channel-password"),
+                HeaderValue::from_str(&other_pass).unwrap(),
+            )
+    };
+
+    let same: Value = compare(copy, copy_pass).await.json();
+    assert_eq!(
+        same,
+        json!({ "text_equal": true, "files_equal": true, "equal": true })
+    );
+
+    let text: Value = compare(text_differs, text_pass).await.json();
+    assert_eq!(
+        text,
+        json!({ "text_equal": false, "files_equal": true, "equal": false })
+    );
+
+    let files: Value = compare(files_differ.clone(), files_pass).await.json();
+    assert_eq!(
+        files,
+        json!({ "text_equal": true, "files_equal": false, "equal": false })
+    );
+
+    let unauthorized = compare(files_differ, "wrong".into()).await;
+    assert_eq!(unauthorized.status_code(), StatusCode::UNAUTHORIZED);
+    let missing = compare("missing".into(), String::new()).await;
+    assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(start_paused = true)]
+async fn sticky_channel_expires_on_schedule_despite_reads() {
+    let server = server();
+    let (sticky, sticky_pass) = create(
+        &server,
+        json!({ "text": "fixed", "ttl_seconds": 60, "sticky_ttl": true }),
+    )
+    .await;
+    let (polled, polled_pass) = create(&server, json!({ "text": "kept", "ttl_seconds": 60 })).await;
+    let fetch = |id: &str, pass: &str| {
+        let (name, value) = password(pass);
+        server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+    };
+
+    tokio::time::advance(Duration::from_secs(40)).await;
+    assert_eq!(
+        fetch(&sticky, &sticky_pass).await.status_code(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        fetch(&polled, &polled_pass).await.status_code(),
+        StatusCode::OK
+    );
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    assert_eq!(
+        fetch(&sticky, &sticky_pass).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        fetch(&polled, &polled_pass).await.status_code(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn channel_under_a_legacy_prefix_is_served_and_migrated() {
+    let legacy = StoredChannel {
+        password_hash: Some(hash_channel_password("old-pass")),
+        data: ChannelData {
+            text: "from before".into(),
+            ..ChannelData::default()
+        },
+        ..StoredChannel::default()
+    };
+    let storage = MemoryStorage::new();
+    storage
+        .set_ex(
+            "v1:channel:legacy01",
+            &serialize_channel(&legacy).unwrap(),
+            Duration::from_secs(600),
+        )
+        .await
+        .unwrap();
+    let state = AppState::new(
+        AppConfig {
+            key_prefix: "v2:".into(),
+            legacy_key_prefixes: vec!["v0:".into(), "v1:".into()],
+            admin_token: Some("admin".into()),
+            ..AppConfig::default()
+        },
+        Box::new(storage),
+    );
+    let server = TestServer::new(build_router(shared(state))).expect("test server");
+
+    let (name, value) = password("old-pass");
+    let fetched = server
+        .get("/api/channels/legacy01")
+        .add_header(name, value)
+        .await;
+    assert_eq!(fetched.status_code(), StatusCode::OK);
+    assert_eq!(fetched.json::<Value>()["text"], "from before");
+
+    // The admin view only looks under the current prefix.
+    let raw = server
+        .get("/admin/channels/legacy01/raw")
+        .add_header(
+            HeaderName::from_static("x-admin-token"),
+            HeaderValue::from_static("admin"),
+        )
+        .await;
+    assert_eq!(raw.status_code(), StatusCode::OK);
+    assert_eq!(raw.json::<Value>()["text"], "from before");
+}
+
+#[tokio::test]
+async fn oversized_files_array_is_rejected() {
+    let file = json!({
+        "id": "f", "name": "a", "mime_type": "text/plain", "size": 0, "data_base64": ""
+    });
+    let response = server()
+        .post("/api/channels")
+        .json(&json!({ "text": "", "files": vec![file; 100_000] }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn archive_bundles_text_and_files_as_tar() {
+    let server = server();
+    let file = |id: &str, name: &str, body: &[u8]| {
+        json!({
+            "id": id,
+            "name": name,
+            "mime_type": "text/plain",
+            "size": body.len(),
+            "data_base64": BASE64_ENGINE.encode(body),
+        })
+    };
+    let body = json!({
+        "text": "hello",
+        "files": [
+            file("f1", "../../etc/passwd", b"sneaky"),
+            file("f2", "notes.txt", b"one"),
+            file("f3", "notes.txt", b"two"),
+        ],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+
+    let response = server
+        .get(&format!("/api/channels/{id}/archive.tar"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.header("content-type"), "application/x-tar");
+    assert!(
+        response
+            .header("content-disposition")
+            .to_str()
+            .unwrap()
+            .starts_with("attachment;")
+    );
+
+    let bytes = response.as_bytes().to_vec();
+    let mut archive = tar::Archive::new(bytes.as_slice());
+    let mut entries = Vec::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut entry, &mut contents).unwrap();
+        entries.push((path, contents));
+    }
+    let expected = [
+        ("text.txt", "hello"),
+        ("passwd", "sneaky"),
+        ("notes.txt", "one"),
+        ("notes (2).txt", "two"),
+    ];
+    let expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|(path, contents)| (path.to_string(), contents.to_string()))
+        .collect();
+    assert_eq!(entries, expected);
+}
+
+#[tokio::test]
+async fn svg_downloads_are_forced_to_an_inert_attachment() {
+    let server = server();
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg"><script>alert(1)</script></svg>"#;
+    let body = json!({
+        "files": [{
+            "id": "f1",
+            "name": "logo.svg",
+            "mime_type": "image/svg+xml",
+            "size": svg.len(),
+            "data_base64": BASE64_ENGINE.encode(svg),
+        }],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+
+    let download = server
+        .get(&format!("/api/channels/{id}/files/f1?disposition=inline"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(download.status_code(), StatusCode::OK);
+    assert_eq!(download.header("content-type"), "application/octet-stream");
+    assert_eq!(download.header("x-content-type-options"), "nosniff");
+    assert!(
+        download
+            .header("content-disposition")
+            .to_str()
+            .unwrap()
+            .starts_with("attachment;")
+    );
+    assert_eq!(download.as_bytes().as_ref(), svg);
+}
+
+#[tokio::test]
+async fn password_query_is_honoured_only_when_enabled() {
+    let disabled = server();
+    let (id, pass) = create(&disabled, json!({ "text": "link" })).await;
+    let response = disabled.get(&format!("/api/channels/{id}?pw={pass}")).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let enabled = server_with(AppConfig {
+        allow_password_query: true,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&enabled, json!({ "text": "link" })).await;
+    let response = enabled.get(&format!("/api/channels/{id}?pw={pass}")).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.json::<Value>()["text"], "link");
+
+    // A header password takes precedence over the query.
+    let (name, value) = password("wrong");
+    let response = enabled
+        .get(&format!("/api/channels/{id}?pw={pass}"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn manifest_tracks_files_and_changes() {
+    let server = server();
+    let body = json!({
+        "text": "v1",
+        "files": [{
+            "id": "f1",
+            "name": "a.txt",
+            "mime_type": "text/plain",
+            "size": 5,
+            "data_base64": BASE64_ENGINE.encode(b"hello"),
+        }],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+    let manifest = || {
+        server
+            .get(&format!("/api/channels/{id}/manifest"))
+            .add_header(name.clone(), value.clone())
+    };
+
+    let before: Value = manifest().await.json();
+    assert_eq!(
+        before["files"],
+        json!([{
+            "id": "f1",
+            "name": "a.txt",
+            "size": 5,
+            "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            "downloads": 0,
+        }])
+    );
+
+    let updated = server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .json(&json!({ "text": "v2" }))
+        .await;
+    assert!(updated.status_code().is_success());
+
+    let after: Value = manifest().await.json();
+    assert_eq!(after["files"], json!([]));
+    assert_ne!(after["text_sha256"], before["text_sha256"]);
+    assert!(after["version"].as_u64() > before["version"].as_u64());
+}
+
+#[tokio::test]
+async fn only_immutable_channels_are_cacheable() {
+    let server = server();
+    let fetch = |id: String, pass: String| {
+        let (name, value) = password(&pass);
+        server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+    };
+
+    let (id, pass) = create(&server, json!({ "text": "fixed", "immutable": true })).await;
+    let first = fetch(id.clone(), pass.clone()).await;
+    let cache_control = first.header("cache-control");
+    let cache_control = cache_control.to_str().unwrap();
+    assert!(
+        cache_control.starts_with("private, max-age="),
+        "{cache_control}"
+    );
+    let etag = first.header("etag");
+    assert!(etag.to_str().unwrap().starts_with('"'));
+    assert_eq!(fetch(id, pass).await.header("etag"), etag);
+
+    let (id, pass) = create(&server, json!({ "text": "editable" })).await;
+    let mutable = fetch(id, pass).await;
+    assert_eq!(mutable.header("cache-control"), "no-cache");
+    assert!(mutable.maybe_header("etag").is_none());
+}
+
+#[tokio::test]
+async fn bulk_create_reports_each_item() {
+    let server = server();
+    let response = server
+        .post("/api/channels/bulk")
+        .json(&json!([
+            { "text": "one" },
+            { "text": "bad", "max_reads": 0 },
+            { "text": "three", "password": "chosen" },
+        ]))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let results: Vec<Value> = response.json();
+    assert_eq!(results.len(), 3);
+    assert!(results[1]["error"].is_string());
+    assert!(results[1].get("id").is_none());
+
+    for (result, text) in [(&results[0], "one"), (&results[2], "three")] {
+        let id = result["id"].as_str().unwrap();
+        let (name, value) = password(result["password"].as_str().unwrap());
+        let fetched: Value = server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+            .await
+            .json();
+        assert_eq!(fetched["text"], text);
+    }
+    assert_eq!(results[2]["password"], "chosen");
+
+    let too_many = server_with(AppConfig {
+        max_bulk_create: 2,
+        ..AppConfig::default()
+    })
+    .post("/api/channels/bulk")
+    .json(&json!([{}, {}, {}]))
+    .await;
+    assert_eq!(too_many.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn metadata_is_kept_out_of_the_content() {
+    let server = server();
+    let (id, pass) = create(
+        &server,
+        json!({ "text": "shared", "metadata": { "source": "ticket-42" } }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert!(fetched.get("metadata").is_none());
+
+    let metadata = server
+        .get(&format!("/api/channels/{id}/metadata"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(metadata.status_code(), StatusCode::OK);
+    assert_eq!(
+        metadata.json::<Value>(),
+        json!({ "metadata": { "source": "ticket-42" } })
+    );
+
+    let anonymous = server.get(&format!("/api/channels/{id}/metadata")).await;
+    assert_eq!(anonymous.status_code(), StatusCode::UNAUTHORIZED);
+
+    let oversized = server
+        .post("/api/channels")
+        .json(&json!({ "metadata": { "blob": "x".repeat(8 * 1024) } }))
+        .await;
+    assert_eq!(oversized.status_code(), StatusCode::BAD_REQUEST);
+}
+
+fn http_server(config: AppConfig) -> TestServer {
+    let state = shared(AppState::new(config, Box::new(MemoryStorage::new())));
+    let config = TestServerConfig::builder().http_transport().build();
+    TestServer::new_with_config(build_router(state), config).expect("test server")
+}
+
+#[tokio::test]
+async fn live_editors_converge_on_the_first_edit_per_version() {
+    let server = http_server(AppConfig::default());
+    let (id, pass) = create(&server, json!({ "text": "draft" })).await;
+    let (name, value) = password(&pass);
+    let connect = || {
+        server
+            .get_websocket(&format!("/api/channels/{id}/live"))
+            .add_header(name.clone(), value.clone())
+    };
+    let mut alice = connect().await.into_websocket().await;
+    let mut bob = connect().await.into_websocket().await;
+
+    let initial: Value = alice.receive_json().await;
+    assert_eq!(initial["type"], "state");
+    assert_eq!(initial["text"], "draft");
+    let version = initial["version"].as_u64().unwrap();
+    assert_eq!(bob.receive_json::<Value>().await, initial);
+
+    alice
+        .send_json(&json!({ "type": "update", "version": version, "text": "alice" }))
+        .await;
+    let accepted = json!({ "type": "state", "version": version + 1, "text": "alice" });
+    assert_eq!(alice.receive_json::<Value>().await, accepted);
+    assert_eq!(bob.receive_json::<Value>().await, accepted);
+
+    // Bob's edit was based on the old version, so he is told to rebase.
+    bob.send_json(&json!({ "type": "update", "version": version, "text": "bob" }))
+        .await;
+    let conflict: Value = bob.receive_json().await;
+    assert_eq!(conflict["type"], "conflict");
+    assert_eq!(conflict["text"], "alice");
+
+    bob.send_json(&json!({ "type": "update", "version": version + 1, "text": "alice+bob" }))
+        .await;
+    let merged = json!({ "type": "state", "version": version + 2, "text": "alice+bob" });
+    assert_eq!(bob.receive_json::<Value>().await, merged);
+    assert_eq!(alice.receive_json::<Value>().await, merged);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(fetched["text"], "alice+bob");
+}
+
+#[tokio::test]
+async fn flooding_live_client_is_closed_while_others_keep_editing() {
+    let server = http_server(AppConfig {
+        live_message_rate: 2,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&server, json!({ "text": "draft" })).await;
+    let (name, value) = password(&pass);
+    let connect = || {
+        server
+            .get_websocket(&format!("/api/channels/{id}/live"))
+            .add_header(name.clone(), value.clone())
+    };
+    let mut flooder = connect().await.into_websocket().await;
+    let mut editor = connect().await.into_websocket().await;
+    let initial: Value = flooder.receive_json().await;
+    let version = initial["version"].as_u64().unwrap();
+    assert_eq!(editor.receive_json::<Value>().await, initial);
+
+    for _ in 0..5 {
+        flooder
+            .send_json(&json!({ "type": "update", "version": 0, "text": "spam" }))
+            .await;
+    }
+    let close = loop {
+        match flooder.receive_message().await {
+            WsMessage::Close(frame) => break frame,
+            WsMessage::Text(_) => continue,
+            other => panic!("unexpected message: {other:?}"),
+        }
+    };
+    assert_eq!(close.map(|frame| u16::from(frame.code)), Some(1008));
+
+    editor
+        .send_json(&json!({ "type": "update", "version": version, "text": "calm" }))
+        .await;
+    let accepted: Value = editor.receive_json().await;
+    assert_eq!(accepted["type"], "state");
+    assert_eq!(accepted["text"], "calm");
+}
+
+#[tokio::test]
+async fn live_session_keeps_the_channel_alive_until_it_disconnects() {
+    let state = shared(AppState::new(
+        AppConfig {
+            channel_ttl: Duration::from_secs(1),
+            min_channel_ttl: Duration::from_secs(1),
+            live_ttl_refresh: Some(Duration::from_millis(200)),
+            ..AppConfig::default()
+        },
+        Box::new(MemoryStorage::new()),
+    ));
+    let config = TestServerConfig::builder().http_transport().build();
+    let server = TestServer::new_with_config(build_router(state.clone()), config).unwrap();
+    let (id, pass) = create(&server, json!({ "text": "draft" })).await;
+    let (name, value) = password(&pass);
+    let key = state.channel_key(&id);
+
+    let mut socket = server
+        .get_websocket(&format!("/api/channels/{id}/live"))
+        .add_header(name, value)
+        .await
+        .into_websocket()
+        .await;
+    let _: Value = socket.receive_json().await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(state.storage().exists(&key).await.unwrap());
+
+    socket.close().await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(!state.storage().exists(&key).await.unwrap());
+}
+
+#[tokio::test]
+async fn gzip_encoded_fetch_compresses_only_compressible_files() {
+    let server = server();
+    let text = "all work and no play ".repeat(200);
+    let png = [0x89, b'P', b'N', b'G', 1, 2, 3, 4];
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "",
+            "files": [
+                { "id": "txt", "name": "a.txt", "mime_type": "text/plain",
+                  "size": text.len(), "data_base64": BASE64_ENGINE.encode(&text) },
+                { "id": "png", "name": "a.png", "mime_type": "image/png",
+                  "size": png.len(), "data_base64": BASE64_ENGINE.encode(png) },
+            ],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}?encoded=gzip"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    let txt = &fetched["files"][0];
+    assert_eq!(txt["content_encoding"], "gzip");
+    let gzipped = BASE64_ENGINE
+        .decode(txt["data_base64"].as_str().unwrap())
+        .unwrap();
+    assert!(gzipped.len() < text.len());
+    let mut plain = String::new();
+    flate2::read::GzDecoder::new(gzipped.as_slice())
+        .read_to_string(&mut plain)
+        .unwrap();
+    assert_eq!(plain, text);
+
+    let png_file = &fetched["files"][1];
+    assert!(png_file.get("content_encoding").is_none());
+    assert_eq!(png_file["data_base64"], BASE64_ENGINE.encode(png));
+
+    let plain_fetch: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert!(plain_fetch["files"][0].get("content_encoding").is_none());
+    assert_eq!(
+        plain_fetch["files"][0]["data_base64"],
+        BASE64_ENGINE.encode(&text)
+    );
+}
+
+#[tokio::test]
+async fn plain_http_is_rejected_only_when_https_is_required() {
+    let forwarded_http = (
+        HeaderName::from_static("x-forwarded-proto"),
+        HeaderValue::from_static("http"),
+    );
+
+    let relaxed = server();
+    let response = relaxed
+        .get("/health")
+        .add_header(forwarded_http.0.clone(), forwarded_http.1.clone())
+        .await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("strict-transport-security").is_none());
+
+    let strict = server_with(AppConfig {
+        require_https: true,
+        ..AppConfig::default()
+    });
+    let rejected = strict
+        .get("/health")
+        .add_header(forwarded_http.0, forwarded_http.1)
+        .await;
+    assert_eq!(rejected.status_code(), StatusCode::BAD_REQUEST);
+
+    let allowed = strict
+        .get("/health")
+        .add_header(
+            HeaderName::from_static("x-forwarded-proto"),
+            HeaderValue::from_static("https"),
+        )
+        .await;
+    allowed.assert_status_ok();
+    assert_eq!(
+        allowed.header("strict-transport-security"),
+        "max-age=31536000"
+    );
+}
+
+/// Splits a `multipart/mixed` body into (headers, body) pairs.
+fn multipart_parts(content_type: &str, body: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let boundary = content_type
+        .split_once("boundary=")
+        .map(|(_, boundary)| format!("--{boundary}"))
+        .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let (_, rest) = body.split_once(&format!("{boundary}\r\n")).unwrap();
+    let (rest, _) = rest.rsplit_once(&format!("{boundary}--")).unwrap();
+    rest.split(&format!("{boundary}\r\n"))
+        .map(|part| {
+            let (head, data) = part.split_once("\r\n\r\n").unwrap();
+            let data = data.strip_suffix("\r\n").unwrap();
+            (head.to_ascii_lowercase(), data.as_bytes().to_vec())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn several_files_come_back_as_multipart_parts() {
+    let server = server();
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "",
+            "files": [
+                { "id": "a", "name": "a.txt", "mime_type": "text/plain",
+                  "size": 5, "data_base64": BASE64_ENGINE.encode("alpha") },
+                { "id": "b", "name": "b.json", "mime_type": "application/json",
+                  "size": 8, "data_base64": BASE64_ENGINE.encode("{\"b\":1}") },
+                { "id": "c", "name": "c.html", "mime_type": "text/html",
+                  "size": 3, "data_base64": BASE64_ENGINE.encode("<p>") },
+            ],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+
+    let response = server
+        .post(&format!("/api/channels/{id}/files/raw"))
+        .add_header(name.clone(), value.clone())
+        .json(&json!({ "file_ids": ["b", "nope", "a", "c"] }))
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("x-missing-file-ids"), "nope");
+    let content_type = response.header("content-type");
+    let content_type = content_type.to_str().unwrap();
+    assert!(content_type.starts_with("multipart/mixed; boundary="));
+
+    let parts = multipart_parts(content_type, response.as_bytes());
+    assert_eq!(parts.len(), 3);
+    let expected = [
+        ("b", "application/json", "b.json", "{\"b\":1}"),
+        ("a", "text/plain", "a.txt", "alpha"),
+        ("c", "application/octet-stream", "c.html", "<p>"),
+    ];
+    for ((head, data), (file_id, mime, file_name, content)) in parts.iter().zip(expected) {
+        assert!(
+            head.contains(&format!("content-type: {mime}\r\n")),
+            "{head}"
+        );
+        assert!(
+            head.contains(&format!("filename=\"{file_name}\"")),
+            "{head}"
+        );
+        assert!(head.contains(&format!("content-id: <{file_id}>")), "{head}");
+        assert_eq!(data, content.as_bytes());
+    }
+
+    let none = server
+        .post(&format!("/api/channels/{id}/files/raw"))
+        .add_header(name, value)
+        .json(&json!({ "file_ids": ["nope"] }))
+        .await;
+    assert_eq!(none.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn error_responses_carry_the_request_id() {
+    let server = server();
+    let response = server.get("/api/channels/missing").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let generated = response.header("x-request-id");
+    let body: Value = response.json();
+    assert_eq!(body["request_id"], generated.to_str().unwrap());
+    assert!(!generated.is_empty());
+
+    // A proxy-assigned id is kept, also for errors raised by middleware.
+    let response = server
+        .post("/api/channels")
+        .add_header(
+            HeaderName::from_static("x-request-id"),
+            HeaderValue::from_static("support-1234"),
+        )
+        .add_header(
+            HeaderName::from_static("x-content-sha256"),
+            HeaderValue::from_static("00"),
+        )
+        .json(&json!({ "text": "hi" }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.header("x-request-id"), "support-1234");
+    assert_eq!(response.json::<Value>()["request_id"], "support-1234");
+}
+
+#[tokio::test]
+async fn declared_oversize_bodies_are_refused_up_front() {
+    let server = server_with(AppConfig {
+        max_request_bytes: 1024,
+        ..AppConfig::default()
+    });
+    let response = server
+        .post("/api/channels")
+        .json(&json!({ "text": "x".repeat(2048) }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        response.json::<Value>()["message"],
+        "request body exceeds allowed size"
+    );
+
+    let declared = server
+        .post("/api/channels")
+        .add_header(
+            HeaderName::from_static("content-length"),
+            HeaderValue::from_static("209715200"),
+        )
+        .json(&json!({ "text": "small" }))
+        .await;
+    assert_eq!(declared.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    create(&server, json!({ "text": "fits" })).await;
+}
+
+#[tokio::test]
+async fn unnamed_uploads_get_default_names_when_enabled() {
+    let server = server_with(AppConfig {
+        auto_name_files: true,
+        ..AppConfig::default()
+    });
+    let png = BASE64_ENGINE.encode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "",
+            "files": [
+                { "id": "", "name": "", "mime_type": "image/png", "size": 16, "data_base64": png },
+                { "id": "own", "name": "mine.txt", "mime_type": "text/plain", "size": 0, "data_base64": "" },
+            ],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["files"][0]["name"], "file-1.png");
+    assert!(!fetched["files"][0]["id"].as_str().unwrap().is_empty());
+    assert_eq!(fetched["files"][1]["name"], "mine.txt");
+    assert_eq!(fetched["files"][1]["id"], "own");
+}
+
+#[tokio::test]
+async fn trailing_slashes_resolve_like_the_bare_path_unless_strict() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "slash" })).await;
+    let (name, value) = password(&pass);
+    let bare: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    let slashed: Value = server
+        .get(&format!("/api/channels/{id}/"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(slashed["text"], bare["text"]);
+    assert_eq!(slashed["id"], bare["id"]);
+
+    let strict = server_with(AppConfig {
+        strict_trailing_slash: true,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&strict, json!({ "text": "slash" })).await;
+    let (name, value) = password(&pass);
+    let response = strict
+        .get(&format!("/api/channels/{id}/"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn envelope_wraps_successes_and_errors_only_when_enabled() {
+    let flat = server();
+    let created: Value = flat
+        .post("/api/channels")
+        .json(&json!({ "text": "flat" }))
+        .await
+        .json();
+    assert!(created["id"].is_string());
+    let missing: Value = flat.get("/api/channels/missing").await.json();
+    assert_eq!(missing["message"], "channel not found");
+
+    let enveloped = server_with(AppConfig {
+        response_envelope: true,
+        ..AppConfig::default()
+    });
+    let response = enveloped
+        .post("/api/channels")
+        .json(&json!({ "text": "wrapped" }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+    let created: Value = response.json();
+    assert!(created.get("id").is_none());
+    assert!(created["data"]["id"].is_string());
+    assert!(created["data"]["password"].is_string());
+
+    let response = enveloped.get("/api/channels/missing").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let missing: Value = response.json();
+    assert_eq!(missing["error"]["message"], "channel not found");
+    assert!(missing.get("data").is_none());
+}
+
+#[tokio::test]
+async fn admin_search_finds_channels_by_metadata() {
+    let server = server_with(AppConfig {
+        admin_token: Some("admin".into()),
+        ..AppConfig::default()
+    });
+    let (wanted, _) = create(
+        &server,
+        json!({ "text": "a", "metadata": { "team": "blue", "ticket": "7" } }),
+    )
+    .await;
+    create(
+        &server,
+        json!({ "text": "b", "metadata": { "team": "red" } }),
+    )
+    .await;
+    create(&server, json!({ "text": "c" })).await;
+
+    let search = |query: &'static str| {
+        server
+            .get(&format!("/admin/channels/search?{query}"))
+            .add_header(
+                HeaderName::from_static("x-admin-token"),
+                HeaderValue::from_static("admin"),
+            )
+    };
+    let found: Value = search("key=team&value=blue").await.json();
+    assert_eq!(found["ids"], json!([wanted]));
+    assert_eq!(found["scanned"], 3);
+    assert!(found["next_cursor"].is_null());
+
+    let none: Value = search("key=team&value=green").await.json();
+    assert_eq!(none["ids"], json!([]));
+
+    let denied = server
+        .get("/admin/channels/search?key=team&value=blue")
+        .await;
+    assert_eq!(denied.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn raw_downloads_are_counted_per_file_in_the_manifest() {
+    let server = server();
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "",
+            "files": [
+                { "id": "a", "name": "a.txt", "mime_type": "text/plain",
+                  "size": 1, "data_base64": BASE64_ENGINE.encode("a") },
+                { "id": "b", "name": "b.txt", "mime_type": "text/plain",
+                  "size": 1, "data_base64": BASE64_ENGINE.encode("b") },
+            ],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+    for _ in 0..2 {
+        server
+            .get(&format!("/api/channels/{id}/files/a"))
+            .add_header(name.clone(), value.clone())
+            .await
+            .assert_status_ok();
+    }
+
+    // Counting happens in the background; give it a moment to land.
+    let mut manifest = Value::Null;
+    for _ in 0..50 {
+        manifest = server
+            .get(&format!("/api/channels/{id}/manifest"))
+            .add_header(name.clone(), value.clone())
+            .await
+            .json();
+        if manifest["files"][0]["downloads"] == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(manifest["files"][0]["downloads"], 2);
+    assert_eq!(manifest["files"][1]["downloads"], 0);
+}
+
+#[tokio::test]
+async fn creation_is_shed_while_storage_latency_is_high() {
+    let state = shared(AppState::new(
+        AppConfig {
+            shed_create_latency: Some(Duration::from_millis(100)),
+            ..AppConfig::default()
+        },
+        Box::new(MemoryStorage::new()),
+    ));
+    let server = TestServer::new(build_router(state.clone())).expect("test server");
+    let (id, pass) = create(&server, json!({ "text": "fast" })).await;
+    let (name, value) = password(&pass);
+    let export: Value = server
+        .get(&format!("/api/channels/{id}/export"))
+        .add_header(name, value)
+        .await
+        .json();
+
+    state.storage_latency().record(Duration::from_secs(5));
+    let shed = server
+        .post("/api/channels")
+        .json(&json!({ "text": "slow" }))
+        .await;
+    assert_eq!(shed.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    let bulk = server
+        .post("/api/channels/bulk")
+        .json(&json!([{ "text": "slow" }]))
+        .await;
+    assert_eq!(bulk.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    let imported = server.post("/api/channels/import").json(&export).await;
+    assert_eq!(imported.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Reads keep sampling the backend, so creation resumes once it is fast again.
+    while state.storage_latency().current().unwrap() > Duration::from_millis(100) {
+        server.get("/api/channels/missing").await;
+    }
+    create(&server, json!({ "text": "recovered" })).await;
+}
+
+#[tokio::test]
+async fn delta_returns_only_what_changed_since_the_manifest() {
+    let server = server();
+    let file = |id: &str, body: &str| {
+        json!({ "id": id, "name": format!("{id}.txt"), "mime_type": "text/plain",
+                "size": body.len(), "data_base64": BASE64_ENGINE.encode(body) })
+    };
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "same",
+            "files": [file("kept", "k"), file("edited", "v1"), file("dropped", "d")],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+    let manifest: Value = server
+        .get(&format!("/api/channels/{id}/manifest"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    let file_hashes: serde_json::Map<String, Value> = manifest["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| {
+            (
+                file["id"].as_str().unwrap().to_owned(),
+                file["sha256"].clone(),
+            )
+        })
+        .collect();
+
+    server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .json(&json!({
+            "text": "same",
+            "files": [file("kept", "k"), file("edited", "v2"), file("added", "a")],
+        }))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let delta: Value = server
+        .post(&format!("/api/channels/{id}/delta"))
+        .add_header(name, value)
+        .json(&json!({
+            "version": manifest["version"],
+            "file_hashes": file_hashes,
+            "text_sha256": manifest["text_sha256"],
+        }))
+        .await
+        .json();
+    let ids: Vec<&str> = delta["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["edited", "added"]);
+    assert_eq!(delta["files"][0]["data_base64"], BASE64_ENGINE.encode("v2"));
+    assert_eq!(delta["deleted"], json!(["dropped"]));
+    assert_eq!(delta["text_changed"], false);
+    assert!(delta.get("text").is_none());
+    assert_eq!(delta["version"], manifest["version"].as_u64().unwrap() + 1);
+}
+
+#[tokio::test]
+async fn malformed_password_headers_are_rejected_before_hashing() {
+    let server = server_with(AppConfig {
+        max_password_bytes: 16,
+        ..AppConfig::default()
+    });
+    let (id, _) = create(&server, json!({ "text": "secret" })).await;
+
+    for bad in ["tab\there", "seventeen-bytes!!"] {
+        let (name, value) = password(bad);
+        let response = server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.json::<Value>()["message"],
+            "channel password must be printable ASCII of at most 16 bytes"
+        );
+    }
+
+    let too_long = server
+        .post("/api/channels")
+        .json(&json!({ "text": "x", "password": "seventeen-bytes!!" }))
+        .await;
+    assert_eq!(too_long.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn fetched_channels_are_served_stale_while_storage_fails() {
+    let storage = MemoryStorage::new();
+    let state = shared(AppState::new(
+        AppConfig {
+            serve_stale_on_error: true,
+            ..AppConfig::default()
+        },
+        Box::new(storage.clone()),
+    ));
+    let server = TestServer::new(build_router(state)).expect("test server");
+    let (id, pass) = create(&server, json!({ "text": "cached" })).await;
+    let (unseen, _) = create(&server, json!({ "text": "never fetched" })).await;
+    let (name, value) = password(&pass);
+    let fresh = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert!(fresh.headers().get("x-served-stale").is_none());
+
+    storage.set_offline(true);
+    let stale = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert_eq!(stale.status_code(), StatusCode::OK);
+    assert_eq!(stale.json::<Value>()["text"], "cached");
+    assert_eq!(stale.headers()["x-served-stale"], "true");
+    assert!(stale.headers().contains_key("warning"));
+
+    let (wrong, bad) = password("nope");
+    let denied = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(wrong, bad)
+        .await;
+    assert_eq!(denied.status_code(), StatusCode::UNAUTHORIZED);
+    let missing = server.get(&format!("/api/channels/{unseen}")).await;
+    assert_eq!(missing.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    let write = server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .json(&json!({ "text": "lost" }))
+        .await;
+    assert_eq!(write.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn fetch_reports_total_size_and_file_count() {
+    let server = server();
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "héllo",
+            "files": [
+                { "id": "a", "name": "a.bin", "mime_type": "application/octet-stream",
+                  "size": 3, "data_base64": BASE64_ENGINE.encode([1, 2, 3]) },
+                { "id": "b", "name": "b.txt", "mime_type": "text/plain",
+                  "size": 4, "data_base64": BASE64_ENGINE.encode("four") },
+            ],
+        }),
+    )
+    .await;
+    let (name, value) = password(&pass);
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}?include_files=false"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["total_bytes"], "héllo".len() + 3 + 4);
+    assert_eq!(fetched["file_count"], 2);
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn gzip_encoded_bodies_are_inflated_within_the_size_limit() {
+    let server = server_with(AppConfig {
+        max_request_bytes: 64 * 1024,
+        ..AppConfig::default()
+    });
+    let paste = "a large paste ".repeat(1000);
+    let body = serde_json::to_vec(&json!({ "text": paste })).unwrap();
+    let created = server
+        .post("/api/channels")
+        .content_type("application/json")
+        .add_header(
+            HeaderName::from_static("content-encoding"),
+            HeaderValue::from_static("gzip"),
+        )
+        .bytes(gzip(&body).into())
+        .await;
+    assert_eq!(created.status_code(), StatusCode::CREATED);
+    let created: Value = created.json();
+    let (name, value) = password(created["password"].as_str().unwrap());
+    let fetched: Value = server
+        .get(&format!(
+            "/api/channels/{}",
+            created["id"].as_str().unwrap()
+        ))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], paste);
+
+    // A kilobyte on the wire that would inflate to a megabyte.
+    let bomb = serde_json::to_vec(&json!({ "text": "0".repeat(1024 * 1024) })).unwrap();
+    let bomb = gzip(&bomb);
+    assert!(bomb.len() < 64 * 1024);
+    let refused = server
+        .post("/api/channels")
+        .content_type("application/json")
+        .add_header(
+            HeaderName::from_static("content-encoding"),
+            HeaderValue::from_static("gzip"),
+        )
+        .bytes(bomb.into())
+        .await;
+    assert_eq!(refused.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn view_page_escapes_channel_content() {
+    let server = server_with(AppConfig {
+        allow_password_query: true,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(
+        &server,
+        json!({
+            "text": "<script>alert('x')</script>",
+            "files": [{ "id": "f1", "name": "\"><b>.txt", "mime_type": "text/plain",
+                        "size": 2, "data_base64": BASE64_ENGINE.encode("hi") }],
+        }),
+    )
+    .await;
+    let response = server
+        .get(&format!("/api/channels/{id}/view"))
+        .add_query_param("pw", &pass)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(
+        response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html")
+    );
+    assert!(response.headers().contains_key("content-security-policy"));
+    let page = response.text();
+    assert!(page.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("&quot;&gt;&lt;b&gt;.txt"));
+
+    // The page's own links work with the same query password.
+    let link = format!("/api/channels/{id}/files/f1");
+    server
+        .get(&link)
+        .add_query_param("pw", &pass)
+        .await
+        .assert_status_ok();
+
+    let anonymous = server.get(&format!("/api/channels/{id}/view")).await;
+    assert_eq!(anonymous.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn writes_from_clients_below_the_minimum_version_are_refused() {
+    let server = server_with(AppConfig {
+        min_client_version: Some("2.3".parse().unwrap()),
+        ..AppConfig::default()
+    });
+    let client = |version: &'static str| {
+        (
+            HeaderName::from_static("x-client-version"),
+            HeaderValue::from_static(version),
+        )
+    };
+
+    let (name, value) = client("2.2.9");
+    let old = server
+        .post("/api/channels")
+        .add_header(name, value)
+        .json(&json!({ "text": "hi" }))
+        .await;
+    assert_eq!(old.status_code(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(
+        old.json::<Value>()["message"],
+        "client version 2.3 or newer is required"
+    );
+    let missing = server
+        .post("/api/channels")
+        .json(&json!({ "text": "hi" }))
+        .await;
+    assert_eq!(missing.status_code(), StatusCode::UPGRADE_REQUIRED);
+
+    let (name, value) = client("2.3.0");
+    let created = server
+        .post("/api/channels")
+        .add_header(name, value)
+        .json(&json!({ "text": "hi" }))
+        .await;
+    assert_eq!(created.status_code(), StatusCode::CREATED);
+    let created: Value = created.json();
+
+    // Reads stay open to every client.
+    let (name, value) = password(created["password"].as_str().unwrap());
+    server
+        .get(&format!(
+            "/api/channels/{}",
+            created["id"].as_str().unwrap()
+        ))
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn view_urls_use_an_allowlisted_base_url() {
+    let server = server_with(AppConfig {
+        public_base_url: Some("https://share.example.com/".parse().unwrap()),
+        public_base_url_allowlist: vec!["https://links.example.org/lynkc".parse().unwrap()],
+        ..AppConfig::default()
+    });
+    let base_url = |value: &str| {
+        (
+            HeaderName::from_static("x-base-url"),
+            HeaderValue::from_str(value).unwrap(),
+        )
+    };
+
+    let default: Value = server
+        .post("/api/channels")
+        .json(&json!({ "text": "hi" }))
+        .await
+        .json();
+    let id = default["id"].as_str().unwrap();
+    assert_eq!(
+        default["view_url"],
+        format!("https://share.example.com/api/channels/{id}/view")
+    );
+
+    let (name, value) = base_url("https://links.example.org/lynkc/");
+    let selected: Value = server
+        .post("/api/channels")
+        .add_header(name, value)
+        .json(&json!({ "text": "hi" }))
+        .await
+        .json();
+    let id = selected["id"].as_str().unwrap();
+    assert_eq!(
+        selected["view_url"],
+        format!("https://links.example.org/lynkc/api/channels/{id}/view")
+    );
+
+    for off_list in ["https://evil.example.net", "javascript:alert(1)"] {
+        let (name, value) = base_url(off_list);
+        let rejected = server
+            .post("/api/channels")
+            .add_header(name, value)
+            .json(&json!({ "text": "hi" }))
+            .await;
+        assert_eq!(rejected.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    assert!("ftp://share.example.com".parse::<PublicBaseUrl>().is_err());
+    assert!(
+        "https://user@share.example.com"
+            .parse::<PublicBaseUrl>()
+            .is_err()
+    );
+    assert!(
+        "https://share.example.com/?next=x"
+            .parse::<PublicBaseUrl>()
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn password_can_be_checked_without_reading_the_channel() {
+    let server = server();
+    let (id, secret) = create(&server, json!({ "text": "hi", "max_reads": 1 })).await;
+    let url = format!("/api/channels/{id}/auth");
+
+    let (name, value) = password(&secret);
+    let correct = server.post(&url).add_header(name, value).await;
+    assert_eq!(correct.status_code(), StatusCode::NO_CONTENT);
+    assert!(correct.as_bytes().is_empty());
+
+    let (name, value) = password("wrong");
+    let wrong = server.post(&url).add_header(name, value).await;
+    assert_eq!(wrong.status_code(), StatusCode::UNAUTHORIZED);
+    let unauthenticated = server.post(&url).await;
+    assert_eq!(unauthenticated.status_code(), StatusCode::UNAUTHORIZED);
+
+    let (name, value) = password(&secret);
+    let missing = server
+        .post("/api/channels/doesnotexist/auth")
+        .add_header(name, value)
+        .await;
+    assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+
+    // The single allowed read is still there.
+    let (name, value) = password(&secret);
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn live_file_requests_stream_the_bytes_in_binary_frames() {
+    let server = http_server(AppConfig {
+        live_file_chunk_bytes: Some(4),
+        live_max_file_bytes: 16,
+        ..AppConfig::default()
+    });
+    let contents = b"0123456789abcdef";
+    let file = |id: &str, bytes: &[u8]| {
+        json!({
+            "id": id,
+            "name": format!("{id}.bin"),
+            "mime_type": "application/octet-stream",
+            "size": bytes.len(),
+            "data_base64": BASE64_ENGINE.encode(bytes),
+        })
+    };
+    let body = json!({
+        "text": "draft",
+        "files": [file("small", contents), file("big", &[0; 17])],
+    });
+    let (id, pass) = create(&server, body).await;
+    let (name, value) = password(&pass);
+    let mut socket = server
+        .get_websocket(&format!("/api/channels/{id}/live"))
+        .add_header(name, value)
+        .await
+        .into_websocket()
+        .await;
+    assert_eq!(socket.receive_json::<Value>().await["type"], "state");
+
+    socket
+        .send_json(&json!({ "type": "get_file", "id": "small" }))
+        .await;
+    let mut received = Vec::new();
+    let complete = loop {
+        match socket.receive_message().await {
+            WsMessage::Binary(chunk) => {
+                assert!(chunk.len() <= 4);
+                received.extend_from_slice(&chunk);
+            }
+            WsMessage::Text(text) => break serde_json::from_str::<Value>(&text).unwrap(),
+            other => panic!("unexpected message: {other:?}"),
+        }
+    };
+    assert_eq!(received, contents);
+    assert_eq!(
+        complete,
+        json!({ "type": "file_complete", "id": "small", "size": 16 })
+    );
+
+    for missing in ["big", "nope"] {
+        socket
+            .send_json(&json!({ "type": "get_file", "id": missing }))
+            .await;
+        assert_eq!(socket.receive_json::<Value>().await["type"], "error");
+    }
+}
+
+#[tokio::test]
+async fn required_content_is_enforced_on_create_and_update() {
+    let server = server_with(AppConfig {
+        content_requirement: ContentRequirement::Both,
+        ..AppConfig::default()
+    });
+    let file = json!({
+        "id": "f1",
+        "name": "notes.txt",
+        "mime_type": "text/plain",
+        "size": 5,
+        "data_base64": BASE64_ENGINE.encode(b"hello"),
+    });
+
+    let no_file = server
+        .post("/api/channels")
+        .json(&json!({ "text": "hi" }))
+        .await;
+    assert_eq!(no_file.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        no_file.json::<Value>()["message"],
+        "channel must include at least one file"
+    );
+
+    let (id, pass) = create(&server, json!({ "text": "hi", "files": [file] })).await;
+    let (name, value) = password(&pass);
+    let blank = server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .json(&json!({ "text": "  ", "files": [file] }))
+        .await;
+    assert_eq!(blank.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        blank.json::<Value>()["message"],
+        "channel text must not be empty"
+    );
+}
+
+#[tokio::test]
+async fn admins_list_channels_by_tag() {
+    let server = server_with(AppConfig {
+        admin_token: Some("admin".into()),
+        max_channel_tags: 2,
+        ..AppConfig::default()
+    });
+    let (first, pass) = create(
+        &server,
+        json!({ "text": "a", "tags": ["team-a", "q3", "team-a"] }),
+    )
+    .await;
+    let (second, _) = create(&server, json!({ "text": "b", "tags": ["team-a"] })).await;
+    create(&server, json!({ "text": "c", "tags": ["team-b"] })).await;
+    let bulk: Vec<Value> = server
+        .post("/api/channels/bulk")
+        .json(&json!([{ "text": "d", "tags": ["team-a"] }]))
+        .await
+        .json();
+    let bulk = bulk[0]["id"].as_str().unwrap().to_owned();
+
+    let list = |tag: &str| {
+        server
+            .get(&format!("/admin/tags/{tag}/channels"))
+            .add_header(
+                HeaderName::from_static("x-admin-token"),
+                HeaderValue::from_static("admin"),
+            )
+    };
+    let mut expected = vec![first.clone(), second, bulk];
+    expected.sort();
+    assert_eq!(list("team-a").await.json::<Value>()["ids"], json!(expected));
+    assert_eq!(list("q3").await.json::<Value>()["ids"], json!([first]));
+    assert_eq!(list("nobody").await.json::<Value>()["ids"], json!([]));
+
+    // Editors see the tags, readers never do.
+    let (name, value) = password(&pass);
+    let metadata: Value = server
+        .get(&format!("/api/channels/{first}/metadata"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(metadata["tags"], json!(["q3", "team-a"]));
+    let fetched: Value = server
+        .get(&format!("/api/channels/{first}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert!(fetched.get("tags").is_none());
+
+    for tags in [json!(["a", "b", "c"]), json!(["no spaces"]), json!([""])] {
+        let rejected = server
+            .post("/api/channels")
+            .json(&json!({ "text": "x", "tags": tags }))
+            .await;
+        assert_eq!(rejected.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn older_storage_formats_are_upgraded_when_read() {
+    let storage = MemoryStorage::new();
+    let legacy = format!(
+        r#"{{"password_hash":"{}","text":"from before","files":[]}}"#,
+        hash_channel_password("old-pass")
+    );
+    let ttl = Duration::from_secs(600);
+    storage
+        .set_ex("channel:unversioned", &legacy, ttl)
+        .await
+        .unwrap();
+    storage
+        .set_ex("channel:plain", "bare text", ttl)
+        .await
+        .unwrap();
+    let state = AppState::new(
+        AppConfig {
+            upgrade_format_on_read: true,
+            ..AppConfig::default()
+        },
+        Box::new(storage.clone()),
+    );
+    let server = TestServer::new(build_router(shared(state))).expect("test server");
+
+    let (name, value) = password("old-pass");
+    let fetched: Value = server
+        .get("/api/channels/unversioned")
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "from before");
+    let plain: Value = server.get("/api/channels/plain").await.json();
+    assert_eq!(plain["text"], "bare text");
+
+    for key in ["channel:unversioned", "channel:plain"] {
+        let stored: Value =
+            serde_json::from_str(&storage.get(key).await.unwrap().unwrap()).unwrap();
+        assert_eq!(stored["format"], CHANNEL_FORMAT_VERSION);
+        assert!(storage.ttl(key).await.unwrap() > 0);
+    }
+}
+
+#[tokio::test]
+async fn strict_deserialize_refuses_bare_text_records() {
+    let storage = MemoryStorage::new();
+    let ttl = Duration::from_secs(600);
+    storage
+        .set_ex("channel:plain", "bare text", ttl)
+        .await
+        .unwrap();
+    let server_for = |strict_deserialize| {
+        let state = AppState::new(
+            AppConfig {
+                strict_deserialize,
+                ..AppConfig::default()
+            },
+            Box::new(storage.clone()),
+        );
+        TestServer::new(build_router(shared(state))).expect("test server")
+    };
+
+    let lenient = server_for(false);
+    let plain: Value = lenient.get("/api/channels/plain").await.json();
+    assert_eq!(plain["text"], "bare text");
+
+    let strict = server_for(true);
+    strict
+        .get("/api/channels/plain")
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    let (id, pass) = create(&strict, json!({ "text": "current format" })).await;
+    let (name, value) = password(&pass);
+    let fetched: Value = strict
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "current format");
+}
+
+#[tokio::test]
+async fn protected_file_needs_its_own_password() {
+    let server = server_with(AppConfig {
+        admin_token: Some("admin".into()),
+        ..AppConfig::default()
+    });
+    let file = |id: &str, bytes: &[u8]| {
+        json!({
+            "id": id,
+            "name": format!("{id}.txt"),
+            "mime_type": "text/plain",
+            "size": bytes.len(),
+            "data_base64": BASE64_ENGINE.encode(bytes),
+        })
+    };
+    let mut secret = file("creds", b"hunter2");
+    secret["password"] = json!("file-pass");
+    let (id, pass) = create(
+        &server,
+        json!({ "text": "notes", "files": [file("notes", b"public"), secret] }),
+    )
+    .await;
+    let file_password = |value: &'static str| {
+        (
+            HeaderName::from_static("x-file-password"),
+            HeaderValue::from_static(value),
+        )
+    };
+    let (name, value) = password(&pass);
+
+    let raw = server
+        .get(&format!("/admin/channels/{id}/raw"))
+        .add_header(
+            HeaderName::from_static("x-admin-token"),
+            HeaderValue::from_static("admin"),
+        )
+        .await
+        .text();
+    assert!(!raw.contains("file-pass"));
+
+    let download = |file_id: &str| {
+        server
+            .get(&format!("/api/channels/{id}/files/{file_id}"))
+            .add_header(name.clone(), value.clone())
+    };
+    assert_eq!(download("notes").await.as_bytes().as_ref(), b"public");
+    assert_eq!(
+        download("creds").await.status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    let (file_name, file_value) = file_password("wrong");
+    let wrong = download("creds").add_header(file_name, file_value).await;
+    assert_eq!(wrong.status_code(), StatusCode::UNAUTHORIZED);
+    let (file_name, file_value) = file_password("file-pass");
+    let unlocked = download("creds").add_header(file_name, file_value).await;
+    assert_eq!(unlocked.as_bytes().as_ref(), b"hunter2");
+
+    let fetch = |query: &str| {
+        server
+            .get(&format!("/api/channels/{id}{query}"))
+            .add_header(name.clone(), value.clone())
+    };
+    assert_eq!(fetch("").await.status_code(), StatusCode::UNAUTHORIZED);
+    let listed: Value = fetch("?include_files=false").await.json();
+    assert_eq!(listed["files"][1]["password_protected"], true);
+    assert!(listed["files"][0].get("password_protected").is_none());
+    let (file_name, file_value) = file_password("file-pass");
+    let full: Value = fetch("").add_header(file_name, file_value).await.json();
+    assert_eq!(
+        full["files"][1]["data_base64"],
+        BASE64_ENGINE.encode(b"hunter2")
+    );
+}
+
+/// Accepts webhook posts and passes each JSON body on, answering `status`.
+async fn mock_sink(status: u16) -> (String, tokio::sync::mpsc::UnboundedReceiver<Value>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/events", listener.local_addr().unwrap());
+    let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 1024];
+            let body = loop {
+                let read = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length").then_some(value)
+                        })
+                        .unwrap()
+                        .trim()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length || read == 0 {
+                        break body.to_owned();
+                    }
+                }
+            };
+            let reply = format!("HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\n\r\n");
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            let _ = sender.send(serde_json::from_str(&body).unwrap());
+        }
+    });
+    (url, events)
+}
+
+#[tokio::test]
+async fn channel_accesses_are_sent_to_the_access_log_sink() {
+    let (url, mut events) = mock_sink(204).await;
+    let server = server_with(AppConfig {
+        access_log_sink: Some(url.parse().unwrap()),
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&server, json!({ "text": "top secret" })).await;
+    let (name, value) = password(&pass);
+    let denied = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), HeaderValue::from_static("wrong"))
+        .add_header(
+            HeaderName::from_static("x-request-id"),
+            HeaderValue::from_static("req-42"),
+        )
+        .await;
+    assert_eq!(denied.status_code(), StatusCode::UNAUTHORIZED);
+    server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .json(&json!({ "text": "still secret" }))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server.get("/health").await.assert_status_ok();
+
+    let mut received = Vec::new();
+    for _ in 0..3 {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("event delivered")
+            .unwrap();
+        received.push(event);
+    }
+    let summary: Vec<_> = received
+        .iter()
+        .map(|event| {
+            (
+                event["action"].as_str().unwrap(),
+                event["id"].as_str().unwrap(),
+                event["result"].as_str().unwrap(),
+                event["status"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("create", id.as_str(), "success", 201),
+            ("read", id.as_str(), "failure", 401),
+            ("update", id.as_str(), "success", 204),
+        ]
+    );
+    assert_eq!(received[1]["request_id"], "req-42");
+    for event in &received {
+        let raw = event.to_string();
+        for secret in ["secret", pass.as_str(), "wrong"] {
+            assert!(!raw.contains(secret), "{secret} leaked into {raw}");
+        }
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), events.recv())
+            .await
+            .is_err(),
+        "health checks are not channel accesses"
+    );
+}
+
+#[tokio::test]
+async fn failing_access_log_sinks_do_not_fail_requests() {
+    let (url, mut events) = mock_sink(500).await;
+    let unreachable = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/events", listener.local_addr().unwrap())
+    };
+    for sink in [url, unreachable] {
+        let server = server_with(AppConfig {
+            access_log_sink: Some(sink.parse().unwrap()),
+            access_log_buffer: 1,
+            ..AppConfig::default()
+        });
+        for _ in 0..5 {
+            let (id, pass) = create(&server, json!({ "text": "hello" })).await;
+            let (name, value) = password(&pass);
+            server
+                .get(&format!("/api/channels/{id}"))
+                .add_header(name, value)
+                .await
+                .assert_status_ok();
+        }
+        let ready: Value = server.get("/ready").await.json();
+        assert!(ready["access_log_dropped"].as_u64().is_some());
+    }
+    assert!(events.recv().await.is_some());
+}
+
+#[tokio::test]
+async fn channels_can_be_deleted_with_their_password() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "pasted by mistake" })).await;
+    let url = format!("/api/channels/{id}");
+    let (name, value) = password(&pass);
+
+    server
+        .delete(&url)
+        .add_header(name.clone(), HeaderValue::from_static("wrong"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .delete(&url)
+        .add_header(name.clone(), value.clone())
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .get(&url)
+        .add_header(name.clone(), value.clone())
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .delete(&url)
+        .add_header(name, value)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let (id, pass) = create(&server, json!({ "text": "keep", "immutable": true })).await;
+    let (name, value) = password(&pass);
+    server
+        .delete(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn creates_wait_for_the_approval_hook() {
+    let approver = |url: &str, timeout_ms| {
+        server_with(AppConfig {
+            create_approval_url: HttpTarget::parse(url),
+            create_approval_timeout: Duration::from_millis(timeout_ms),
+            ..AppConfig::default()
+        })
+    };
+    let file = json!({
+        "id": "f1",
+        "name": "cat.png",
+        "mime_type": "image/png",
+        "size": 3,
+        "data_base64": BASE64_ENGINE.encode(b"png"),
+    });
+    let payload = json!({ "text": "moderated", "files": [file] });
+
+    let (url, mut asked) = mock_sink(200).await;
+    let server = approver(&url, 2000);
+    let (id, pass) = create(&server, payload.clone()).await;
+    let question = asked.recv().await.unwrap();
+    assert_eq!(question["size"], 12);
+    assert_eq!(question["file_count"], 1);
+    assert_eq!(question["mime_types"], json!(["image/png"]));
+    assert!(!question.to_string().contains("moderated"));
+    let (name, value) = password(&pass);
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+
+    let (url, mut asked) = mock_sink(451).await;
+    let server = approver(&url, 2000);
+    let denied = server.post("/api/channels").json(&payload).await;
+    assert_eq!(denied.status_code(), StatusCode::FORBIDDEN);
+    assert!(asked.recv().await.is_some());
+    let bulk: Value = server
+        .post("/api/channels/bulk")
+        .json(&json!([payload.clone()]))
+        .await
+        .json();
+    assert_eq!(bulk[0]["error"], "channel creation was not approved");
+
+    // An approver that accepts the connection but never answers.
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/approve", silent.local_addr().unwrap());
+    let server = approver(&url, 100);
+    let timed_out = server.post("/api/channels").json(&payload).await;
+    assert_eq!(timed_out.status_code(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn empty_channels_can_be_fetched_as_no_content() {
+    let server = server();
+    let (empty, empty_pass) = create(&server, json!({ "text": "" })).await;
+    let (full, full_pass) = create(&server, json!({ "text": "data" })).await;
+
+    let (name, value) = password(&empty_pass);
+    let waiting = server
+        .get(&format!("/api/channels/{empty}"))
+        .add_query_param("empty204", true)
+        .add_header(name.clone(), value.clone())
+        .await;
+    assert_eq!(waiting.status_code(), StatusCode::NO_CONTENT);
+    assert!(waiting.as_bytes().is_empty());
+    let ttl: i64 = waiting
+        .header("x-channel-ttl")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(ttl > 0);
+
+    let plain = server
+        .get(&format!("/api/channels/{empty}"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(plain.status_code(), StatusCode::OK);
+    assert_eq!(plain.json::<Value>()["text"], "");
+
+    let (name, value) = password(&full_pass);
+    let ready = server
+        .get(&format!("/api/channels/{full}"))
+        .add_query_param("empty204", true)
+        .add_header(name, value)
+        .await;
+    assert_eq!(ready.status_code(), StatusCode::OK);
+    assert_eq!(ready.json::<Value>()["text"], "data");
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_channels_are_readable_once_within_the_grace_window() {
+    let server = server_with(AppConfig {
+        expired_grace: Some(Duration::from_secs(30)),
+        ..AppConfig::default()
+    });
+    let (early, early_pass) = create(&server, json!({ "text": "just", "ttl_seconds": 60 })).await;
+    let (late, late_pass) = create(&server, json!({ "text": "too late", "ttl_seconds": 60 })).await;
+    let (limited, limited_pass) = create(
+        &server,
+        json!({ "text": "budget", "ttl_seconds": 60, "max_reads": 5 }),
+    )
+    .await;
+    let fetch = |id: &str, pass: &str| {
+        let (name, value) = password(pass);
+        server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+    };
+
+    tokio::time::advance(Duration::from_secs(70)).await;
+    assert_eq!(
+        fetch(&early, "wrong").await.status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+    let graced = fetch(&early, &early_pass).await;
+    assert_eq!(graced.status_code(), StatusCode::OK);
+    assert_eq!(graced.header("x-expired-grace"), "true");
+    assert_eq!(graced.json::<Value>()["text"], "just");
+    assert_eq!(
+        fetch(&early, &early_pass).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        fetch(&limited, &limited_pass).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    assert_eq!(
+        fetch(&late, &late_pass).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn reads_can_leave_the_ttl_alone() {
+    for (refresh_ttl_on_read, second_ttl) in [(true, 840), (false, 780)] {
+        let server = server_with(AppConfig {
+            refresh_ttl_on_read,
+            ..AppConfig::default()
+        });
+        let (id, pass) = create(&server, json!({ "text": "viewed" })).await;
+        let (name, value) = password(&pass);
+        let fetch = || {
+            server
+                .get(&format!("/api/channels/{id}"))
+                .add_header(name.clone(), value.clone())
+        };
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let first: Value = fetch().await.json();
+        assert_eq!(first["ttl_seconds"], 840);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let second: Value = fetch().await.json();
+        assert_eq!(second["ttl_seconds"], second_ttl, "{refresh_ttl_on_read}");
+    }
+}
+
+#[derive(Clone, Default)]
+struct RecordingSink(std::sync::Arc<std::sync::Mutex<Vec<(&'static str, String)>>>);
+
+impl RecordingSink {
+    fn record(&self, event: &'static str, id: &str) {
+        self.0.lock().unwrap().push((event, id.to_owned()));
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn on_created(&self, id: &str) {
+        self.record("created", id);
+    }
+
+    fn on_read(&self, id: &str) {
+        self.record("read", id);
+    }
+
+    fn on_updated(&self, id: &str) {
+        self.record("updated", id);
+    }
+
+    fn on_deleted(&self, id: &str) {
+        self.record("deleted", id);
+    }
+
+    fn on_expired(&self, id: &str) {
+        self.record("expired", id);
+    }
+}
+
+#[tokio::test]
+async fn event_sinks_hear_about_each_step_of_a_channel_lifecycle() {
+    let sink = RecordingSink::default();
+    let state = AppState::new(
+        AppConfig {
+            admin_token: Some("admin".into()),
+            ..AppConfig::default()
+        },
+        Box::new(MemoryStorage::new()),
+    )
+    .with_event_sinks(EventSinks::new(vec![Box::new(sink.clone())]));
+    let server = TestServer::new(build_router(shared(state))).expect("test server");
+
+    let (id, pass) = create(&server, json!({ "text": "hello" })).await;
+    let (name, value) = password(&pass);
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .assert_status_ok();
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), HeaderValue::from_static("wrong"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .put(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .json(&json!({ "text": "bye" }))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .post("/admin/evict")
+        .add_header(
+            HeaderName::from_static("x-admin-token"),
+            HeaderValue::from_static("admin"),
+        )
+        .json(&json!({ "count": 1 }))
+        .await
+        .assert_status_ok();
+
+    let events = sink.0.lock().unwrap().clone();
+    let expected: Vec<_> = ["created", "read", "updated", "deleted"]
+        .into_iter()
+        .map(|event| (event, id.clone()))
+        .collect();
+    assert_eq!(events, expected);
+}
+
+#[tokio::test]
+async fn strict_file_id_policy_accepts_only_uuids() {
+    let server = server_with(AppConfig {
+        file_id_policy: FileIdPolicy::Uuid,
+        ..AppConfig::default()
+    });
+    let file = |id: &str| {
+        json!({
+            "id": id,
+            "name": "a.txt",
+            "mime_type": "text/plain",
+            "size": 2,
+            "data_base64": BASE64_ENGINE.encode(b"hi"),
+        })
+    };
+    let uuid = "6f1c2a9e-0d4b-4c8e-9a57-3b2f1e0d9c8a";
+    let (id, pass) = create(&server, json!({ "files": [file(uuid)] })).await;
+    let (name, value) = password(&pass);
+    server
+        .delete(&format!("/api/channels/{id}/files/{uuid}"))
+        .add_header(name, value)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let rejected = server
+        .post("/api/channels")
+        .json(&json!({ "files": [file("notes")] }))
+        .await;
+    assert_eq!(rejected.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        rejected.json::<Value>()["message"],
+        "file id \"notes\" is not a UUID"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn password_tokens_reveal_the_password_once_before_expiring() {
+    let server = server_with(AppConfig {
+        password_token_ttl: Some(Duration::from_secs(60)),
+        ..AppConfig::default()
+    });
+    let create_with_token = || async {
+        let created: Value = server
+            .post("/api/channels")
+            .json(&json!({ "text": "secret" }))
+            .await
+            .json();
+        assert!(created.get("password").is_none());
+        (
+            created["id"].as_str().unwrap().to_owned(),
+            created["password_token"].as_str().unwrap().to_owned(),
+        )
+    };
+    let redeem =
+        |id: &str, token: &str| server.get(&format!("/api/channels/{id}/password?token={token}"));
+
+    let (id, token) = create_with_token().await;
+    assert_eq!(
+        redeem(&id, "not-the-token").await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+    let revealed = redeem(&id, &token).await;
+    assert_eq!(revealed.status_code(), StatusCode::OK);
+    assert_eq!(revealed.header("cache-control"), "no-store");
+    let pass = revealed.json::<Value>()["password"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let (name, value) = password(&pass);
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .json();
+    assert_eq!(fetched["text"], "secret");
+    assert_eq!(
+        redeem(&id, &token).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+
+    let (late, late_token) = create_with_token().await;
+    tokio::time::advance(Duration::from_secs(61)).await;
+    assert_eq!(
+        redeem(&late, &late_token).await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn throttled_and_unavailable_responses_say_when_to_retry() {
+    let state = shared(AppState::new(
+        AppConfig {
+            shed_create_latency: Some(Duration::from_millis(100)),
+            max_subscribers_per_channel: 1,
+            retry_after: Duration::from_secs(7),
+            ..AppConfig::default()
+        },
+        Box::new(MemoryStorage::new()),
+    ));
+    let config = TestServerConfig::builder().http_transport().build();
+    let server = TestServer::new_with_config(build_router(state.clone()), config).unwrap();
+    let (id, pass) = create(&server, json!({ "text": "busy" })).await;
+    let (name, value) = password(&pass);
+    let connect = || {
+        server
+            .get_websocket(&format!("/api/channels/{id}/live"))
+            .add_header(name.clone(), value.clone())
+    };
+
+    let _first = connect().await.into_websocket().await;
+    let refused = connect().expect_failure().await;
+    assert_eq!(refused.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(refused.header("retry-after"), "7");
+
+    state.storage_latency().record(Duration::from_secs(5));
+    let shed = server
+        .post("/api/channels")
+        .json(&json!({ "text": "slow" }))
+        .await;
+    assert_eq!(shed.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(shed.header("retry-after"), "7");
+
+    let ok = server.get("/health").await;
+    assert!(ok.maybe_header("retry-after").is_none());
+}
+
+#[tokio::test]
+async fn channel_text_can_be_read_by_byte_range() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "héllo world" })).await;
+    let (name, value) = password(&pass);
+    let text = |range: Option<&'static str>| {
+        let request = server
+            .get(&format!("/api/channels/{id}/text"))
+            .add_header(name.clone(), value.clone());
+        match range {
+            Some(range) => request.add_header(header::RANGE, HeaderValue::from_static(range)),
+            None => request,
+        }
+    };
+
+    let full = text(None).await;
+    assert_eq!(full.status_code(), StatusCode::OK);
+    assert_eq!(full.header("accept-ranges"), "bytes");
+    assert_eq!(full.text(), "héllo world");
+
+    // "é" is two bytes, so this range ends halfway through it.
+    let head = text(Some("bytes=0-1")).await;
+    assert_eq!(head.status_code(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(head.header("content-range"), "bytes 0-1/12");
+    assert_eq!(head.as_bytes().as_ref(), b"h\xc3");
+
+    let middle = text(Some("bytes=3-6")).await;
+    assert_eq!(middle.status_code(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(middle.header("content-range"), "bytes 3-6/12");
+    assert_eq!(middle.text(), "llo ");
+
+    let tail = text(Some("bytes=-5")).await;
+    assert_eq!(tail.text(), "world");
+
+    let beyond = text(Some("bytes=12-20")).await;
+    assert_eq!(beyond.status_code(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(beyond.header("content-range"), "bytes */12");
+}
+
+#[tokio::test(start_paused = true)]
+async fn endpoints_are_rate_limited_independently() {
+    let server = server_with(AppConfig {
+        rate_limits: RateLimits {
+            create: Some("2/60".parse().unwrap()),
+            fetch: Some("3/60:channel".parse().unwrap()),
+            ..RateLimits::default()
+        },
+        ..AppConfig::default()
+    });
+    let (first, first_pass) = create(&server, json!({ "text": "one" })).await;
+    let (second, second_pass) = create(&server, json!({ "text": "two" })).await;
+    let refused = server
+        .post("/api/channels")
+        .json(&json!({ "text": "three" }))
+        .await;
+    assert_eq!(refused.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(refused.header("retry-after"), "30");
+
+    let fetch = |id: &str, pass: &str| {
+        let (name, value) = password(pass);
+        server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+    };
+    for _ in 0..3 {
+        fetch(&first, &first_pass).await.assert_status_ok();
+    }
+    assert_eq!(
+        fetch(&first, &first_pass).await.status_code(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    // Fetch buckets are per channel, and updates are not limited at all.
+    fetch(&second, &second_pass).await.assert_status_ok();
+    let (name, value) = password(&first_pass);
+    for _ in 0..5 {
+        server
+            .put(&format!("/api/channels/{first}"))
+            .add_header(name.clone(), value.clone())
+            .json(&json!({ "text": "edited" }))
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+    }
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    create(&server, json!({ "text": "three" })).await;
+    fetch(&first, &first_pass).await.assert_status_ok();
+}
+
+#[tokio::test(start_paused = true)]
+async fn bursts_above_the_global_rate_are_shed() {
+    let server = server_with(AppConfig {
+        global_rate_limit: Some(4),
+        ..AppConfig::default()
+    });
+    let mut statuses = Vec::new();
+    for _ in 0..10 {
+        statuses.push(server.get("/api/channels/missing").await.status_code());
+    }
+    let shed = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::SERVICE_UNAVAILABLE)
+        .count();
+    assert_eq!(shed, 6, "{statuses:?}");
+    let refused = server.get("/api/channels/missing").await;
+    assert_eq!(refused.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(refused.header("retry-after"), "1");
+    // Probes are never shed, even with the bucket empty.
+    server.get("/health").await.assert_status_ok();
+
+    tokio::time::advance(Duration::from_millis(250)).await;
+    server
+        .get("/api/channels/missing")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn diff_shows_what_the_latest_edit_changed() {
+    let server = server();
+    let (id, pass) = create(&server, json!({ "text": "alpha\nbeta\ngamma\n" })).await;
+    let (name, value) = password(&pass);
+    let diff = || {
+        server
+            .get(&format!("/api/channels/{id}/diff"))
+            .add_header(name.clone(), value.clone())
+    };
+    let edit = |text: &'static str| {
+        server
+            .put(&format!("/api/channels/{id}"))
+            .add_header(name.clone(), value.clone())
+            .json(&json!({ "text": text }))
+    };
+
+    assert_eq!(diff().await.status_code(), StatusCode::NOT_FOUND);
+
+    edit("alpha\nBETA\ngamma\n")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    let first = diff().await;
+    assert_eq!(first.status_code(), StatusCode::OK);
+    assert_eq!(
+        first.text(),
+        "--- previous\n+++ current\n@@ -1,3 +1,3 @@\n alpha\n-beta\n+BETA\n gamma\n"
+    );
+
+    // Only the last edit is kept.
+    edit("alpha\nBETA\ngamma\ndelta\n")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    let second = diff().await.text();
+    assert!(second.contains("+delta\n"), "{second}");
+    assert!(!second.contains("-beta"), "{second}");
+}
+
+#[tokio::test]
+async fn edits_build_a_history_that_can_be_reverted_to() {
+    let server = server_with(AppConfig {
+        history_versions: 2,
+        ..AppConfig::default()
+    });
+    let (id, pass) = create(&server, json!({ "text": "v1" })).await;
+    let (name, value) = password(&pass);
+    for text in ["v2", "v3", "v4"] {
+        server
+            .put(&format!("/api/channels/{id}"))
+            .add_header(name.clone(), value.clone())
+            .json(&json!({ "text": text }))
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+    }
+
+    let history: Value = server
+        .get(&format!("/api/channels/{id}/history"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(history["version"], 4);
+    let versions = history["versions"].as_array().unwrap();
+    // Capped at two, newest first: "v1" has dropped out.
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0]["version"], 3);
+    assert_eq!(versions[0]["text"], "v3");
+    assert_eq!(versions[1]["text"], "v2");
+    assert!(versions[0]["replaced_at"].as_u64().unwrap() > 0);
+
+    let reverted: Value = server
+        .post(&format!("/api/channels/{id}/revert/2"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(reverted["version"], 5);
+    let fetched: Value = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(fetched["text"], "v2");
+
+    let history: Value = server
+        .get(&format!("/api/channels/{id}/history"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .json();
+    assert_eq!(history["versions"][0]["text"], "v4");
+    let missing = server
+        .post(&format!("/api/channels/{id}/revert/1"))
+        .add_header(name, value)
+        .await;
+    assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_json_is_an_error_response() {
+    let server = server();
+    let response = server
+        .post("/api/channels/some-id/files/delete")
+        .add_header(
+            HeaderName::from_static("x-request-id"),
+            HeaderValue::from_static("req-7"),
+        )
+        .content_type("application/json")
+        .bytes("{\"file_ids\": [".into())
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert_eq!(body["request_id"], "req-7");
+    assert!(
+        body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse the request body as JSON")
+    );
+
+    let untyped = server.post("/api/channels").bytes("{}".into()).await;
+    assert_eq!(untyped.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(untyped.json::<Value>()["message"].is_string());
+}
+
+#[tokio::test]
+async fn bare_layer_errors_become_error_responses() {
+    let server = server();
+    let response = server
+        .method(axum::http::Method::PATCH, "/api/channels/some-id")
+        .add_header(
+            HeaderName::from_static("x-request-id"),
+            HeaderValue::from_static("req-8"),
+        )
+        .await;
+    assert_eq!(response.status_code(), StatusCode::METHOD_NOT_ALLOWED);
+    let body: Value = response.json();
+    assert_eq!(
+        body,
+        json!({ "message": "Method Not Allowed", "request_id": "req-8" })
+    );
+}
+
+#[tokio::test]
+async fn configured_event_sinks_post_and_count_events() {
+    let (url, mut events) = mock_sink(204).await;
+    let server = server_with(AppConfig {
+        channel_events_url: HttpTarget::parse(&url),
+        count_channel_events: true,
+        ..AppConfig::default()
+    });
+
+    let (id, pass) = create(&server, json!({ "text": "hello" })).await;
+    let (name, value) = password(&pass);
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await
+        .assert_status_ok();
+
+    for expected in ["created", "read"] {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event["event"], expected);
+        assert_eq!(event["id"], id.as_str());
+    }
+    let ready: Value = server.get("/ready").await.json();
+    assert_eq!(
+        ready["channel_events"],
+        json!({ "created": 1, "read": 1, "updated": 0, "deleted": 0, "expired": 0 })
+    );
+}
+
+#[tokio::test]
+async fn deleted_channels_are_not_served_stale() {
+    let storage = MemoryStorage::new();
+    let state = shared(AppState::new(
+        AppConfig {
+            serve_stale_on_error: true,
+            ..AppConfig::default()
+        },
+        Box::new(storage.clone()),
+    ));
+    let server = TestServer::new(build_router(state)).expect("test server");
+    let (id, pass) = create(&server, json!({ "text": "sensitive" })).await;
+    let (name, value) = password(&pass);
+    server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .assert_status_ok();
+    server
+        .delete(&format!("/api/channels/{id}"))
+        .add_header(name.clone(), value.clone())
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    storage.set_offline(true);
+    let after = server
+        .get(&format!("/api/channels/{id}"))
+        .add_header(name, value)
+        .await;
+    assert_ne!(after.status_code(), StatusCode::OK);
+    assert!(after.headers().get("x-served-stale").is_none());
+}
+
+#[tokio::test]
+async fn rewritten_and_renamed_channels_drop_their_stale_copies() {
+    let storage = MemoryStorage::new();
+    let state = shared(AppState::new(
+        AppConfig {
+            serve_stale_on_error: true,
+            ..AppConfig::default()
+        },
+        Box::new(storage.clone()),
+    ));
+    let server = TestServer::new(build_router(state)).expect("test server");
+    let (updated, pass) = create(&server, json!({ "text": "old" })).await;
+    let (renamed, renamed_pass) = create(&server, json!({ "text": "leaked" })).await;
+    for (id, pass) in [(&updated, &pass), (&renamed, &renamed_pass)] {
+        let (name, value) = password(pass);
+        server
+            .get(&format!("/api/channels/{id}"))
+            .add_header(name, value)
+            .await
+            .assert_status_ok();
+    }
+
+    let (name, value) = password(&pass);
+    server
+        .put(&format!("/api/channels/{updated}"))
+        .add_header(name.clone(), value.clone())
+        .json(&json!({ "text": "new" }))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    let (renamed_name, renamed_value) = password(&renamed_pass);
+    server
+        .post(&format!("/api/channels/{renamed}/regenerate-id"))
+        .add_header(renamed_name.clone(), renamed_value.clone())
+        .await
+        .assert_status_ok();
+
+    storage.set_offline(true);
+    let after_update = server
+        .get(&format!("/api/channels/{updated}"))
+        .add_header(name, value)
+        .await;
+    assert!(after_update.headers().get("x-served-stale").is_none());
+    let old_id = server
+        .get(&format!("/api/channels/{renamed}"))
+        .add_header(renamed_name, renamed_value)
+        .await;
+    assert!(old_id.headers().get("x-served-stale").is_none());
+}
+
+#[tokio::test]
+async fn upserts_that_create_are_gated_like_creates() {
+    let (url, mut asked) = mock_sink(451).await;
+    let server = server_with(AppConfig {
+        create_approval_url: HttpTarget::parse(&url),
+        ..AppConfig::default()
+    });
+    let denied = server
+        .put("/api/channels/upserted-id?upsert=true")
+        .json(&json!({ "text": "unmoderated" }))
+        .await;
+    assert_eq!(denied.status_code(), StatusCode::FORBIDDEN);
+    assert!(asked.recv().await.is_some());
+    assert_eq!(
+        server.get("/api/channels/upserted-id").await.status_code(),
+        StatusCode::NOT_FOUND
+    );
+
+    let limited = server_with(AppConfig {
+        rate_limits: RateLimits {
+            create: Some("1/60".parse().unwrap()),
+            ..RateLimits::default()
+        },
+        ..AppConfig::default()
+    });
+    create(&limited, json!({ "text": "one" })).await;
+    let refused = limited
+        .put("/api/channels/another-id?upsert=true")
+        .json(&json!({ "text": "two" }))
+        .await;
+    assert_eq!(refused.status_code(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,206 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::Response,
+};
+use serde::Deserialize;
+use similar::TextDiff;
+use tracing::instrument;
+
+use super::handlers::{charge_read, encryption_key, load_authorized, load_authorized_with_query};
+use crate::{
+    error::AppError,
+    state::{SharedState, refresh_ttl_on_read},
+};
+
+#[derive(Debug, Deserialize, Default)]
+pub struct TextQuery {
+    /// Channel password, honoured only with `ALLOW_PASSWORD_QUERY`.
+    #[serde(default)]
+    pub pw: Option<String>,
+}
+
+/// What a `Range` header asks of a body of some length.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No usable range: serve everything.
+    Full,
+    /// First and last byte, inclusive, both within the body.
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+/// Reads a single `bytes=` range. Anything else (other units, several ranges,
+/// garbage) is ignored, as a server that does not support it would.
+fn byte_range(headers: &HeaderMap, len: usize) -> ByteRange {
+    let Some(spec) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let parse = |raw: &str| raw.trim().parse::<usize>().ok();
+    match (first.trim().is_empty(), last.trim().is_empty()) {
+        // `bytes=-n`: the last n bytes.
+        (true, false) => match parse(last) {
+            Some(0) => ByteRange::Unsatisfiable,
+            Some(_) if len == 0 => ByteRange::Unsatisfiable,
+            Some(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            None => ByteRange::Full,
+        },
+        // `bytes=a-`: from a to the end.
+        (false, true) => match parse(first) {
+            Some(start) if start >= len => ByteRange::Unsatisfiable,
+            Some(start) => ByteRange::Partial(start, len - 1),
+            None => ByteRange::Full,
+        },
+        (false, false) => match (parse(first), parse(last)) {
+            (Some(start), Some(end)) if start > end => ByteRange::Full,
+            (Some(start), Some(_)) if start >= len => ByteRange::Unsatisfiable,
+            (Some(start), Some(end)) => ByteRange::Partial(start, end.min(len - 1)),
+            _ => ByteRange::Full,
+        },
+        (true, true) => ByteRange::Full,
+    }
+}
+
+/// The channel text as `text/plain`. A `Range: bytes=...` header gets `206`
+/// with that slice of the UTF-8 bytes, split characters and all, so big pastes
+/// can be tailed or paged; a range past the end gets `416`. Every request,
+/// ranged or not, counts as a read.
+#[instrument(level = "debug", skip(state, query, headers))]
+pub async fn channel_text(
+    Path(id): Path<String>,
+    Query(query): Query<TextQuery>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let query_password = query
+        .pw
+        .as_deref()
+        .filter(|_| state.config().allow_password_query);
+    let mut record = load_authorized_with_query(&state, &id, &headers, query_password).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    // Refuse a hopeless range before it uses up one of the channel's reads.
+    if byte_range(&headers, record.data.text.len()) == ByteRange::Unsatisfiable {
+        return Err(AppError::RangeNotSatisfiable(record.data.text.len() as u64));
+    }
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let text = std::mem::take(&mut record.data.text).into_bytes();
+    let len = text.len();
+    let (status, body, content_range) = match byte_range(&headers, len) {
+        ByteRange::Full => (StatusCode::OK, text, None),
+        ByteRange::Partial(start, end) => (
+            StatusCode::PARTIAL_CONTENT,
+            text[start..=end].to_vec(),
+            Some(format!("bytes {start}-{end}/{len}")),
+        ),
+        ByteRange::Unsatisfiable => return Err(AppError::RangeNotSatisfiable(len as u64)),
+    };
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(content_range) = content_range {
+        response_headers.insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).expect("content range is ASCII"),
+        );
+    }
+    Ok(response)
+}
+
+/// Lines of unchanged context around each hunk of a diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// What the latest edit changed in the channel text, as a unified diff from
+/// the text before it to the current one. Only that one previous text is
+/// kept, so this always shows the last edit alone. Channels never edited, or
+/// encrypted, have nothing to diff against and get `404`. Counts as a read.
+#[instrument(level = "debug", skip(state, headers))]
+pub async fn channel_diff(
+    Path(id): Path<String>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mut record = load_authorized(&state, &id, &headers).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    let Some(previous) = state.storage().get(&state.previous_text_key(&id)).await? else {
+        return Err(AppError::NoPreviousText);
+    };
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let diff = TextDiff::from_lines(&previous, &record.data.text)
+        .unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .header("previous", "current")
+        .to_string();
+    let mut response = Response::new(Body::from(diff));
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/x-diff; charset=utf-8"),
+    );
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    use super::{ByteRange, byte_range};
+
+    fn range(value: &str, len: usize) -> ByteRange {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+        byte_range(&headers, len)
+    }
+
+    #[test]
+    fn byte_ranges_parse() {
+        assert_eq!(byte_range(&HeaderMap::new(), 10), ByteRange::Full);
+        assert_eq!(range("bytes=2-5", 10), ByteRange::Partial(2, 5));
+        assert_eq!(range("bytes=2-50", 10), ByteRange::Partial(2, 9));
+        assert_eq!(range("bytes=7-", 10), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=-30", 10), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=12-20", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        for ignored in [
+            "items=0-1",
+            "bytes=0-1,4-5",
+            "bytes=5-2",
+            "bytes=x-",
+            "bytes=-",
+        ] {
+            assert_eq!(range(ignored, 10), ByteRange::Full, "{ignored}");
+        }
+    }
+}
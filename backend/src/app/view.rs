@@ -0,0 +1,161 @@
+use std::fmt::Write as _;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::{Html, IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::{
+    files::percent_encode,
+    handlers::{charge_read, encryption_key, load_authorized_with_query},
+};
+use crate::{
+    channel::ChannelData,
+    error::AppError,
+    state::{SharedState, refresh_ttl_on_read},
+};
+
+/// Nothing but the inline stylesheet may load or run, so even markup that
+/// slipped past escaping stays inert.
+const VIEW_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'";
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ViewQuery {
+    /// Channel password, honoured only with `ALLOW_PASSWORD_QUERY`.
+    #[serde(default)]
+    pub pw: Option<String>,
+}
+
+/// The channel as a minimal HTML page for people following a link: the text,
+/// escaped, and a download link per file. Counts as a read like a fetch.
+#[instrument(level = "debug", skip(state, query, headers))]
+pub async fn view_channel(
+    Path(id): Path<String>,
+    Query(query): Query<ViewQuery>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let query_password = query
+        .pw
+        .as_deref()
+        .filter(|_| state.config().allow_password_query);
+    let mut record = load_authorized_with_query(&state, &id, &headers, query_password).await?;
+    let key = encryption_key(&headers);
+    record.unseal(key.as_deref())?;
+    let mut record = charge_read(&state, &id, record).await?;
+    record.unseal(key.as_deref())?;
+    refresh_ttl_on_read(&state, &id, &record).await?;
+
+    let mut response = Html(render_view(&id, &record.data, query_password)).into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(VIEW_CONTENT_SECURITY_POLICY),
+    );
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    // The URL may carry the password; keep it out of caches and referrers.
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response_headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    Ok(response)
+}
+
+/// Links carry `?pw=` only when the page itself was opened with one, since a
+/// browser following them cannot send the password header.
+fn render_view(id: &str, data: &ChannelData, query_password: Option<&str>) -> String {
+    let password_query = query_password
+        .map(|password| format!("?pw={}", percent_encode(password)))
+        .unwrap_or_default();
+    let mut page = String::from(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+    );
+    let _ = write!(
+        page,
+        "<title>Channel {}</title>\n\
+         <style>body{{font-family:sans-serif;margin:2rem auto;max-width:48rem}}\
+         pre{{white-space:pre-wrap;word-break:break-word;background:#f4f4f4;padding:1rem}}</style>\n\
+         </head>\n<body>\n<h1>Channel {}</h1>\n<pre>{}</pre>\n",
+        escape_html(id),
+        escape_html(id),
+        escape_html(&data.text),
+    );
+    if !data.files.is_empty() {
+        page.push_str("<ul>\n");
+        for file in &data.files {
+            let href = format!(
+                "/api/channels/{}/files/{}{password_query}",
+                percent_encode(id),
+                percent_encode(&file.id),
+            );
+            let _ = writeln!(
+                page,
+                "<li><a href=\"{}\" download>{}</a> ({} bytes)</li>",
+                escape_html(&href),
+                escape_html(&file.name),
+                file.size,
+            );
+        }
+        page.push_str("</ul>\n");
+    }
+    page.push_str("</body>\n</html>\n");
+    page
+}
+
+/// Escapes text for both element content and quoted attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_html, render_view};
+    use crate::channel::{ChannelData, ChannelFile};
+
+    #[test]
+    fn markup_characters_are_escaped() {
+        assert_eq!(
+            escape_html(r#"<a href="x" onclick='y'>&</a>"#),
+            "&lt;a href=&quot;x&quot; onclick=&#39;y&#39;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn file_links_are_encoded_and_carry_the_query_password() {
+        let data = ChannelData {
+            text: String::new(),
+            files: vec![ChannelFile {
+                id: "a b\"".into(),
+                name: "<img>.png".into(),
+                mime_type: "image/png".into(),
+                size: 3,
+                data_base64: String::new(),
+                ..ChannelFile::default()
+            }],
+        };
+        let page = render_view("abc", &data, Some("p&w"));
+        assert!(page.contains(r#"href="/api/channels/abc/files/a%20b%22?pw=p%26w""#));
+        assert!(page.contains("&lt;img&gt;.png"));
+        assert!(!page.contains("<img>"));
+    }
+}
@@ -1,14 +1,111 @@
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
-use rand::{Rng, distributions::Alphanumeric};
-use serde::{Deserialize, Serialize};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use rand::{Rng, RngCore, distributions::Alphanumeric};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{SeqAccess, Visitor},
+};
 use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
-use crate::{config::MAX_CHANNEL_BYTES, error::AppError};
+use crate::{
+    config::{
+        ContentRequirement, FileIdPolicy, MAX_CHANNEL_BYTES, MAX_FILES_PER_CHANNEL,
+        MAX_METADATA_BYTES, MAX_METADATA_KEY_BYTES, MAX_METADATA_VALUE_BYTES, MAX_TAG_BYTES,
+        TtlPolicy,
+    },
+    encryption::{SealedData, open_channel_data, seal_channel_data},
+    error::AppError,
+};
 
 const CHANNEL_PASSWORD_LENGTH: usize = 12;
+const PASSWORD_SALT_LENGTH: usize = 16;
+/// Storage format every write produces. Older ones are still read:
+/// `PLAIN_TEXT_FORMAT` is a bare string from before records were JSON, and
+/// `UNVERSIONED_JSON_FORMAT` is JSON written before records carried `format`.
+pub const CHANNEL_FORMAT_VERSION: u32 = 2;
+pub const PLAIN_TEXT_FORMAT: u32 = 0;
+pub const UNVERSIONED_JSON_FORMAT: u32 = 1;
+const MAX_CHANNEL_ID_LENGTH: usize = 64;
+
+/// Formats that are already compressed; gzipping them again only burns CPU.
+const PRECOMPRESSED_MIME_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-rar-compressed",
+    "application/x-xz",
+    "application/zip",
+    "application/zstd",
+    "image/avif",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+const PRECOMPRESSED_MIME_PREFIXES: &[&str] = &["audio/", "video/"];
+
+/// Extensions given to generated file names, by MIME essence.
+const MIME_EXTENSIONS: &[(&str, &str)] = &[
+    ("application/gzip", ".gz"),
+    ("application/json", ".json"),
+    ("application/pdf", ".pdf"),
+    ("application/zip", ".zip"),
+    ("audio/mpeg", ".mp3"),
+    ("image/gif", ".gif"),
+    ("image/jpeg", ".jpg"),
+    ("image/png", ".png"),
+    ("image/svg+xml", ".svg"),
+    ("image/webp", ".webp"),
+    ("text/csv", ".csv"),
+    ("text/html", ".html"),
+    ("text/markdown", ".md"),
+    ("text/plain", ".txt"),
+    ("video/mp4", ".mp4"),
+];
+
+/// Non-`text/` types whose content is still text a UI can show as is.
+const TEXTUAL_MIME_TYPES: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/toml",
+    "application/x-sh",
+    "application/x-yaml",
+    "application/xml",
+    "application/yaml",
+];
+
+/// Leading bytes of formats recognisable when the declared type says nothing.
+const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+/// How a file blob is encoded on top of base64: in storage, or in a fetch
+/// response whose reader asked for `?encoded=gzip`. Never accepted from
+/// clients.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobCompression {
+    Gzip,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ChannelFile {
@@ -19,29 +116,347 @@ pub struct ChannelFile {
     pub size: u64,
     #[serde(rename = "data_base64")]
     pub data_base64: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<BlobCompression>,
+    /// Password the uploader set on this file alone; hashed into
+    /// `password_hash` by `protect_files` and never stored.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    /// Set on files that need `x-file-password` on top of the channel
+    /// password before their bytes are served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ChannelData {
     #[serde(default)]
     pub text: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_capped_files")]
     pub files: Vec<ChannelFile>,
 }
 
+/// Deserializes a `files` array, keeping at most one entry past
+/// `MAX_FILES_PER_CHANNEL` so `validate_channel_data` rejects it. Surplus
+/// entries are parsed and dropped one at a time, so an enormous array never
+/// turns into an equally enormous `Vec`.
+pub fn deserialize_capped_files<'de, D>(deserializer: D) -> Result<Vec<ChannelFile>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct CappedFiles;
+
+    impl<'de> Visitor<'de> for CappedFiles {
+        type Value = Vec<ChannelFile>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an array of files")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let cap = MAX_FILES_PER_CHANNEL + 1;
+            let mut files = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(cap));
+            while files.len() < cap {
+                match seq.next_element()? {
+                    Some(file) => files.push(file),
+                    None => return Ok(files),
+                }
+            }
+            while seq.next_element::<serde_json::Value>()?.is_some() {}
+            Ok(files)
+        }
+    }
+
+    deserializer.deserialize_seq(CappedFiles)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct StoredChannel {
     #[serde(default)]
     pub password_hash: Option<String>,
+    #[serde(default)]
+    pub immutable: bool,
+    #[serde(default)]
+    pub max_reads: Option<u32>,
+    /// Unix seconds at creation; absent on channels stored before it existed.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    /// Creator-supplied key-value pairs kept beside the content, never part of
+    /// what readers are served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    /// Labels the creator filed the channel under, sorted and deduplicated.
+    /// Like `metadata`, never served to readers; admins list channels by tag.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Bumped on every write of the content, so sync clients can tell whether
+    /// anything changed. Zero on channels stored before it existed.
+    #[serde(default)]
+    pub version: u64,
+    /// TTL the creator asked for, in place of the server default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    /// Expire a fixed time after creation: activity never extends the TTL.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sticky_ttl: bool,
+    /// Deleted, without a trace, by the first read that gets it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub burn_after_read: bool,
+    /// Set when the content is encrypted under a caller-held key; `data` is
+    /// then empty until `unseal` restores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<SealedData>,
+    /// Storage format the record was read in; `None` for records that were
+    /// never stored. Never written back as is: `serialize_channel` always
+    /// stamps `CHANNEL_FORMAT_VERSION`.
+    #[serde(
+        rename = "format",
+        default = "unversioned_json_format",
+        skip_serializing
+    )]
+    pub format: Option<u32>,
     #[serde(flatten)]
     pub data: ChannelData,
 }
 
+fn unversioned_json_format() -> Option<u32> {
+    Some(UNVERSIONED_JSON_FORMAT)
+}
+
+/// A record as written: the current format number alongside its fields.
+#[derive(Serialize)]
+struct CurrentFormat<'a> {
+    format: u32,
+    #[serde(flatten)]
+    record: &'a StoredChannel,
+}
+
+impl StoredChannel {
+    pub fn ensure_mutable(&self) -> Result<(), AppError> {
+        if self.immutable {
+            return Err(AppError::ChannelImmutable);
+        }
+        Ok(())
+    }
+
+    /// How long the channel lives after each write or read.
+    pub fn ttl(&self, default: Duration) -> Duration {
+        self.ttl_seconds.map_or(default, Duration::from_secs)
+    }
+
+    /// Moves `data` into an encrypted envelope keyed by `passphrase`.
+    pub fn seal(&mut self, passphrase: &str) -> Result<(), AppError> {
+        self.sealed = Some(seal_channel_data(&self.data, passphrase)?);
+        self.data = ChannelData::default();
+        Ok(())
+    }
+
+    /// Restores `data` from the encrypted envelope, if there is one. A sealed
+    /// channel without a passphrase is as undecryptable as a wrong one.
+    pub fn unseal(&mut self, passphrase: Option<&str>) -> Result<(), AppError> {
+        let Some(sealed) = &self.sealed else {
+            return Ok(());
+        };
+        let passphrase = passphrase.ok_or(AppError::UndecryptableChannel)?;
+        self.data = open_channel_data(sealed, passphrase)?;
+        self.sealed = None;
+        Ok(())
+    }
+}
+
+/// Bounds metadata by entry count, by the length of each key and value, and by
+/// its combined size.
+pub fn validate_metadata(
+    metadata: Option<&HashMap<String, String>>,
+    max_entries: usize,
+) -> Result<(), AppError> {
+    let Some(metadata) = metadata else {
+        return Ok(());
+    };
+    if metadata.len() > max_entries {
+        return Err(AppError::TooManyMetadataEntries(max_entries));
+    }
+    if metadata.iter().any(|(key, value)| {
+        key.len() > MAX_METADATA_KEY_BYTES || value.len() > MAX_METADATA_VALUE_BYTES
+    }) {
+        return Err(AppError::MetadataEntryTooLong);
+    }
+    let size: usize = metadata
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    if size > MAX_METADATA_BYTES {
+        return Err(AppError::MetadataTooLarge);
+    }
+    Ok(())
+}
+
+/// Checks `tags` against the limits and returns them sorted and without
+/// duplicates. Tags end up in storage keys, so only a small safe alphabet is
+/// allowed.
+pub fn normalize_tags(mut tags: Vec<String>, max_tags: usize) -> Result<Vec<String>, AppError> {
+    tags.sort_unstable();
+    tags.dedup();
+    if tags.len() > max_tags {
+        return Err(AppError::TooManyTags(max_tags));
+    }
+    let valid = |tag: &String| {
+        (1..=MAX_TAG_BYTES).contains(&tag.len())
+            && tag
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(&byte))
+    };
+    if !tags.iter().all(valid) {
+        return Err(AppError::InvalidTag);
+    }
+    Ok(tags)
+}
+
+pub fn validate_max_reads(max_reads: Option<u32>) -> Result<(), AppError> {
+    if max_reads == Some(0) {
+        return Err(AppError::InvalidMaxReads);
+    }
+    Ok(())
+}
+
+/// An earlier version of a channel's text, as kept in its history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub version: u64,
+    /// Unix seconds at which a later version replaced it.
+    pub replaced_at: u64,
+    pub text: String,
+}
+
+/// Checks a requested TTL against `min..=max` seconds. Out-of-range values are
+/// rejected, or pulled into range under `TtlPolicy::Clamp`.
+pub fn resolve_channel_ttl(
+    requested: Option<u64>,
+    min: u64,
+    max: u64,
+    policy: TtlPolicy,
+) -> Result<Option<u64>, AppError> {
+    let Some(requested) = requested else {
+        return Ok(None);
+    };
+    if (min..=max).contains(&requested) {
+        return Ok(Some(requested));
+    }
+    match policy {
+        TtlPolicy::Reject => Err(AppError::InvalidTtl { min, max }),
+        TtlPolicy::Clamp => Ok(Some(requested.clamp(min, max))),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 pub fn generate_channel_id() -> String {
     let raw = Uuid::new_v4().simple().to_string();
     raw[..8].to_string()
 }
 
+/// Fills in an empty file id with a fresh one and an empty name with
+/// `file-{n}{ext}` (`n` counting from 1), taking the extension from the
+/// declared MIME type or, failing that, from the content's magic number.
+/// Provided ids and names are left alone.
+pub fn name_unnamed_files(data: &mut ChannelData) {
+    for (index, file) in data.files.iter_mut().enumerate() {
+        if file.id.trim().is_empty() {
+            file.id = Uuid::new_v4().simple().to_string();
+        }
+        if file.name.trim().is_empty() {
+            let extension = extension_for(&file.mime_type)
+                .or_else(|| sniff_mime_type(&file.data_base64).and_then(extension_for))
+                .unwrap_or_default();
+            file.name = format!("file-{}{extension}", index + 1);
+        }
+    }
+}
+
+fn extension_for(mime_type: &str) -> Option<&'static str> {
+    let essence = mime_essence(mime_type);
+    MIME_EXTENSIONS
+        .iter()
+        .find(|(mime, _)| *mime == essence)
+        .map(|(_, extension)| *extension)
+}
+
+fn sniff_mime_type(data_base64: &str) -> Option<&'static str> {
+    // 12 bytes cover every magic number and decode from whole base64 quanta.
+    let head = data_base64.get(..16).unwrap_or(data_base64);
+    let bytes = BASE64_ENGINE.decode(head).ok()?;
+    MAGIC_NUMBERS
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
+
+/// `mime_type` without parameters, lowercased: `Text/HTML; charset=utf-8`
+/// becomes `text/html`.
+pub(crate) fn mime_essence(mime_type: &str) -> String {
+    mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// How a UI can present a file, judged from its declared MIME type alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCategory {
+    Image,
+    Text,
+    Audio,
+    Video,
+    Other,
+}
+
+impl FileCategory {
+    pub fn of(mime_type: &str) -> Self {
+        let essence = mime_essence(mime_type);
+        let (kind, subtype) = essence.split_once('/').unwrap_or((&essence, ""));
+        match kind {
+            "image" => Self::Image,
+            "audio" => Self::Audio,
+            "video" => Self::Video,
+            "text" => Self::Text,
+            _ if TEXTUAL_MIME_TYPES.contains(&essence.as_str())
+                || subtype.ends_with("+json")
+                || subtype.ends_with("+xml") =>
+            {
+                Self::Text
+            }
+            _ => Self::Other,
+        }
+    }
+
+    /// Whether a UI can render the file inline rather than only offer it
+    /// for download.
+    pub fn previewable(self) -> bool {
+        self != Self::Other
+    }
+}
+
+/// Client-chosen ids (upsert) are restricted to a URL- and key-safe alphabet.
+pub fn validate_channel_id(id: &str) -> Result<(), AppError> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_CHANNEL_ID_LENGTH
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if !valid {
+        return Err(AppError::InvalidChannelId);
+    }
+    Ok(())
+}
+
 pub fn generate_channel_password() -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -50,36 +465,119 @@ pub fn generate_channel_password() -> String {
         .collect()
 }
 
+/// Argon2id under a fresh random salt, as a PHC string
+/// (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`) that carries everything
+/// needed to verify it later.
 pub fn hash_channel_password(password: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(password.as_bytes());
-    format!("{:x}", hasher.finalize())
+    let mut salt = [0u8; PASSWORD_SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).expect("salt length is within PHC bounds");
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default Argon2 parameters accept any password")
+        .to_string()
+}
+
+/// Runs Argon2 work on the blocking pool. A single hash takes long enough that
+/// running it on a runtime worker would stall every request sharing that
+/// worker.
+pub async fn run_password_work<T, F>(work: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// `hash_channel_password` off the runtime workers.
+pub async fn hash_channel_password_async(password: String) -> String {
+    run_password_work(move || hash_channel_password(&password)).await
+}
+
+/// `verify_channel_password` off the runtime workers. An open channel is
+/// answered without leaving the current task.
+pub async fn verify_channel_password_async(
+    stored_hash: Option<String>,
+    provided: Option<String>,
+) -> bool {
+    if stored_hash.is_none() {
+        return true;
+    }
+    run_password_work(move || verify_channel_password(stored_hash.as_deref(), provided.as_deref()))
+        .await
+}
+
+/// Turns the per-file passwords of an upload into hashes. Whatever hash an
+/// upload claims is discarded, so only a password sent with the file protects
+/// it; a blank one leaves the file open.
+pub async fn protect_files(data: &mut ChannelData) {
+    for file in &mut data.files {
+        file.password_hash = None;
+    }
+    if data.files.iter().all(|file| file.password.is_none()) {
+        return;
+    }
+    let mut files = std::mem::take(&mut data.files);
+    data.files = run_password_work(move || {
+        for file in &mut files {
+            file.password_hash = file
+                .password
+                .take()
+                .filter(|password| !password.trim().is_empty())
+                .map(|password| hash_channel_password(&password));
+        }
+        files
+    })
+    .await;
 }
 
 pub fn verify_channel_password(stored_hash: Option<&str>, provided: Option<&str>) -> bool {
-    match stored_hash {
-        Some(hash) if !hash.is_empty() => {
-            let Some(provided) = provided else {
-                return false;
-            };
-            let computed = hash_channel_password(provided);
+    // Only an explicitly absent hash means "open"; an empty hash never matches.
+    let Some(hash) = stored_hash else {
+        return true;
+    };
+    let Some(provided) = provided else {
+        return false;
+    };
+    match PasswordHash::new(hash) {
+        // The verifier compares the derived hash in constant time.
+        Ok(parsed) => Argon2::default()
+            .verify_password(provided.as_bytes(), &parsed)
+            .is_ok(),
+        // Channels created before Argon2 store a bare, unsalted SHA-256 hex
+        // digest; they keep working until they expire.
+        Err(_) => {
+            let computed = sha256_hex(provided.as_bytes());
             hash.as_bytes().ct_eq(computed.as_bytes()).into()
         }
-        _ => true,
     }
 }
 
+/// Verifies `password` against a throwaway Argon2 hash, spending the time a
+/// real check would without anything to check.
+pub fn mimic_password_verification(password: Option<&str>) {
+    static DECOY: OnceLock<String> = OnceLock::new();
+    let decoy = DECOY.get_or_init(|| hash_channel_password(""));
+    std::hint::black_box(verify_channel_password(Some(decoy), password));
+}
+
+/// `mimic_password_verification` off the runtime workers, so it costs the
+/// caller the same wait a real check would.
+pub async fn mimic_password_verification_async(password: Option<String>) {
+    run_password_work(move || mimic_password_verification(password.as_deref())).await;
+}
+
 pub fn validate_channel_data(data: &ChannelData) -> Result<(), AppError> {
-    let mut total = data.text.len();
-    for file in &data.files {
-        let decoded = BASE64_ENGINE
-            .decode(&file.data_base64)
-            .map_err(|_| AppError::InvalidFileData)?;
-        total = total
-            .checked_add(decoded.len())
-            .ok_or(AppError::PayloadTooLarge)?;
+    if data.files.len() > MAX_FILES_PER_CHANNEL {
+        return Err(AppError::TooManyFiles);
     }
-
+    if data.files.iter().any(|file| file.compression.is_some()) {
+        return Err(AppError::InvalidFileData);
+    }
+    let total = channel_size(data).ok_or(AppError::InvalidFileData)?;
     if total > MAX_CHANNEL_BYTES {
         return Err(AppError::PayloadTooLarge);
     }
@@ -87,13 +585,231 @@ pub fn validate_channel_data(data: &ChannelData) -> Result<(), AppError> {
     Ok(())
 }
 
+/// Enforces `REQUIRE_CHANNEL_CONTENT`, naming the part that is missing.
+/// Whitespace-only text counts as empty.
+pub fn check_content_requirement(
+    data: &ChannelData,
+    requirement: ContentRequirement,
+) -> Result<(), AppError> {
+    let has_text = !data.text.trim().is_empty();
+    let has_files = !data.files.is_empty();
+    match requirement {
+        ContentRequirement::Nothing => Ok(()),
+        ContentRequirement::Either if !has_text && !has_files => Err(AppError::ContentRequired),
+        ContentRequirement::Text | ContentRequirement::Both if !has_text => {
+            Err(AppError::TextRequired)
+        }
+        ContentRequirement::Files | ContentRequirement::Both if !has_files => {
+            Err(AppError::FileRequired)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Holds uploaded file ids to `policy`: checks that each is a UUID, or swaps
+/// them all for fresh ones.
+pub fn apply_file_id_policy(data: &mut ChannelData, policy: FileIdPolicy) -> Result<(), AppError> {
+    match policy {
+        FileIdPolicy::Any => {}
+        FileIdPolicy::Uuid => {
+            if let Some(file) = data
+                .files
+                .iter()
+                .find(|file| Uuid::parse_str(&file.id).is_err())
+            {
+                return Err(AppError::FileIdNotUuid(file.id.clone()));
+            }
+        }
+        FileIdPolicy::Generate => {
+            for file in &mut data.files {
+                file.id = Uuid::new_v4().to_string();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bytes of text plus decoded file content, or `None` when a file is not
+/// valid base64. File bodies must not be compressed.
+pub fn channel_size(data: &ChannelData) -> Option<usize> {
+    data.files.iter().try_fold(data.text.len(), |total, file| {
+        Some(total.saturating_add(decoded_base64_len(&file.data_base64)?))
+    })
+}
+
+/// Re-decodes every stored file so corrupt base64 surfaces as a server-side
+/// integrity failure instead of reaching the client.
+pub fn verify_stored_files(data: &ChannelData) -> Result<(), AppError> {
+    for file in &data.files {
+        decoded_base64_len(&file.data_base64).ok_or(AppError::IntegrityError)?;
+    }
+    Ok(())
+}
+
+/// Base64 characters validated per step; a multiple of four, so only the last
+/// step can carry padding.
+const BASE64_CHECK_CHUNK: usize = 4 * 1024;
+
+/// Size `encoded` decodes to, or `None` when it is not valid base64. Decodes
+/// a chunk at a time into a small scratch buffer instead of allocating the
+/// whole result, and accepts exactly what `BASE64_ENGINE.decode` accepts.
+pub fn decoded_base64_len(encoded: &str) -> Option<usize> {
+    let mut scratch = [0u8; BASE64_CHECK_CHUNK / 4 * 3];
+    let mut chunks = encoded.as_bytes().chunks(BASE64_CHECK_CHUNK).peekable();
+    let mut total = 0;
+    while let Some(chunk) = chunks.next() {
+        // Padding is only valid at the very end of the input.
+        if chunks.peek().is_some() && chunk.last() == Some(&b'=') {
+            return None;
+        }
+        total += BASE64_ENGINE.decode_slice(chunk, &mut scratch).ok()?;
+    }
+    Some(total)
+}
+
+/// The first `max_chars` characters of `text`, cut on a char boundary, and
+/// whether anything was left off.
+pub fn preview_text(text: &str, max_chars: usize) -> (&str, bool) {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => (&text[..end], true),
+        None => (text, false),
+    }
+}
+
+/// Compares two channels' content, returning whether their text and their
+/// files match. Files are matched by name, type and a hash of their content,
+/// ignoring ids and order, so a copy with fresh file ids still counts as equal.
+pub fn compare_channel_data(left: &ChannelData, right: &ChannelData) -> (bool, bool) {
+    (
+        left.text == right.text,
+        file_fingerprints(left) == file_fingerprints(right),
+    )
+}
+
+/// Lowercase hex SHA-256 of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+fn file_fingerprints(data: &ChannelData) -> Vec<(&str, &str, Vec<u8>)> {
+    let mut fingerprints: Vec<_> = data
+        .files
+        .iter()
+        .map(|file| {
+            let digest = Sha256::digest(file.data_base64.as_bytes()).to_vec();
+            (file.name.as_str(), file.mime_type.as_str(), digest)
+        })
+        .collect();
+    fingerprints.sort();
+    fingerprints
+}
+
+/// Removes every file whose id is listed, returning the ids that were removed
+/// and the ones that were not present, each in request order without repeats.
+pub fn remove_files(data: &mut ChannelData, file_ids: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut removed = Vec::new();
+    let mut not_found = Vec::new();
+    for file_id in file_ids {
+        if removed.contains(file_id) || not_found.contains(file_id) {
+            continue;
+        }
+        if data.files.iter().any(|file| &file.id == file_id) {
+            removed.push(file_id.clone());
+        } else {
+            not_found.push(file_id.clone());
+        }
+    }
+
+    data.files.retain(|file| !removed.contains(&file.id));
+    (removed, not_found)
+}
+
+fn is_precompressed(mime_type: &str) -> bool {
+    let essence = mime_essence(mime_type);
+    PRECOMPRESSED_MIME_TYPES.contains(&essence.as_str())
+        || PRECOMPRESSED_MIME_PREFIXES
+            .iter()
+            .any(|prefix| essence.starts_with(prefix))
+}
+
+/// Gzips file blobs larger than `threshold` bytes before they are stored,
+/// skipping already-compressed formats and blobs that would not shrink.
+pub fn compress_blobs(data: &mut ChannelData, threshold: usize) -> Result<(), AppError> {
+    for file in &mut data.files {
+        if let Some(compressed) = gzip_blob(file, threshold)? {
+            file.data_base64 = BASE64_ENGINE.encode(compressed);
+            file.compression = Some(BlobCompression::Gzip);
+        }
+    }
+    Ok(())
+}
+
+/// The gzipped bytes of a plain blob larger than `threshold` bytes, or `None`
+/// for already-compressed formats and blobs that would not shrink.
+pub fn gzip_blob(file: &ChannelFile, threshold: usize) -> Result<Option<Vec<u8>>, AppError> {
+    if file.compression.is_some() || is_precompressed(&file.mime_type) {
+        return Ok(None);
+    }
+    let raw = BASE64_ENGINE
+        .decode(&file.data_base64)
+        .map_err(|_| AppError::InvalidFileData)?;
+    if raw.len() <= threshold {
+        return Ok(None);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    let compressed = encoder.finish()?;
+    Ok((compressed.len() < raw.len()).then_some(compressed))
+}
+
+/// Restores compressed blobs to their plain base64 form after loading.
+pub fn decompress_blobs(data: &mut ChannelData) -> Result<(), AppError> {
+    for file in &mut data.files {
+        let Some(BlobCompression::Gzip) = file.compression else {
+            continue;
+        };
+        let compressed = BASE64_ENGINE
+            .decode(&file.data_base64)
+            .map_err(|_| AppError::InvalidFileData)?;
+        let mut raw = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut raw)?;
+        file.data_base64 = BASE64_ENGINE.encode(raw);
+        file.compression = None;
+    }
+    Ok(())
+}
+
 pub fn serialize_channel(data: &StoredChannel) -> Result<String, AppError> {
-    Ok(serde_json::to_string(data)?)
+    if data.password_hash.as_deref().is_some_and(str::is_empty) {
+        return Err(AppError::EmptyPasswordHash);
+    }
+    Ok(serde_json::to_string(&CurrentFormat {
+        format: CHANNEL_FORMAT_VERSION,
+        record: data,
+    })?)
+}
+
+/// `deserialize_channel` without the plain-text fallback, for
+/// `STRICT_DESERIALIZE`: anything but a JSON record is an error.
+pub fn deserialize_channel_strict(raw: &str) -> Result<StoredChannel, AppError> {
+    Ok(serde_json::from_str(raw)?)
 }
 
 pub fn deserialize_channel(raw: String) -> StoredChannel {
     serde_json::from_str(&raw).unwrap_or_else(|_| StoredChannel {
         password_hash: None,
+        immutable: false,
+        max_reads: None,
+        created_at: None,
+        metadata: None,
+        tags: Vec::new(),
+        version: 0,
+        ttl_seconds: None,
+        sticky_ttl: false,
+        burn_after_read: false,
+        sealed: None,
+        format: Some(PLAIN_TEXT_FORMAT),
         data: ChannelData {
             text: raw,
             files: Vec::new(),
@@ -103,9 +819,27 @@ pub fn deserialize_channel(raw: String) -> StoredChannel {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+
     use super::{
-        generate_channel_id, generate_channel_password, hash_channel_password,
-        verify_channel_password,
+        BlobCompression, CHANNEL_FORMAT_VERSION, ChannelData, ChannelFile, FileCategory,
+        PLAIN_TEXT_FORMAT, StoredChannel, UNVERSIONED_JSON_FORMAT, apply_file_id_policy,
+        check_content_requirement, compare_channel_data, compress_blobs, decoded_base64_len,
+        decompress_blobs, deserialize_channel, deserialize_channel_strict, generate_channel_id,
+        generate_channel_password, hash_channel_password, name_unnamed_files, preview_text,
+        remove_files, resolve_channel_ttl, serialize_channel, sha256_hex, validate_channel_data,
+        validate_channel_id, validate_max_reads, validate_metadata, verify_channel_password,
+        verify_stored_files,
+    };
+    use crate::{
+        config::{
+            ContentRequirement, FileIdPolicy, MAX_FILES_PER_CHANNEL, MAX_METADATA_KEY_BYTES,
+            MAX_METADATA_VALUE_BYTES, TtlPolicy,
+        },
+        error::AppError,
     };
 
     #[test]
@@ -129,6 +863,28 @@ mod tests {
         assert!(verify_channel_password(Some(&hash), Some(password)));
     }
 
+    #[test]
+    fn password_hashes_are_salted_argon2id_phc_strings() {
+        let first = hash_channel_password("correct horse");
+        let second = hash_channel_password("correct horse");
+        assert!(first.starts_with("$argon2id$"), "{first}");
+        assert_ne!(first, second);
+        assert!(verify_channel_password(
+            Some(&second),
+            Some("correct horse")
+        ));
+    }
+
+    #[test]
+    fn legacy_sha256_hashes_still_verify() {
+        let legacy = sha256_hex(b"correct horse");
+        assert!(verify_channel_password(
+            Some(&legacy),
+            Some("correct horse")
+        ));
+        assert!(!verify_channel_password(Some(&legacy), Some("wrong horse")));
+    }
+
     #[test]
     fn password_hash_verification_fails_for_incorrect_password() {
         let password = "correct horse";
@@ -136,4 +892,484 @@ mod tests {
         assert!(!verify_channel_password(Some(&hash), Some("wrong")));
         assert!(!verify_channel_password(Some(&hash), None));
     }
+
+    #[test]
+    fn immutable_channel_refuses_mutation() {
+        let record = StoredChannel {
+            immutable: true,
+            ..StoredChannel::default()
+        };
+        assert!(matches!(
+            record.ensure_mutable(),
+            Err(AppError::ChannelImmutable)
+        ));
+        assert!(StoredChannel::default().ensure_mutable().is_ok());
+    }
+
+    #[test]
+    fn immutable_flag_survives_round_trip() {
+        let record = StoredChannel {
+            immutable: true,
+            ..StoredChannel::default()
+        };
+        let restored = deserialize_channel(serialize_channel(&record).unwrap());
+        assert!(restored.immutable);
+
+        let legacy = deserialize_channel(r#"{"text":"hello","files":[]}"#.to_string());
+        assert!(!legacy.immutable);
+        assert_eq!(legacy.data.text, "hello");
+    }
+
+    #[test]
+    fn strict_deserialization_has_no_plain_text_fallback() {
+        assert!(matches!(
+            deserialize_channel_strict("bare text"),
+            Err(AppError::Serialization(_))
+        ));
+        let record = deserialize_channel_strict(r#"{"text":"hello","files":[]}"#).unwrap();
+        assert_eq!(record.data.text, "hello");
+        assert_eq!(
+            deserialize_channel("bare text".into()).data.text,
+            "bare text"
+        );
+    }
+
+    #[test]
+    fn ttl_below_the_floor_is_rejected() {
+        assert_eq!(
+            resolve_channel_ttl(Some(300), 60, 900, TtlPolicy::Reject).unwrap(),
+            Some(300)
+        );
+        assert_eq!(
+            resolve_channel_ttl(None, 60, 900, TtlPolicy::Reject).unwrap(),
+            None
+        );
+        assert!(matches!(
+            resolve_channel_ttl(Some(1), 60, 900, TtlPolicy::Reject),
+            Err(AppError::InvalidTtl { min: 60, max: 900 })
+        ));
+    }
+
+    #[test]
+    fn ttl_outside_the_range_is_clamped_when_configured() {
+        assert_eq!(
+            resolve_channel_ttl(Some(1), 60, 900, TtlPolicy::Clamp).unwrap(),
+            Some(60)
+        );
+        assert_eq!(
+            resolve_channel_ttl(Some(10_000), 60, 900, TtlPolicy::Clamp).unwrap(),
+            Some(900)
+        );
+    }
+
+    #[test]
+    fn max_reads_must_allow_at_least_one_read() {
+        assert!(validate_max_reads(None).is_ok());
+        assert!(validate_max_reads(Some(1)).is_ok());
+        assert!(matches!(
+            validate_max_reads(Some(0)),
+            Err(AppError::InvalidMaxReads)
+        ));
+    }
+
+    #[test]
+    fn metadata_is_bounded_by_entry_count_and_entry_length() {
+        let entries = |count: usize| -> HashMap<String, String> {
+            (0..count).map(|n| (format!("k{n}"), "v".into())).collect()
+        };
+        assert!(validate_metadata(Some(&entries(3)), 3).is_ok());
+        assert!(matches!(
+            validate_metadata(Some(&entries(4)), 3),
+            Err(AppError::TooManyMetadataEntries(3))
+        ));
+
+        for (key, value) in [
+            ("k".repeat(MAX_METADATA_KEY_BYTES + 1), "v".to_owned()),
+            ("k".to_owned(), "v".repeat(MAX_METADATA_VALUE_BYTES + 1)),
+        ] {
+            let long = HashMap::from([(key, value)]);
+            assert!(matches!(
+                validate_metadata(Some(&long), 3),
+                Err(AppError::MetadataEntryTooLong)
+            ));
+        }
+        assert!(validate_metadata(None, 0).is_ok());
+    }
+
+    #[test]
+    fn channel_id_validation_accepts_generated_and_custom_ids() {
+        assert!(validate_channel_id(&generate_channel_id()).is_ok());
+        assert!(validate_channel_id("release-2024_notes").is_ok());
+    }
+
+    #[test]
+    fn channel_id_validation_rejects_unsafe_ids() {
+        for id in ["", "a/b", "has space", "colon:key", &"x".repeat(65)] {
+            assert!(
+                matches!(validate_channel_id(id), Err(AppError::InvalidChannelId)),
+                "{id:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_password_hash_is_rejected_at_store_time() {
+        let record = StoredChannel {
+            password_hash: Some(String::new()),
+            ..StoredChannel::default()
+        };
+        assert!(matches!(
+            serialize_channel(&record),
+            Err(AppError::EmptyPasswordHash)
+        ));
+    }
+
+    #[test]
+    fn empty_password_hash_never_verifies() {
+        assert!(!verify_channel_password(Some(""), Some("")));
+        assert!(!verify_channel_password(Some(""), None));
+        assert!(verify_channel_password(None, None));
+    }
+
+    #[test]
+    fn preview_never_splits_multibyte_characters() {
+        assert_eq!(preview_text("héllo", 2), ("hé", true));
+        assert_eq!(preview_text("日本語テキスト", 3), ("日本語", true));
+        assert_eq!(preview_text("👋🏽 hi", 1), ("👋", true));
+        assert_eq!(preview_text("short", 5), ("short", false));
+        assert_eq!(preview_text("", 0), ("", false));
+    }
+
+    #[test]
+    fn channel_comparison_reports_text_and_file_differences() {
+        let base = ChannelData {
+            text: "same".into(),
+            files: vec![blob("text/plain", b"one"), blob("image/png", b"two")],
+        };
+        let mut reordered = base.clone();
+        reordered.files.reverse();
+        reordered.files[0].id = "renamed-id".into();
+        assert_eq!(compare_channel_data(&base, &reordered), (true, true));
+
+        let mut text_differs = base.clone();
+        text_differs.text = "other".into();
+        assert_eq!(compare_channel_data(&base, &text_differs), (false, true));
+
+        let mut files_differ = base.clone();
+        files_differ.files[1] = blob("image/png", b"three");
+        assert_eq!(compare_channel_data(&base, &files_differ), (true, false));
+    }
+
+    #[test]
+    fn remove_files_reports_removed_and_missing_ids() {
+        let file = |id: &str| ChannelFile {
+            id: id.into(),
+            ..ChannelFile::default()
+        };
+        let mut data = ChannelData {
+            text: String::new(),
+            files: vec![file("a"), file("b"), file("c")],
+        };
+        let ids = ["a", "x", "c", "a"].map(String::from);
+
+        let (removed, not_found) = remove_files(&mut data, &ids);
+
+        assert_eq!(removed, ["a", "c"]);
+        assert_eq!(not_found, ["x"]);
+        assert_eq!(data.files.len(), 1);
+        assert_eq!(data.files[0].id, "b");
+    }
+
+    fn blob(mime_type: &str, bytes: &[u8]) -> ChannelFile {
+        ChannelFile {
+            id: "f".into(),
+            mime_type: mime_type.into(),
+            size: bytes.len() as u64,
+            data_base64: BASE64_ENGINE.encode(bytes),
+            ..ChannelFile::default()
+        }
+    }
+
+    #[test]
+    fn large_text_blob_is_stored_compressed_and_restored() {
+        let text = "log line repeated over and over\n".repeat(1_000);
+        let original = BASE64_ENGINE.encode(&text);
+        let mut data = ChannelData {
+            text: String::new(),
+            files: vec![blob("text/plain", text.as_bytes())],
+        };
+
+        compress_blobs(&mut data, 1024).unwrap();
+        assert_eq!(data.files[0].compression, Some(BlobCompression::Gzip));
+        assert!(data.files[0].data_base64.len() < original.len());
+
+        decompress_blobs(&mut data).unwrap();
+        assert_eq!(data.files[0].compression, None);
+        assert_eq!(data.files[0].data_base64, original);
+    }
+
+    #[test]
+    fn jpeg_and_small_blobs_are_not_compressed() {
+        let payload = vec![0u8; 4096];
+        let mut data = ChannelData {
+            text: String::new(),
+            files: vec![blob("image/jpeg", &payload), blob("text/plain", b"tiny")],
+        };
+
+        compress_blobs(&mut data, 1024).unwrap();
+        assert!(data.files.iter().all(|file| file.compression.is_none()));
+    }
+
+    #[test]
+    fn stored_file_verification_accepts_valid_base64() {
+        let data = ChannelData {
+            text: String::new(),
+            files: vec![blob("text/plain", b"hello")],
+        };
+        assert!(verify_stored_files(&data).is_ok());
+    }
+
+    #[test]
+    fn stored_file_verification_flags_corrupt_base64() {
+        let mut file = blob("text/plain", b"hello");
+        file.data_base64 = "not*base64!".into();
+        let data = ChannelData {
+            text: String::new(),
+            files: vec![file],
+        };
+        assert!(matches!(
+            verify_stored_files(&data),
+            Err(AppError::IntegrityError)
+        ));
+    }
+
+    #[test]
+    fn client_supplied_compression_marker_is_rejected() {
+        let mut file = blob("text/plain", b"hello");
+        file.compression = Some(BlobCompression::Gzip);
+        let data = ChannelData {
+            text: String::new(),
+            files: vec![file],
+        };
+        assert!(validate_channel_data(&data).is_err());
+    }
+
+    fn file_json() -> serde_json::Value {
+        serde_json::json!({
+            "id": "f", "name": "a", "mime_type": "text/plain", "size": 0, "data_base64": ""
+        })
+    }
+
+    #[test]
+    fn oversized_files_array_is_capped_while_parsing() {
+        let files = vec![file_json(); MAX_FILES_PER_CHANNEL * 40];
+        let data: ChannelData =
+            serde_json::from_value(serde_json::json!({ "text": "", "files": files })).unwrap();
+        assert_eq!(data.files.len(), MAX_FILES_PER_CHANNEL + 1);
+        assert!(matches!(
+            validate_channel_data(&data),
+            Err(AppError::TooManyFiles)
+        ));
+
+        let within: ChannelData = serde_json::from_value(
+            serde_json::json!({ "files": vec![file_json(); MAX_FILES_PER_CHANNEL] }),
+        )
+        .unwrap();
+        assert_eq!(within.files.len(), MAX_FILES_PER_CHANNEL);
+        assert!(validate_channel_data(&within).is_ok());
+    }
+
+    #[test]
+    fn decoded_length_matches_a_full_decode() {
+        // Lengths around every padding case, and past several scratch chunks.
+        let lengths = (0..=20).chain([3071, 3072, 3073, 3074, 10_000]);
+        for len in lengths {
+            let raw: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+            let encoded = BASE64_ENGINE.encode(&raw);
+            let decoded = BASE64_ENGINE.decode(&encoded).unwrap();
+            assert_eq!(decoded_base64_len(&encoded), Some(decoded.len()), "{len}");
+        }
+    }
+
+    #[test]
+    fn decoded_length_rejects_what_decode_rejects() {
+        let padded_early = format!("{}AA==", "A".repeat(4 * 1024 - 4)) + "AAAA";
+        for input in [
+            "A",
+            "AA",
+            "AA=A",
+            "AB==",
+            "!!!!",
+            "QQ== ",
+            padded_early.as_str(),
+        ] {
+            assert!(BASE64_ENGINE.decode(input).is_err(), "{input}");
+            assert_eq!(decoded_base64_len(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn unnamed_files_get_generated_ids_and_names() {
+        let png = BASE64_ENGINE.encode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        let mut data = ChannelData {
+            text: String::new(),
+            files: vec![
+                ChannelFile {
+                    name: "kept.bin".into(),
+                    id: "kept".into(),
+                    ..ChannelFile::default()
+                },
+                ChannelFile {
+                    mime_type: "application/octet-stream".into(),
+                    data_base64: png,
+                    ..ChannelFile::default()
+                },
+                ChannelFile {
+                    mime_type: "text/plain; charset=utf-8".into(),
+                    ..ChannelFile::default()
+                },
+                ChannelFile::default(),
+            ],
+        };
+        name_unnamed_files(&mut data);
+
+        let names: Vec<&str> = data.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, ["kept.bin", "file-2.png", "file-3.txt", "file-4"]);
+        assert_eq!(data.files[0].id, "kept");
+        assert!(data.files[1..].iter().all(|file| !file.id.is_empty()));
+        assert_ne!(data.files[1].id, data.files[2].id);
+    }
+
+    #[test]
+    fn file_ids_follow_the_policy() {
+        let data = |ids: &[&str]| ChannelData {
+            text: String::new(),
+            files: ids
+                .iter()
+                .map(|id| ChannelFile {
+                    id: (*id).into(),
+                    ..ChannelFile::default()
+                })
+                .collect(),
+        };
+        let uuid = "6f1c2a9e-0d4b-4c8e-9a57-3b2f1e0d9c8a";
+
+        let mut any = data(&["notes"]);
+        assert!(apply_file_id_policy(&mut any, FileIdPolicy::Any).is_ok());
+        let mut strict = data(&[uuid, "6f1c2a9e0d4b4c8e9a573b2f1e0d9c8a"]);
+        assert!(apply_file_id_policy(&mut strict, FileIdPolicy::Uuid).is_ok());
+        let mut rejected = data(&[uuid, "notes"]);
+        assert!(matches!(
+            apply_file_id_policy(&mut rejected, FileIdPolicy::Uuid),
+            Err(AppError::FileIdNotUuid(id)) if id == "notes"
+        ));
+
+        let mut generated = data(&["notes", uuid]);
+        apply_file_id_policy(&mut generated, FileIdPolicy::Generate).unwrap();
+        assert!(
+            generated
+                .files
+                .iter()
+                .all(|file| file.id != "notes" && file.id != uuid)
+        );
+        assert!(apply_file_id_policy(&mut generated, FileIdPolicy::Uuid).is_ok());
+    }
+
+    #[test]
+    fn mime_types_map_to_preview_categories() {
+        for (mime_type, category) in [
+            ("image/png", FileCategory::Image),
+            ("IMAGE/SVG+XML", FileCategory::Image),
+            ("text/plain; charset=utf-8", FileCategory::Text),
+            ("application/json", FileCategory::Text),
+            ("application/ld+json", FileCategory::Text),
+            ("audio/mpeg", FileCategory::Audio),
+            ("video/mp4", FileCategory::Video),
+            ("application/pdf", FileCategory::Other),
+            ("application/octet-stream", FileCategory::Other),
+            ("", FileCategory::Other),
+        ] {
+            assert_eq!(FileCategory::of(mime_type), category, "{mime_type}");
+            assert_eq!(category.previewable(), category != FileCategory::Other);
+        }
+    }
+
+    #[test]
+    fn content_requirements_name_the_missing_part() {
+        let data = |text: &str, files: usize| ChannelData {
+            text: text.into(),
+            files: vec![ChannelFile::default(); files],
+        };
+        let check = |data: &ChannelData, requirement| {
+            check_content_requirement(data, requirement).map_err(|err| err.to_string())
+        };
+
+        assert_eq!(check(&data("", 0), ContentRequirement::Nothing), Ok(()));
+        assert_eq!(check(&data("hi", 0), ContentRequirement::Text), Ok(()));
+        assert_eq!(
+            check(&data(" \n", 1), ContentRequirement::Text),
+            Err("channel text must not be empty".into())
+        );
+        assert_eq!(check(&data("", 1), ContentRequirement::Files), Ok(()));
+        assert_eq!(
+            check(&data("hi", 0), ContentRequirement::Files),
+            Err("channel must include at least one file".into())
+        );
+        assert_eq!(check(&data("", 1), ContentRequirement::Either), Ok(()));
+        assert_eq!(check(&data("hi", 0), ContentRequirement::Either), Ok(()));
+        assert_eq!(
+            check(&data(" ", 0), ContentRequirement::Either),
+            Err("channel must include text or at least one file".into())
+        );
+        assert_eq!(check(&data("hi", 1), ContentRequirement::Both), Ok(()));
+        assert_eq!(
+            check(&data("", 1), ContentRequirement::Both),
+            Err("channel text must not be empty".into())
+        );
+        assert_eq!(
+            check(&data("hi", 0), ContentRequirement::Both),
+            Err("channel must include at least one file".into())
+        );
+    }
+
+    #[test]
+    fn every_storage_format_reads_and_is_rewritten_in_the_current_one() {
+        let current = serialize_channel(&StoredChannel {
+            password_hash: Some("hash".into()),
+            version: 3,
+            data: ChannelData {
+                text: "hello".into(),
+                files: Vec::new(),
+            },
+            ..StoredChannel::default()
+        })
+        .unwrap();
+        let formats = [
+            ("hello".to_string(), PLAIN_TEXT_FORMAT, None, 0),
+            (
+                r#"{"password_hash":"hash","text":"hello","files":[]}"#.to_string(),
+                UNVERSIONED_JSON_FORMAT,
+                Some("hash"),
+                0,
+            ),
+            (current, CHANNEL_FORMAT_VERSION, Some("hash"), 3),
+        ];
+
+        for (raw, format, password_hash, version) in formats {
+            let record = deserialize_channel(raw);
+            assert_eq!(record.format, Some(format));
+            assert_eq!(record.password_hash.as_deref(), password_hash);
+            assert_eq!(record.version, version);
+            assert_eq!(record.data.text, "hello");
+
+            let rewritten = serialize_channel(&record).unwrap();
+            let json: serde_json::Value = serde_json::from_str(&rewritten).unwrap();
+            assert_eq!(json["format"], CHANNEL_FORMAT_VERSION);
+            let reread = deserialize_channel(rewritten);
+            assert_eq!(reread.format, Some(CHANNEL_FORMAT_VERSION));
+            assert_eq!(reread.password_hash.as_deref(), password_hash);
+            assert_eq!(reread.version, version);
+            assert_eq!(reread.data.text, "hello");
+        }
+    }
 }
@@ -1,24 +1,493 @@
-use std::{net::SocketAddr, path::Path, time::Duration};
+use std::{cmp::Ordering, fmt, net::SocketAddr, path::Path, str::FromStr, time::Duration};
 
-use crate::error::AppError;
+use axum::http::HeaderName;
+
+use crate::{
+    access_log::AccessLogSink,
+    error::AppError,
+    rate_limit::{RateLimit, RateLimits},
+    webhook::HttpTarget,
+};
 
 pub const DEFAULT_CHANNEL_TTL_SECONDS: u64 = 15 * 60; // 15 minutes
 pub const MAX_CHANNEL_BYTES: usize = 100 * 1024 * 1024; // 100 MiB
-pub const MAX_REQUEST_BYTES: usize = 200 * 1024 * 1024; // allow headroom for base64 expansion
+pub const MAX_FILES_PER_CHANNEL: usize = 256;
+/// Combined size of a channel's metadata keys and values.
+pub const MAX_METADATA_BYTES: usize = 4 * 1024;
+pub const MAX_METADATA_KEY_BYTES: usize = 128;
+pub const MAX_METADATA_VALUE_BYTES: usize = 1024;
+pub const DEFAULT_MAX_METADATA_ENTRIES: usize = 32;
+pub const MAX_TAG_BYTES: usize = 64;
+pub const DEFAULT_MAX_CHANNEL_TAGS: usize = 8;
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 200 * 1024 * 1024; // allow headroom for base64 expansion
+pub const DEFAULT_MAX_SUBSCRIBERS_PER_CHANNEL: u32 = 100;
+pub const DEFAULT_LIVE_MESSAGE_RATE: u32 = 20;
+pub const DEFAULT_LIVE_MAX_FILE_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_WRITE_LOCK_TTL_MS: u64 = 5_000;
+pub const DEFAULT_COMPRESS_BLOB_THRESHOLD_BYTES: usize = 64 * 1024;
+pub const DEFAULT_READY_CACHE_MS: u64 = 1_000;
+pub const DEFAULT_STALE_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024; // 64 MiB
+pub const DEFAULT_MIN_CHANNEL_TTL_SECONDS: u64 = 60;
+pub const DEFAULT_PREVIEW_CHARS: usize = 200;
+/// Most earlier versions `HISTORY_VERSIONS` may keep per channel.
+pub const MAX_HISTORY_VERSIONS: usize = 20;
+pub const DEFAULT_HISTORY_MAX_BYTES: usize = 256 * 1024;
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
+/// Types a browser would render with script or markup when served inline.
+pub const DEFAULT_RISKY_MIME_TYPES: &[&str] = &[
+    "application/javascript",
+    "application/xhtml+xml",
+    "application/xml",
+    "image/svg+xml",
+    "text/html",
+    "text/javascript",
+    "text/xml",
+];
+pub const DEFAULT_IMMUTABLE_CACHE_MAX_AGE_SECONDS: u64 = 60 * 60;
+pub const DEFAULT_MAX_BULK_CREATE: usize = 100;
+pub const DEFAULT_MAX_PASSWORD_BYTES: usize = 256;
+pub const HSTS_MAX_AGE_SECONDS: u64 = 365 * 24 * 60 * 60;
+pub const DEFAULT_CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
+pub const DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS: u64 = 5_000;
+pub const DEFAULT_ACCESS_LOG_BUFFER: usize = 1024;
+pub const DEFAULT_CREATE_APPROVAL_TIMEOUT_MS: u64 = 2000;
+pub const DEFAULT_RETRY_AFTER_SECONDS: u64 = 5;
+pub const DEFAULT_MAX_BACKGROUND_TASKS: usize = 1024;
+
+/// Where channels live. `memory` keeps everything in-process, which suits
+/// local dev and tests but is lost on restart and not shared between instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageBackend {
+    #[default]
+    Redis,
+    Memory,
+}
+
+/// What happens to a requested channel TTL outside the allowed range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TtlPolicy {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+/// What every uploaded channel must contain, beyond the size limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentRequirement {
+    #[default]
+    Nothing,
+    /// Non-blank text.
+    Text,
+    /// At least one file.
+    Files,
+    /// Non-blank text or at least one file.
+    Either,
+    /// Non-blank text and at least one file.
+    Both,
+}
+
+/// What uploaded file ids must look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileIdPolicy {
+    /// Whatever the client sends.
+    #[default]
+    Any,
+    /// A UUID, in any of its usual spellings; anything else is refused.
+    Uuid,
+    /// Client ids are replaced with fresh UUIDs on every write.
+    Generate,
+}
+
+/// A dotted numeric version such as `2.4.1`. Missing trailing parts count as
+/// zero, so `2.4` and `2.4.0` are equal.
+#[derive(Debug, Clone)]
+pub struct ClientVersion(Vec<u64>);
+
+impl FromStr for ClientVersion {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        raw.trim()
+            .trim_start_matches(['v', 'V'])
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        f.write_str(&parts.join("."))
+    }
+}
+
+impl Ord for ClientVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        let part = |version: &Self, index: usize| version.0.get(index).copied().unwrap_or(0);
+        (0..len)
+            .map(|index| part(self, index).cmp(&part(other, index)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for ClientVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ClientVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for ClientVersion {}
+
+/// An absolute `http(s)` URL that links are generated under, such as
+/// `https://share.example.com` or `https://example.com/lynkc`. It is kept
+/// without a trailing slash and may carry no credentials, query or fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicBaseUrl(String);
+
+impl PublicBaseUrl {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for PublicBaseUrl {
+    type Err = AppError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidPublicBaseUrl(raw.to_owned());
+        let url = raw.trim().trim_end_matches('/');
+        let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+        let scheme = scheme.to_ascii_lowercase();
+        if scheme != "http" && scheme != "https" {
+            return Err(invalid());
+        }
+        let host = rest.split('/').next().unwrap_or_default();
+        if host.is_empty()
+            || rest
+                .chars()
+                .any(|ch| !ch.is_ascii_graphic() || matches!(ch, '?' | '#' | '@' | '\\'))
+        {
+            return Err(invalid());
+        }
+        Ok(Self(format!("{scheme}://{rest}")))
+    }
+}
+
+impl fmt::Display for PublicBaseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub bind_address: SocketAddr,
-    pub redis_url: String,
+    /// One URL per shard; channels are spread across them by id.
+    pub redis_urls: Vec<String>,
+    /// Read replica per shard, in `redis_urls` order (empty for none). Only
+    /// fetches and metadata reads go there.
+    pub redis_replica_urls: Vec<String>,
+    /// Credentials applied on top of the URLs, so secrets injected separately
+    /// never have to be embedded in (and logged with) a URL.
+    pub redis_username: Option<String>,
+    pub redis_password: Option<String>,
+    /// Prepended to every storage key, so several deployments can share a
+    /// backend.
+    pub key_prefix: String,
+    /// Prefixes used before `key_prefix`; channels still stored under them are
+    /// found on access and moved to the current prefix.
+    pub legacy_key_prefixes: Vec<String>,
+    pub storage_backend: StorageBackend,
     pub channel_ttl: Duration,
+    /// Shortest TTL a client may request; `channel_ttl` is the longest.
+    pub min_channel_ttl: Duration,
+    pub ttl_policy: TtlPolicy,
+    pub content_requirement: ContentRequirement,
+    pub file_id_policy: FileIdPolicy,
+    pub omit_file_bodies_by_default: bool,
+    /// Give uploaded files with an empty id or name a generated one.
+    pub auto_name_files: bool,
+    pub max_subscribers_per_channel: u32,
+    /// Messages per second one live connection may send, with a burst of the
+    /// same size; zero disables the limit.
+    pub live_message_rate: u32,
+    /// How often an open live connection pushes its channel's expiry out
+    /// again; `None` lets channels expire mid-session.
+    pub live_ttl_refresh: Option<Duration>,
+    /// Size of the binary frames a live connection receives a requested file
+    /// in; `None` turns file requests over the socket off.
+    pub live_file_chunk_bytes: Option<usize>,
+    /// Largest file a live connection may request.
+    pub live_max_file_bytes: usize,
+    pub write_lock: bool,
+    pub write_lock_ttl: Duration,
+    pub channel_compress_blobs: bool,
+    /// Rewrite records stored in an older format whenever one is read, so a
+    /// deployment converges on the current format without waiting for edits.
+    pub upgrade_format_on_read: bool,
+    /// Reset a channel's TTL whenever its content is read. Off, a channel only
+    /// lives longer through writes and explicit touches.
+    pub refresh_ttl_on_read: bool,
+    /// Refuse stored values that are not JSON records instead of serving them
+    /// as plain text, once no bare-text channels are left.
+    pub strict_deserialize: bool,
+    pub compress_blob_threshold_bytes: usize,
+    pub validate_on_read: bool,
+    pub strict_json: bool,
+    /// Route `/path/` only where `/path/` itself is registered, instead of
+    /// treating it as `/path`.
+    pub strict_trailing_slash: bool,
+    /// Wrap JSON responses as `{"data": ...}` or `{"error": ...}`.
+    pub response_envelope: bool,
+    /// Accept the channel password as `?pw=` on fetches, for shareable links.
+    pub allow_password_query: bool,
+    /// Longest password accepted from request headers.
+    pub max_password_bytes: usize,
+    /// Answer wrong passwords and read-out channels with the same `404` as
+    /// unknown ids, so valid ids cannot be enumerated.
+    pub hide_channel_existence: bool,
+    /// Minimum time taken by a `401`/`404`/`410` response, plus up to half
+    /// again of random jitter, so failures can't be told apart by timing.
+    pub auth_failure_delay: Option<Duration>,
+    /// Send HSTS and refuse requests a proxy reports (via `x-forwarded-proto`)
+    /// as plain HTTP.
+    pub require_https: bool,
+    /// Oldest `x-client-version` allowed to write; `None` accepts any client.
+    pub min_client_version: Option<ClientVersion>,
+    /// Where generated links point by default; `None` leaves them out.
+    pub public_base_url: Option<PublicBaseUrl>,
+    /// Further base URLs a request may pick with `x-base-url`, for deployments
+    /// served under several hostnames. Anything else is refused.
+    pub public_base_url_allowlist: Vec<PublicBaseUrl>,
+    /// Longest `max-age` sent for immutable channels; zero turns caching off.
+    pub immutable_cache_max_age: Duration,
+    /// Mark cacheable responses `public` so shared caches (CDNs) may keep them.
+    /// Those serve the content without checking the channel password.
+    pub immutable_cache_public: bool,
+    /// Most channels one bulk create request may ask for.
+    pub max_bulk_create: usize,
+    /// Most metadata pairs one channel may carry.
+    pub max_metadata_entries: usize,
+    /// Most tags one channel may carry.
+    pub max_channel_tags: usize,
+    /// Largest request body accepted; bodies declared larger are refused
+    /// before any of them is read.
+    pub max_request_bytes: usize,
+    /// Characters of text returned by the preview endpoint.
+    pub preview_chars: usize,
+    /// Lowercased MIME types the raw-download endpoint never serves as
+    /// declared; they go out as `application/octet-stream` attachments.
+    pub risky_mime_types: Vec<String>,
+    /// How long a successful readiness probe is reused; zero disables caching.
+    pub ready_cache: Duration,
+    /// Answer fetches from the last copy seen on this instance while storage
+    /// fails, instead of with an error.
+    pub serve_stale_on_error: bool,
+    /// Content kept for `serve_stale_on_error`, across all channels.
+    pub stale_cache_max_bytes: usize,
+    /// Consecutive storage failures that open the circuit; zero disables it.
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_cooldown: Duration,
+    /// `Retry-After` sent with `429`/`503` answers that have no window of their
+    /// own to wait out: shed creates, full subscriber slots, failed readiness.
+    /// An open circuit answers with the rest of its cooldown instead.
+    pub retry_after: Duration,
+    /// Rolling storage latency above which channel creation answers `503`;
+    /// `None` never sheds.
+    pub shed_create_latency: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    /// How long after expiry a channel can still be fetched, once; `None`
+    /// keeps no copy to serve it from.
+    pub expired_grace: Option<Duration>,
+    /// When set, creates answer with a single-use `password_token` redeemable
+    /// at `/api/channels/:id/password` for this long instead of the password.
+    pub password_token_ttl: Option<Duration>,
+    /// Earlier versions of the text kept per channel for `/history` and
+    /// `/revert`, at most `MAX_HISTORY_VERSIONS`; zero keeps none.
+    pub history_versions: usize,
+    /// Bytes of earlier text kept per channel; the oldest versions go first.
+    pub history_max_bytes: usize,
+    /// How often to delete counters left behind by vanished channels; `None`
+    /// never sweeps.
+    pub orphan_sweep_interval: Option<Duration>,
+    pub admin_token: Option<String>,
+    /// Requests per second the whole instance takes before shedding the rest
+    /// with `503`; `None` takes them all.
+    pub global_rate_limit: Option<u32>,
+    /// Per-endpoint request limits; all unlimited by default.
+    pub rate_limits: RateLimits,
+    /// Receives an event for every channel create, read, update and delete;
+    /// `None` sends none.
+    pub access_log_sink: Option<AccessLogSink>,
+    /// Asked to approve every channel before it is created; `None` creates
+    /// without asking.
+    pub create_approval_url: Option<HttpTarget>,
+    /// How long the approval hook may take before the create is refused.
+    pub create_approval_timeout: Duration,
+    /// Log every channel lifecycle event at info level.
+    pub log_channel_events: bool,
+    /// Receives a JSON `POST` for every channel lifecycle event; `None` sends
+    /// none.
+    pub channel_events_url: Option<HttpTarget>,
+    /// Count channel lifecycle events and report the totals in `/ready`.
+    pub count_channel_events: bool,
+    /// Events queued for the sink before new ones are dropped.
+    pub access_log_buffer: usize,
+    /// Background tasks alive at once; work beyond it is dropped and counted.
+    pub max_background_tasks: usize,
+    /// Header carrying the request id: taken from the request when a proxy
+    /// set one, generated otherwise, and echoed on every response.
+    pub request_id_header: HeaderName,
+}
+
+impl Default for AppConfig {
+    /// The settings `from_env` falls back to when nothing is configured.
+    fn default() -> Self {
+        Self {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            redis_urls: vec!["redis://127.0.0.1:6379".to_string()],
+            redis_replica_urls: Vec::new(),
+            redis_username: None,
+            redis_password: None,
+            key_prefix: String::new(),
+            legacy_key_prefixes: Vec::new(),
+            storage_backend: StorageBackend::default(),
+            channel_ttl: Duration::from_secs(DEFAULT_CHANNEL_TTL_SECONDS),
+            min_channel_ttl: Duration::from_secs(DEFAULT_MIN_CHANNEL_TTL_SECONDS),
+            ttl_policy: TtlPolicy::default(),
+            content_requirement: ContentRequirement::default(),
+            file_id_policy: FileIdPolicy::default(),
+            omit_file_bodies_by_default: false,
+            auto_name_files: false,
+            max_subscribers_per_channel: DEFAULT_MAX_SUBSCRIBERS_PER_CHANNEL,
+            live_message_rate: DEFAULT_LIVE_MESSAGE_RATE,
+            live_ttl_refresh: None,
+            live_file_chunk_bytes: None,
+            live_max_file_bytes: DEFAULT_LIVE_MAX_FILE_BYTES,
+            write_lock: false,
+            write_lock_ttl: Duration::from_millis(DEFAULT_WRITE_LOCK_TTL_MS),
+            channel_compress_blobs: false,
+            upgrade_format_on_read: false,
+            refresh_ttl_on_read: true,
+            strict_deserialize: false,
+            compress_blob_threshold_bytes: DEFAULT_COMPRESS_BLOB_THRESHOLD_BYTES,
+            validate_on_read: false,
+            strict_json: false,
+            strict_trailing_slash: false,
+            response_envelope: false,
+            allow_password_query: false,
+            max_password_bytes: DEFAULT_MAX_PASSWORD_BYTES,
+            hide_channel_existence: false,
+            auth_failure_delay: None,
+            require_https: false,
+            min_client_version: None,
+            public_base_url: None,
+            public_base_url_allowlist: Vec::new(),
+            immutable_cache_max_age: Duration::from_secs(DEFAULT_IMMUTABLE_CACHE_MAX_AGE_SECONDS),
+            immutable_cache_public: false,
+            max_bulk_create: DEFAULT_MAX_BULK_CREATE,
+            max_metadata_entries: DEFAULT_MAX_METADATA_ENTRIES,
+            max_channel_tags: DEFAULT_MAX_CHANNEL_TAGS,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            preview_chars: DEFAULT_PREVIEW_CHARS,
+            risky_mime_types: DEFAULT_RISKY_MIME_TYPES
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect(),
+            ready_cache: Duration::from_millis(DEFAULT_READY_CACHE_MS),
+            serve_stale_on_error: false,
+            stale_cache_max_bytes: DEFAULT_STALE_CACHE_MAX_BYTES,
+            circuit_breaker_threshold: DEFAULT_CIRCUIT_BREAKER_THRESHOLD,
+            circuit_breaker_cooldown: Duration::from_millis(DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS),
+            retry_after: Duration::from_secs(DEFAULT_RETRY_AFTER_SECONDS),
+            shed_create_latency: None,
+            max_lifetime: None,
+            expired_grace: None,
+            password_token_ttl: None,
+            history_versions: 0,
+            history_max_bytes: DEFAULT_HISTORY_MAX_BYTES,
+            orphan_sweep_interval: None,
+            admin_token: None,
+            global_rate_limit: None,
+            rate_limits: RateLimits::default(),
+            access_log_sink: None,
+            create_approval_url: None,
+            create_approval_timeout: Duration::from_millis(DEFAULT_CREATE_APPROVAL_TIMEOUT_MS),
+            log_channel_events: false,
+            channel_events_url: None,
+            count_channel_events: false,
+            access_log_buffer: DEFAULT_ACCESS_LOG_BUFFER,
+            max_background_tasks: DEFAULT_MAX_BACKGROUND_TASKS,
+            request_id_header: HeaderName::from_static(DEFAULT_REQUEST_ID_HEADER),
+        }
+    }
 }
 
 impl AppConfig {
     pub fn from_env() -> Result<Self, AppError> {
         Self::load_env_file();
 
-        let redis_url =
-            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let redis_urls = std::env::var("REDIS_URLS")
+            .ok()
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|urls| !urls.is_empty())
+            .unwrap_or_else(|| {
+                vec![
+                    std::env::var("REDIS_URL")
+                        .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
+                ]
+            });
+
+        // Entries stay in place even when empty, to line up with the shards.
+        let redis_replica_urls = std::env::var("REDIS_REPLICA_URLS")
+            .ok()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| raw.split(',').map(|url| url.trim().to_owned()).collect())
+            .unwrap_or_default();
+
+        let redis_username = std::env::var("REDIS_USERNAME")
+            .ok()
+            .filter(|name| !name.is_empty());
+        let redis_password = std::env::var("REDIS_PASSWORD")
+            .ok()
+            .filter(|password| !password.is_empty());
+
+        let key_prefix = std::env::var("REDIS_KEY_PREFIX").unwrap_or_default();
+        // An empty entry stands for the unprefixed keyspace.
+        let mut legacy_key_prefixes = Vec::new();
+        if let Ok(raw) = std::env::var("LEGACY_KEY_PREFIXES") {
+            for prefix in raw.split(',').map(str::trim) {
+                if prefix != key_prefix && !legacy_key_prefixes.iter().any(|p| p == prefix) {
+                    legacy_key_prefixes.push(prefix.to_owned());
+                }
+            }
+        }
+
+        let storage_backend = match std::env::var("STORAGE_BACKEND")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "memory" => StorageBackend::Memory,
+            _ => StorageBackend::Redis,
+        };
 
         let host = std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
         let port = std::env::var("PORT")
@@ -40,13 +509,360 @@ impl AppConfig {
             .filter(|&ttl| ttl > 0)
             .unwrap_or(DEFAULT_CHANNEL_TTL_SECONDS);
 
+        let min_channel_ttl_seconds = std::env::var("MIN_CHANNEL_TTL_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ttl| ttl > 0)
+            .unwrap_or(DEFAULT_MIN_CHANNEL_TTL_SECONDS)
+            .min(channel_ttl_seconds);
+        let ttl_policy = match std::env::var("CHANNEL_TTL_POLICY")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "clamp" => TtlPolicy::Clamp,
+            _ => TtlPolicy::Reject,
+        };
+        let content_requirement = match std::env::var("REQUIRE_CHANNEL_CONTENT")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "text" => ContentRequirement::Text,
+            "files" => ContentRequirement::Files,
+            "either" => ContentRequirement::Either,
+            "both" => ContentRequirement::Both,
+            _ => ContentRequirement::Nothing,
+        };
+        let file_id_policy = match std::env::var("FILE_ID_POLICY")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "uuid" => FileIdPolicy::Uuid,
+            "generate" => FileIdPolicy::Generate,
+            _ => FileIdPolicy::Any,
+        };
+
+        let omit_file_bodies_by_default = env_flag("DEFAULT_OMIT_FILE_BODIES", false);
+        let auto_name_files = env_flag("AUTO_NAME_FILES", false);
+
+        let max_subscribers_per_channel = std::env::var("MAX_SUBSCRIBERS_PER_CHANNEL")
+            .ok()
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_SUBSCRIBERS_PER_CHANNEL);
+        let live_message_rate = std::env::var("LIVE_MESSAGE_RATE")
+            .ok()
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_LIVE_MESSAGE_RATE);
+        let live_ttl_refresh = std::env::var("LIVE_TTL_REFRESH_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+        let live_file_chunk_bytes = std::env::var("LIVE_FILE_CHUNK_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .filter(|&bytes| bytes > 0);
+        let live_max_file_bytes = std::env::var("LIVE_MAX_FILE_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_LIVE_MAX_FILE_BYTES);
+
+        let write_lock = env_flag("CHANNEL_WRITE_LOCK", false);
+        let write_lock_ttl_ms = std::env::var("WRITE_LOCK_TTL_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ttl| ttl > 0)
+            .unwrap_or(DEFAULT_WRITE_LOCK_TTL_MS);
+
+        let channel_compress_blobs = env_flag("CHANNEL_COMPRESS_BLOBS", false);
+        let upgrade_format_on_read = env_flag("UPGRADE_FORMAT_ON_READ", false);
+        let refresh_ttl_on_read = env_flag("REFRESH_TTL_ON_READ", true);
+        let strict_deserialize = env_flag("STRICT_DESERIALIZE", false);
+        let compress_blob_threshold_bytes = std::env::var("COMPRESS_BLOB_THRESHOLD_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_COMPRESS_BLOB_THRESHOLD_BYTES);
+
+        let validate_on_read = env_flag("VALIDATE_ON_READ", false);
+        let strict_json = env_flag("STRICT_JSON", false);
+        let strict_trailing_slash = env_flag("STRICT_TRAILING_SLASH", false);
+        let response_envelope = env_flag("RESPONSE_ENVELOPE", false);
+        let allow_password_query = env_flag("ALLOW_PASSWORD_QUERY", false);
+        let max_password_bytes = std::env::var("MAX_PASSWORD_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_PASSWORD_BYTES);
+        let hide_channel_existence = env_flag("HIDE_CHANNEL_EXISTENCE", false);
+        let require_https = env_flag("REQUIRE_HTTPS", false);
+        let min_client_version = std::env::var("MIN_CLIENT_VERSION")
+            .ok()
+            .and_then(|raw| raw.parse().ok());
+        let public_base_url = std::env::var("PUBLIC_BASE_URL")
+            .ok()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| raw.parse::<PublicBaseUrl>())
+            .transpose()?;
+        let public_base_url_allowlist = std::env::var("PUBLIC_BASE_URL_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|raw| !raw.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<PublicBaseUrl>, _>>()?;
+        let auth_failure_delay = std::env::var("AUTH_FAILURE_DELAY_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+        let immutable_cache_max_age_seconds = std::env::var("IMMUTABLE_CACHE_MAX_AGE_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_IMMUTABLE_CACHE_MAX_AGE_SECONDS);
+        let immutable_cache_public = env_flag("IMMUTABLE_CACHE_PUBLIC", false);
+        let max_bulk_create = std::env::var("MAX_BULK_CREATE")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_BULK_CREATE);
+        let max_metadata_entries = std::env::var("MAX_METADATA_ENTRIES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_METADATA_ENTRIES);
+        let max_channel_tags = std::env::var("MAX_CHANNEL_TAGS")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_CHANNEL_TAGS);
+        let max_request_bytes = std::env::var("MAX_REQUEST_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_REQUEST_BYTES);
+        let risky_mime_types = match std::env::var("RISKY_MIME_TYPES") {
+            Ok(raw) => raw
+                .split(',')
+                .map(|mime| mime.trim().to_ascii_lowercase())
+                .filter(|mime| !mime.is_empty())
+                .collect(),
+            Err(_) => DEFAULT_RISKY_MIME_TYPES
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect(),
+        };
+        let preview_chars = std::env::var("PREVIEW_CHARS")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_PREVIEW_CHARS);
+        let circuit_breaker_threshold = std::env::var("CIRCUIT_BREAKER_THRESHOLD")
+            .ok()
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_CIRCUIT_BREAKER_THRESHOLD);
+        let circuit_breaker_cooldown_ms = std::env::var("CIRCUIT_BREAKER_COOLDOWN_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS);
+        let retry_after_seconds = std::env::var("RETRY_AFTER_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&seconds| seconds > 0)
+            .unwrap_or(DEFAULT_RETRY_AFTER_SECONDS);
+        let ready_cache_ms = std::env::var("READY_CACHE_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_READY_CACHE_MS);
+        let serve_stale_on_error = env_flag("SERVE_STALE_ON_ERROR", false);
+        let stale_cache_max_bytes = std::env::var("STALE_CACHE_MAX_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_STALE_CACHE_MAX_BYTES);
+        let shed_create_latency = std::env::var("SHED_CREATE_LATENCY_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+
+        let max_lifetime = std::env::var("MAX_LIFETIME_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs);
+
+        let expired_grace = std::env::var("EXPIRED_GRACE_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs);
+
+        let history_versions = std::env::var("HISTORY_VERSIONS")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(MAX_HISTORY_VERSIONS);
+        let history_max_bytes = std::env::var("HISTORY_MAX_BYTES")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_HISTORY_MAX_BYTES);
+
+        let password_token_ttl = std::env::var("PASSWORD_TOKEN_TTL_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs);
+
+        let orphan_sweep_interval = std::env::var("ORPHAN_SWEEP_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs);
+
+        let admin_token = std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty());
+
+        let global_rate_limit = std::env::var("GLOBAL_RATE_LIMIT")
+            .ok()
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .filter(|&rate| rate > 0);
+        let rate_limit = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|raw| !raw.trim().is_empty())
+                .map(|raw| raw.parse::<RateLimit>())
+                .transpose()
+        };
+        let rate_limits = RateLimits {
+            create: rate_limit("RATE_LIMIT_CREATE")?,
+            fetch: rate_limit("RATE_LIMIT_FETCH")?,
+            update: rate_limit("RATE_LIMIT_UPDATE")?,
+            download: rate_limit("RATE_LIMIT_DOWNLOAD")?,
+        };
+
+        let access_log_sink = std::env::var("ACCESS_LOG_SINK")
+            .ok()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| raw.parse::<AccessLogSink>())
+            .transpose()?;
+        let create_approval_url = std::env::var("CREATE_APPROVAL_URL")
+            .ok()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| HttpTarget::parse(&raw).ok_or(AppError::InvalidApprovalUrl(raw)))
+            .transpose()?;
+        let create_approval_timeout_ms = std::env::var("CREATE_APPROVAL_TIMEOUT_MS")
+            .ok()
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|&ms| ms > 0)
+            .unwrap_or(DEFAULT_CREATE_APPROVAL_TIMEOUT_MS);
+        let log_channel_events = env_flag("LOG_CHANNEL_EVENTS", false);
+        let channel_events_url = std::env::var("CHANNEL_EVENTS_URL")
+            .ok()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| HttpTarget::parse(&raw).ok_or(AppError::InvalidChannelEventsUrl(raw)))
+            .transpose()?;
+        let count_channel_events = env_flag("COUNT_CHANNEL_EVENTS", false);
+        let access_log_buffer = std::env::var("ACCESS_LOG_BUFFER")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .filter(|&buffer| buffer > 0)
+            .unwrap_or(DEFAULT_ACCESS_LOG_BUFFER);
+        let max_background_tasks = std::env::var("MAX_BACKGROUND_TASKS")
+            .ok()
+            .and_then(|raw| raw.parse::<usize>().ok())
+            .filter(|&limit| limit > 0)
+            .unwrap_or(DEFAULT_MAX_BACKGROUND_TASKS);
+
+        let request_id_header = std::env::var("REQUEST_ID_HEADER")
+            .ok()
+            .and_then(|raw| HeaderName::from_bytes(raw.trim().to_ascii_lowercase().as_bytes()).ok())
+            .unwrap_or(HeaderName::from_static(DEFAULT_REQUEST_ID_HEADER));
+
         Ok(Self {
             bind_address,
-            redis_url,
+            redis_urls,
+            redis_replica_urls,
+            redis_username,
+            redis_password,
+            key_prefix,
+            legacy_key_prefixes,
+            storage_backend,
             channel_ttl: Duration::from_secs(channel_ttl_seconds),
+            min_channel_ttl: Duration::from_secs(min_channel_ttl_seconds),
+            ttl_policy,
+            content_requirement,
+            file_id_policy,
+            omit_file_bodies_by_default,
+            auto_name_files,
+            max_subscribers_per_channel,
+            live_message_rate,
+            live_ttl_refresh,
+            live_file_chunk_bytes,
+            live_max_file_bytes,
+            write_lock,
+            write_lock_ttl: Duration::from_millis(write_lock_ttl_ms),
+            channel_compress_blobs,
+            upgrade_format_on_read,
+            refresh_ttl_on_read,
+            strict_deserialize,
+            compress_blob_threshold_bytes,
+            validate_on_read,
+            strict_json,
+            strict_trailing_slash,
+            response_envelope,
+            allow_password_query,
+            max_password_bytes,
+            hide_channel_existence,
+            auth_failure_delay,
+            require_https,
+            min_client_version,
+            public_base_url,
+            public_base_url_allowlist,
+            immutable_cache_max_age: Duration::from_secs(immutable_cache_max_age_seconds),
+            immutable_cache_public,
+            max_bulk_create,
+            max_metadata_entries,
+            max_channel_tags,
+            max_request_bytes,
+            preview_chars,
+            risky_mime_types,
+            ready_cache: Duration::from_millis(ready_cache_ms),
+            serve_stale_on_error,
+            stale_cache_max_bytes,
+            circuit_breaker_threshold,
+            circuit_breaker_cooldown: Duration::from_millis(circuit_breaker_cooldown_ms),
+            retry_after: Duration::from_secs(retry_after_seconds),
+            shed_create_latency,
+            max_lifetime,
+            expired_grace,
+            password_token_ttl,
+            history_versions,
+            history_max_bytes,
+            orphan_sweep_interval,
+            admin_token,
+            global_rate_limit,
+            rate_limits,
+            access_log_sink,
+            create_approval_url,
+            create_approval_timeout: Duration::from_millis(create_approval_timeout_ms),
+            log_channel_events,
+            channel_events_url,
+            count_channel_events,
+            access_log_buffer,
+            max_background_tasks,
+            request_id_header,
         })
     }
 
+    /// The Redis URLs with any embedded password masked, safe to log.
+    pub fn redacted_redis_urls(&self) -> Vec<String> {
+        self.redis_urls
+            .iter()
+            .map(|url| redact_url_password(url))
+            .collect()
+    }
+
     fn load_env_file() {
         let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
         if let Some(workspace_root) = manifest_dir.parent() {
@@ -59,3 +875,33 @@ impl AppConfig {
         dotenvy::dotenv().ok();
     }
 }
+
+fn redact_url_password(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_owned();
+    };
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    let Some(at) = rest[..authority_end].rfind('@') else {
+        return url.to_owned();
+    };
+    let user = match rest[..at].split_once(':') {
+        Some((user, _password)) => user,
+        None => &rest[..at],
+    };
+    format!("{scheme}://{user}:***@{}", &rest[at + 1..])
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    std::env::var(name)
+        .ok()
+        .and_then(|raw| parse_flag(&raw))
+        .unwrap_or(default)
+}
+
+fn parse_flag(raw: &str) -> Option<bool> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
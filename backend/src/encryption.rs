@@ -0,0 +1,106 @@
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{channel::ChannelData, error::AppError};
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// `ChannelData` encrypted under a caller-held passphrase. The passphrase is
+/// never stored; the key is derived from it with Argon2id and a per-seal salt,
+/// and the data is sealed with ChaCha20-Poly1305.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedData {
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, AppError> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| AppError::UndecryptableChannel)?;
+    Ok(key)
+}
+
+pub fn seal_channel_data(data: &ChannelData, passphrase: &str) -> Result<SealedData, AppError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = serde_json::to_vec(data)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| AppError::UndecryptableChannel)?;
+
+    Ok(SealedData {
+        salt: BASE64_ENGINE.encode(salt),
+        nonce: BASE64_ENGINE.encode(nonce),
+        ciphertext: BASE64_ENGINE.encode(ciphertext),
+    })
+}
+
+/// Fails with `UndecryptableChannel` for a wrong passphrase or tampered data;
+/// the two are indistinguishable by design.
+pub fn open_channel_data(sealed: &SealedData, passphrase: &str) -> Result<ChannelData, AppError> {
+    let decode = |value: &str| {
+        BASE64_ENGINE
+            .decode(value)
+            .map_err(|_| AppError::UndecryptableChannel)
+    };
+    let salt = decode(&sealed.salt)?;
+    let nonce = decode(&sealed.nonce)?;
+    if nonce.len() != NONCE_LENGTH {
+        return Err(AppError::UndecryptableChannel);
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            decode(&sealed.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| AppError::UndecryptableChannel)?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open_channel_data, seal_channel_data};
+    use crate::{channel::ChannelData, error::AppError};
+
+    fn sample() -> ChannelData {
+        ChannelData {
+            text: "top secret".into(),
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sealed_data_round_trips_with_the_passphrase() {
+        let sealed = seal_channel_data(&sample(), "correct horse").unwrap();
+        assert!(!sealed.ciphertext.contains("top secret"));
+
+        let opened = open_channel_data(&sealed, "correct horse").unwrap();
+        assert_eq!(opened.text, "top secret");
+    }
+
+    #[test]
+    fn wrong_passphrase_cannot_open_sealed_data() {
+        let sealed = seal_channel_data(&sample(), "correct horse").unwrap();
+        assert!(matches!(
+            open_channel_data(&sealed, "battery staple"),
+            Err(AppError::UndecryptableChannel)
+        ));
+    }
+}
@@ -1,4 +1,10 @@
-use axum::{Json, http::StatusCode, response::IntoResponse};
+use std::time::Duration;
+
+use axum::{
+    Json,
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    response::IntoResponse,
+};
 use redis::RedisError;
 use serde::Serialize;
 use thiserror::Error;
@@ -7,6 +13,20 @@ use thiserror::Error;
 pub enum AppError {
     #[error("failed to parse bind address: {0}")]
     BindAddress(std::net::AddrParseError),
+    #[error("invalid public base url {0:?}: expected an absolute http(s) url")]
+    InvalidPublicBaseUrl(String),
+    #[error(
+        "invalid access log sink {0:?}: expected http(s)://host[:port]/path or udp://host:port"
+    )]
+    InvalidAccessLogSink(String),
+    #[error("invalid create approval url {0:?}: expected http(s)://host[:port]/path")]
+    InvalidApprovalUrl(String),
+    #[error("invalid channel events url {0:?}: expected http(s)://host[:port]/path")]
+    InvalidChannelEventsUrl(String),
+    #[error("x-base-url is not an allowed public base url")]
+    BaseUrlNotAllowed,
+    #[error("REDIS_REPLICA_URLS has {replicas} entries for {shards} shards")]
+    ReplicaCountMismatch { shards: usize, replicas: usize },
     #[error("redis error: {0}")]
     Redis(#[from] RedisError),
     #[error("io error: {0}")]
@@ -15,43 +35,272 @@ pub enum AppError {
     ChannelNotFound,
     #[error("invalid channel password")]
     InvalidChannelPassword,
+    #[error("invalid file password")]
+    InvalidFilePassword,
+    #[error("requested range lies outside the {0} bytes available")]
+    RangeNotSatisfiable(u64),
+    #[error("channel has no previous text to diff against")]
+    NoPreviousText,
+    #[error("version {0} is not in the channel's history")]
+    VersionNotInHistory(u64),
+    #[error("channel has reached its read limit")]
+    ChannelGone,
+    #[error("password token is invalid, used or expired")]
+    InvalidPasswordToken,
+    #[error("invalid channel id")]
+    InvalidChannelId,
+    #[error("ttl_seconds must be between {min} and {max}")]
+    InvalidTtl { min: u64, max: u64 },
+    #[error("max_reads must be at least 1")]
+    InvalidMaxReads,
+    #[error("channel text must not be empty")]
+    TextRequired,
+    #[error("channel must include at least one file")]
+    FileRequired,
+    #[error("channel must include text or at least one file")]
+    ContentRequired,
+    #[error("file id {0:?} is not a UUID")]
+    FileIdNotUuid(String),
+    #[error("channel metadata is too large")]
+    MetadataTooLarge,
+    #[error("channel metadata may have at most {0} entries")]
+    TooManyMetadataEntries(usize),
+    #[error(
+        "metadata keys may be at most {} bytes and values at most {} bytes",
+        crate::config::MAX_METADATA_KEY_BYTES,
+        crate::config::MAX_METADATA_VALUE_BYTES
+    )]
+    MetadataEntryTooLong,
+    #[error("a channel may have at most {0} tags")]
+    TooManyTags(usize),
+    #[error(
+        "tags must be 1 to {} letters, digits, '-', '_' or '.'",
+        crate::config::MAX_TAG_BYTES
+    )]
+    InvalidTag,
+    #[error("another write to this channel is in progress")]
+    ChannelLocked,
+    #[error("invalid admin token")]
+    InvalidAdminToken,
+    #[error("channel could not be decrypted with the provided key")]
+    UndecryptableChannel,
+    #[error("eviction requires a count or min_bytes")]
+    InvalidEvictionRequest,
+    #[error("invalid scan cursor")]
+    InvalidScanCursor,
+    #[error("unknown field `{0}`")]
+    UnknownField(String),
+    #[error("invalid channel export")]
+    InvalidExport,
+    #[error("content checksum mismatch")]
+    ChecksumMismatch,
+    #[error("channel has too many files")]
+    TooManyFiles,
+    #[error("at most {0} channels can be created per request")]
+    BatchTooLarge(usize),
+    #[error("requests must use https")]
+    HttpsRequired,
+    #[error("client version {0} or newer is required")]
+    ClientTooOld(String),
+    #[error("malformed authorization header")]
+    MalformedAuthorization,
+    #[error("channel password must be printable ASCII of at most {0} bytes")]
+    InvalidPasswordHeader(usize),
+    #[error("channel creation was not approved")]
+    CreationDenied,
+    #[error("channel is immutable")]
+    ChannelImmutable,
     #[error("channel file not found")]
     ChannelFileNotFound,
+    #[error("files cannot be requested over live connections")]
+    LiveFilesDisabled,
+    #[error("files over {0} bytes cannot be requested over live connections")]
+    LiveFileTooLarge(usize),
     #[error("channel payload exceeds allowed size")]
     PayloadTooLarge,
+    #[error("request body exceeds allowed size")]
+    RequestTooLarge,
+    #[error("request body must be sent with `Content-Type: application/json`")]
+    JsonContentTypeRequired,
+    #[error("{0}")]
+    MalformedJson(String),
+    #[error("{0}")]
+    InvalidJsonBody(String),
+    #[error("request body could not be read")]
+    UnreadableBody,
+    #[error("request body is not valid gzip")]
+    InvalidContentEncoding,
     #[error("invalid file data encoding")]
     InvalidFileData,
+    #[error("too many requests, slow down")]
+    RateLimited { retry_after: Duration },
+    #[error("invalid rate limit {0:?}: expected requests/seconds, optionally :ip or :channel")]
+    InvalidRateLimit(String),
+    #[error("too many subscribers on this channel")]
+    TooManySubscribers { retry_after: Duration },
+    #[error("could not allocate a fresh channel id")]
+    ChannelIdExhausted,
+    #[error("protected channel requires a non-empty password hash")]
+    EmptyPasswordHash,
+    #[error("stored channel data failed integrity check")]
+    IntegrityError,
+    #[error("storage backend unavailable")]
+    StorageUnavailable { retry_after: Duration },
+    #[error("server is overloaded, try again later")]
+    Overloaded { retry_after: Duration },
+    #[error("storage backend is overloaded, try again later")]
+    StorageOverloaded { retry_after: Duration },
     #[error("serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 }
 
+impl AppError {
+    /// Whether the storage backend failed or is held off by the circuit
+    /// breaker, as opposed to the request itself being refused.
+    pub fn is_storage_failure(&self) -> bool {
+        matches!(
+            self,
+            AppError::Redis(_) | AppError::Io(_) | AppError::StorageUnavailable { .. }
+        )
+    }
+
+    /// How long a client should back off before retrying, for the `429` and
+    /// `503` errors that are expected to clear up by themselves.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AppError::TooManySubscribers { retry_after }
+            | AppError::RateLimited { retry_after }
+            | AppError::StorageUnavailable { retry_after }
+            | AppError::StorageOverloaded { retry_after }
+            | AppError::Overloaded { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+/// `Retry-After` in whole seconds, rounded up and at least one so clients
+/// never retry straight away.
+pub fn retry_after_header(wait: Duration) -> (HeaderName, HeaderValue) {
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    (header::RETRY_AFTER, HeaderValue::from(seconds.max(1)))
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         tracing::error!(error = ?self, "request failed");
+        let retry_after = self.retry_after().map(retry_after_header);
+        let content_range = match &self {
+            AppError::RangeNotSatisfiable(len) => {
+                HeaderValue::from_str(&format!("bytes */{len}")).ok()
+            }
+            _ => None,
+        };
         let status = match self {
-            AppError::ChannelNotFound => StatusCode::NOT_FOUND,
-            AppError::ChannelFileNotFound => StatusCode::NOT_FOUND,
-            AppError::InvalidChannelPassword => StatusCode::UNAUTHORIZED,
-            AppError::PayloadTooLarge | AppError::InvalidFileData => StatusCode::BAD_REQUEST,
+            AppError::ChannelNotFound | AppError::InvalidPasswordToken => StatusCode::NOT_FOUND,
+            AppError::ChannelFileNotFound
+            | AppError::LiveFilesDisabled
+            | AppError::NoPreviousText
+            | AppError::VersionNotInHistory(_) => StatusCode::NOT_FOUND,
+            AppError::LiveFileTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::InvalidChannelPassword
+            | AppError::InvalidFilePassword
+            | AppError::InvalidAdminToken
+            | AppError::UndecryptableChannel => StatusCode::UNAUTHORIZED,
+            AppError::CreationDenied => StatusCode::FORBIDDEN,
+            AppError::ChannelImmutable | AppError::ChannelLocked => StatusCode::CONFLICT,
+            AppError::ChannelGone => StatusCode::GONE,
+            AppError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            AppError::TooManySubscribers { .. } | AppError::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            AppError::StorageUnavailable { .. }
+            | AppError::StorageOverloaded { .. }
+            | AppError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::RequestTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::JsonContentTypeRequired => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::InvalidJsonBody(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ClientTooOld(_) => StatusCode::UPGRADE_REQUIRED,
+            AppError::PayloadTooLarge
+            | AppError::InvalidFileData
+            | AppError::InvalidContentEncoding
+            | AppError::MalformedJson(_)
+            | AppError::UnreadableBody
+            | AppError::InvalidMaxReads
+            | AppError::MetadataTooLarge
+            | AppError::TooManyMetadataEntries(_)
+            | AppError::MetadataEntryTooLong
+            | AppError::TooManyTags(_)
+            | AppError::InvalidTag
+            | AppError::InvalidTtl { .. }
+            | AppError::InvalidChannelId
+            | AppError::MalformedAuthorization
+            | AppError::InvalidPasswordHeader(_)
+            | AppError::HttpsRequired
+            | AppError::InvalidEvictionRequest
+            | AppError::InvalidScanCursor
+            | AppError::ChecksumMismatch
+            | AppError::TooManyFiles
+            | AppError::BatchTooLarge(_)
+            | AppError::InvalidExport
+            | AppError::UnknownField(_)
+            | AppError::BaseUrlNotAllowed
+            | AppError::TextRequired
+            | AppError::FileRequired
+            | AppError::ContentRequired
+            | AppError::FileIdNotUuid(_) => StatusCode::BAD_REQUEST,
             AppError::BindAddress(_)
+            | AppError::InvalidPublicBaseUrl(_)
+            | AppError::InvalidAccessLogSink(_)
+            | AppError::InvalidApprovalUrl(_)
+            | AppError::InvalidChannelEventsUrl(_)
+            | AppError::InvalidRateLimit(_)
+            | AppError::ReplicaCountMismatch { .. }
             | AppError::Redis(_)
             | AppError::Io(_)
-            | AppError::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            | AppError::Serialization(_)
+            | AppError::EmptyPasswordHash
+            | AppError::ChannelIdExhausted
+            | AppError::IntegrityError => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
-        (status, Json(ErrorResponse::from(self))).into_response()
+        let mut response = (status, Json(ErrorResponse::from(self))).into_response();
+        if let Some((name, value)) = retry_after {
+            response.headers_mut().insert(name, value);
+        }
+        if let Some(content_range) = content_range {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_RANGE, content_range);
+        }
+        response
     }
 }
 
+tokio::task_local! {
+    /// Id of the request being handled, set by the request-id middleware.
+    pub static REQUEST_ID: String;
+}
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
     message: String,
+    /// Lets a client quote the failing request when reporting it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
-impl From<AppError> for ErrorResponse {
-    fn from(value: AppError) -> Self {
+impl ErrorResponse {
+    /// An error body for the request being handled.
+    pub fn new(message: String) -> Self {
         Self {
-            message: value.to_string(),
+            message,
+            request_id: REQUEST_ID.try_with(String::clone).ok(),
         }
     }
 }
+
+impl From<AppError> for ErrorResponse {
+    fn from(value: AppError) -> Self {
+        Self::new(value.to_string())
+    }
+}
@@ -0,0 +1,305 @@
+//! Channel lifecycle events for observability integrations.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{channel::unix_now, config::AppConfig, webhook::HttpTarget};
+
+/// How long one webhook delivery may take before the event is given up on.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Observer of channel lifecycle events, installed with `run_with_event_sinks`.
+/// Each method is called once the operation has succeeded, on the request's
+/// own task, so it must not block: a sink doing I/O hands the event to a task
+/// of its own. Every method does nothing by default.
+pub trait EventSink: Send + Sync {
+    fn on_created(&self, _id: &str) {}
+
+    /// Content was served, in any form (fetch, view, download, export, ...).
+    fn on_read(&self, _id: &str) {}
+
+    fn on_updated(&self, _id: &str) {}
+
+    fn on_deleted(&self, _id: &str) {}
+
+    /// Storage expires channels by itself, so this fires from the first orphan
+    /// sweep (`ORPHAN_SWEEP_INTERVAL_SECONDS`) past a channel's expiry, not on
+    /// the dot, and never without the sweep. Deleted, burned and read-out
+    /// channels do not count.
+    fn on_expired(&self, _id: &str) {}
+}
+
+/// Logs every event at info level, for `LOG_CHANNEL_EVENTS`.
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn on_created(&self, id: &str) {
+        tracing::info!(%id, "channel created");
+    }
+
+    fn on_read(&self, id: &str) {
+        tracing::info!(%id, "channel read");
+    }
+
+    fn on_updated(&self, id: &str) {
+        tracing::info!(%id, "channel updated");
+    }
+
+    fn on_deleted(&self, id: &str) {
+        tracing::info!(%id, "channel deleted");
+    }
+
+    fn on_expired(&self, id: &str) {
+        tracing::info!(%id, "channel expired");
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelEventKind {
+    Created,
+    Read,
+    Updated,
+    Deleted,
+    Expired,
+}
+
+/// What `CHANNEL_EVENTS_URL` is sent for each event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelEvent {
+    pub event: ChannelEventKind,
+    pub id: String,
+    /// Unix seconds.
+    pub timestamp: u64,
+}
+
+/// POSTs every event as JSON to `CHANNEL_EVENTS_URL`. Events are handed to a
+/// background task; when `buffer` are already queued new ones are dropped, so
+/// a slow endpoint never delays requests.
+pub struct WebhookSink {
+    sender: Sender<ChannelEvent>,
+}
+
+impl WebhookSink {
+    /// Spawns the delivery task, so this needs a Tokio runtime.
+    pub fn new(target: HttpTarget, buffer: usize) -> Self {
+        let (sender, events) = mpsc::channel(buffer.max(1));
+        tokio::spawn(deliver(target, events));
+        Self { sender }
+    }
+
+    fn send(&self, event: ChannelEventKind, id: &str) {
+        let event = ChannelEvent {
+            event,
+            id: id.to_owned(),
+            timestamp: unix_now(),
+        };
+        if self.sender.try_send(event).is_err() {
+            tracing::warn!(%id, "channel event webhook fell behind, dropping event");
+        }
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn on_created(&self, id: &str) {
+        self.send(ChannelEventKind::Created, id);
+    }
+
+    fn on_read(&self, id: &str) {
+        self.send(ChannelEventKind::Read, id);
+    }
+
+    fn on_updated(&self, id: &str) {
+        self.send(ChannelEventKind::Updated, id);
+    }
+
+    fn on_deleted(&self, id: &str) {
+        self.send(ChannelEventKind::Deleted, id);
+    }
+
+    fn on_expired(&self, id: &str) {
+        self.send(ChannelEventKind::Expired, id);
+    }
+}
+
+async fn deliver(target: HttpTarget, mut events: Receiver<ChannelEvent>) {
+    while let Some(event) = events.recv().await {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to serialize channel event");
+                continue;
+            }
+        };
+        match tokio::time::timeout(WEBHOOK_TIMEOUT, target.post_json(&body)).await {
+            Ok(Ok(code)) if (200..300).contains(&code) => {}
+            Ok(Ok(code)) => tracing::warn!(code, %target, "channel event webhook refused event"),
+            Ok(Err(err)) => tracing::warn!(error = %err, %target, "channel event webhook failed"),
+            Err(_) => tracing::warn!(%target, "channel event webhook timed out"),
+        }
+    }
+}
+
+/// Running totals of each event, kept by `MetricsSink`.
+#[derive(Debug, Default)]
+pub struct ChannelEventCounts {
+    created: AtomicU64,
+    read: AtomicU64,
+    updated: AtomicU64,
+    deleted: AtomicU64,
+    expired: AtomicU64,
+}
+
+/// `ChannelEventCounts` at one moment, as `/ready` reports them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ChannelEventTotals {
+    pub created: u64,
+    pub read: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    pub expired: u64,
+}
+
+impl ChannelEventCounts {
+    pub fn totals(&self) -> ChannelEventTotals {
+        ChannelEventTotals {
+            created: self.created.load(Ordering::Relaxed),
+            read: self.read.load(Ordering::Relaxed),
+            updated: self.updated.load(Ordering::Relaxed),
+            deleted: self.deleted.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts every event, for `COUNT_CHANNEL_EVENTS`.
+pub struct MetricsSink(Arc<ChannelEventCounts>);
+
+impl MetricsSink {
+    pub fn new(counts: Arc<ChannelEventCounts>) -> Self {
+        Self(counts)
+    }
+}
+
+impl EventSink for MetricsSink {
+    fn on_created(&self, _id: &str) {
+        self.0.created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_read(&self, _id: &str) {
+        self.0.read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_updated(&self, _id: &str) {
+        self.0.updated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_deleted(&self, _id: &str) {
+        self.0.deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_expired(&self, _id: &str) {
+        self.0.expired.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Passes every event on to each installed sink in turn. Without any it is
+/// the no-op default.
+#[derive(Default)]
+pub struct EventSinks(Vec<Box<dyn EventSink>>);
+
+impl EventSinks {
+    pub fn new(sinks: Vec<Box<dyn EventSink>>) -> Self {
+        Self(sinks)
+    }
+
+    /// The sinks `config` turns on: `LOG_CHANNEL_EVENTS`, `CHANNEL_EVENTS_URL`
+    /// and `COUNT_CHANNEL_EVENTS`, along with the counters the last one keeps.
+    /// Spawns the webhook's delivery task, so this needs a Tokio runtime when
+    /// one is configured.
+    pub fn configured(config: &AppConfig) -> (Self, Option<Arc<ChannelEventCounts>>) {
+        let mut sinks = Self::default();
+        if config.log_channel_events {
+            sinks.push(Box::new(LogSink));
+        }
+        if let Some(target) = &config.channel_events_url {
+            sinks.push(Box::new(WebhookSink::new(
+                target.clone(),
+                config.access_log_buffer,
+            )));
+        }
+        let counts = config
+            .count_channel_events
+            .then(|| Arc::new(ChannelEventCounts::default()));
+        if let Some(counts) = &counts {
+            sinks.push(Box::new(MetricsSink::new(counts.clone())));
+        }
+        (sinks, counts)
+    }
+
+    pub fn push(&mut self, sink: Box<dyn EventSink>) {
+        self.0.push(sink);
+    }
+
+    pub fn extend(&mut self, other: EventSinks) {
+        self.0.extend(other.0);
+    }
+}
+
+impl EventSink for EventSinks {
+    fn on_created(&self, id: &str) {
+        self.0.iter().for_each(|sink| sink.on_created(id));
+    }
+
+    fn on_read(&self, id: &str) {
+        self.0.iter().for_each(|sink| sink.on_read(id));
+    }
+
+    fn on_updated(&self, id: &str) {
+        self.0.iter().for_each(|sink| sink.on_updated(id));
+    }
+
+    fn on_deleted(&self, id: &str) {
+        self.0.iter().for_each(|sink| sink.on_deleted(id));
+    }
+
+    fn on_expired(&self, id: &str) {
+        self.0.iter().for_each(|sink| sink.on_expired(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ChannelEventCounts, ChannelEventTotals, EventSink, MetricsSink};
+
+    #[test]
+    fn metrics_sink_counts_each_event() {
+        let counts = Arc::new(ChannelEventCounts::default());
+        let sink = MetricsSink::new(counts.clone());
+        sink.on_created("a");
+        sink.on_read("a");
+        sink.on_read("a");
+        sink.on_updated("a");
+        sink.on_deleted("a");
+        assert_eq!(
+            counts.totals(),
+            ChannelEventTotals {
+                created: 1,
+                read: 2,
+                updated: 1,
+                deleted: 1,
+                expired: 0,
+            }
+        );
+    }
+}
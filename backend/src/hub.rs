@@ -0,0 +1,81 @@
+//! In-process fan-out of live channel updates to WebSocket sessions.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Updates a slow session may fall behind by before it starts skipping ahead.
+const UPDATE_BUFFER: usize = 16;
+
+/// A channel's text as of `version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LiveText {
+    pub version: u64,
+    pub text: String,
+}
+
+/// One broadcast sender per channel with live sessions on this instance.
+/// Sessions on other instances are not reached; they catch up through a
+/// version conflict on their next edit.
+#[derive(Default)]
+pub struct ChannelHub {
+    channels: Mutex<HashMap<String, broadcast::Sender<LiveText>>>,
+}
+
+impl ChannelHub {
+    fn channels(&self) -> std::sync::MutexGuard<'_, HashMap<String, broadcast::Sender<LiveText>>> {
+        self.channels.lock().expect("channel hub lock poisoned")
+    }
+
+    pub fn subscribe(&self, id: &str) -> broadcast::Receiver<LiveText> {
+        self.channels()
+            .entry(id.to_owned())
+            .or_insert_with(|| broadcast::channel(UPDATE_BUFFER).0)
+            .subscribe()
+    }
+
+    /// Sends `update` to every session subscribed to `id`, if any.
+    pub fn publish(&self, id: &str, update: LiveText) {
+        if let Some(sender) = self.channels().get(id) {
+            let _ = sender.send(update);
+        }
+    }
+
+    /// Forgets `id` once its last session has dropped its receiver.
+    pub fn release(&self, id: &str) {
+        let mut channels = self.channels();
+        if channels
+            .get(id)
+            .is_some_and(|sender| sender.receiver_count() == 0)
+        {
+            channels.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelHub, LiveText};
+
+    #[tokio::test]
+    async fn updates_reach_every_subscriber_until_released() {
+        let hub = ChannelHub::default();
+        let mut first = hub.subscribe("a");
+        let mut second = hub.subscribe("a");
+        let update = LiveText {
+            version: 2,
+            text: "hi".into(),
+        };
+        hub.publish("a", update.clone());
+        assert_eq!(first.recv().await.unwrap(), update);
+        assert_eq!(second.recv().await.unwrap(), update);
+
+        drop(first);
+        hub.release("a");
+        assert_eq!(hub.channels().len(), 1);
+        drop(second);
+        hub.release("a");
+        assert!(hub.channels().is_empty());
+    }
+}
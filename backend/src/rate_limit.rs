@@ -0,0 +1,219 @@
+use std::{net::IpAddr, str::FromStr, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{error::AppError, storage::Storage};
+
+/// The endpoint groups that are rate limited, each against its own limit and
+/// its own buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Single, bulk and import creates.
+    Create,
+    /// Content reads: the channel itself, `/text` and `/view`.
+    Fetch,
+    Update,
+    /// Raw file downloads, one at a time, several at once or as an archive.
+    Download,
+}
+
+impl Endpoint {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Fetch => "fetch",
+            Self::Update => "update",
+            Self::Download => "download",
+        }
+    }
+}
+
+/// Whose requests share a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitBy {
+    /// The connecting peer's address.
+    Ip,
+    /// The channel being addressed, whoever asks.
+    Channel,
+}
+
+/// `requests` per `per`, as a token bucket: up to `requests` may arrive at
+/// once, after which they are let through as the bucket refills. Written
+/// `{requests}/{seconds}`, optionally followed by `:ip` (the default) or
+/// `:channel`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+    pub by: LimitBy,
+}
+
+impl RateLimit {
+    /// Time for one request's worth of the bucket to refill.
+    fn refill(&self) -> Duration {
+        self.per / self.requests
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = AppError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidRateLimit(raw.to_owned());
+        let (rate, by) = match raw.trim().split_once(':') {
+            Some((rate, "ip")) => (rate, LimitBy::Ip),
+            Some((rate, "channel")) => (rate, LimitBy::Channel),
+            Some(_) => return Err(invalid()),
+            None => (raw.trim(), LimitBy::Ip),
+        };
+        let (requests, seconds) = rate.split_once('/').ok_or_else(invalid)?;
+        let requests = requests.parse::<u32>().map_err(|_| invalid())?;
+        let seconds = seconds.parse::<u64>().map_err(|_| invalid())?;
+        if requests == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            requests,
+            per: Duration::from_secs(seconds),
+            by,
+        })
+    }
+}
+
+/// The limit of each endpoint group; `None` leaves it unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    pub create: Option<RateLimit>,
+    pub fetch: Option<RateLimit>,
+    pub update: Option<RateLimit>,
+    pub download: Option<RateLimit>,
+}
+
+impl RateLimits {
+    pub fn get(&self, endpoint: Endpoint) -> Option<&RateLimit> {
+        match endpoint {
+            Endpoint::Create => self.create.as_ref(),
+            Endpoint::Fetch => self.fetch.as_ref(),
+            Endpoint::Update => self.update.as_ref(),
+            Endpoint::Download => self.download.as_ref(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        [&self.create, &self.fetch, &self.update, &self.download]
+            .iter()
+            .all(|limit| limit.is_none())
+    }
+}
+
+/// Checks requests against the configured `RateLimits`, keeping the buckets in
+/// storage so every instance shares them.
+pub struct RateLimiter {
+    limits: RateLimits,
+    key_prefix: String,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, key_prefix: &str) -> Self {
+        Self {
+            limits,
+            key_prefix: format!("{key_prefix}rate:"),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.limits.is_empty()
+    }
+
+    /// Takes one request from the bucket `endpoint`'s limit assigns to this
+    /// caller, failing with `429` and the wait when it is empty. Requests with
+    /// nothing to key on (a channel limit on a create, a peer address that is
+    /// unknown) all share one bucket.
+    pub async fn check(
+        &self,
+        storage: &dyn Storage,
+        endpoint: Endpoint,
+        ip: Option<IpAddr>,
+        channel: Option<&str>,
+    ) -> Result<(), AppError> {
+        let Some(limit) = self.limits.get(endpoint) else {
+            return Ok(());
+        };
+        let subject = match limit.by {
+            LimitBy::Ip => ip.map_or_else(|| "unknown".to_owned(), |ip| ip.to_string()),
+            LimitBy::Channel => channel.unwrap_or("unknown").to_owned(),
+        };
+        let key = format!("{}{}:{subject}", self.key_prefix, endpoint.as_str());
+        match storage
+            .take_token(&key, limit.requests, limit.refill())
+            .await?
+        {
+            None => Ok(()),
+            Some(retry_after) => Err(AppError::RateLimited { retry_after }),
+        }
+    }
+}
+
+/// Process-wide token bucket for `GLOBAL_RATE_LIMIT`: a burst of a second's
+/// worth, then one request every `1 / per_second`. It lives in memory, not in
+/// storage, since protecting storage is much of the point.
+pub struct GlobalLimiter {
+    burst: Duration,
+    refill: Duration,
+    /// When the bucket will be full again; in the past while it is.
+    full_at: Mutex<Instant>,
+}
+
+impl GlobalLimiter {
+    pub fn new(per_second: u32) -> Self {
+        let per_second = per_second.max(1);
+        let refill = Duration::from_secs(1) / per_second;
+        Self {
+            burst: refill * (per_second - 1),
+            refill,
+            full_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Takes one request's token, or fails with `503` and the wait until the
+    /// next one.
+    pub fn check(&self) -> Result<(), AppError> {
+        let now = Instant::now();
+        let mut full_at = self.full_at.lock().expect("global limiter lock poisoned");
+        let start = (*full_at).max(now);
+        let wait = start
+            .saturating_duration_since(now)
+            .saturating_sub(self.burst);
+        if !wait.is_zero() {
+            return Err(AppError::Overloaded { retry_after: wait });
+        }
+        *full_at = start + self.refill;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LimitBy, RateLimit};
+
+    #[test]
+    fn limits_parse() {
+        assert_eq!(
+            "30/60".parse::<RateLimit>().unwrap(),
+            RateLimit {
+                requests: 30,
+                per: Duration::from_secs(60),
+                by: LimitBy::Ip,
+            }
+        );
+        assert_eq!(
+            "5/1:channel".parse::<RateLimit>().unwrap().by,
+            LimitBy::Channel
+        );
+        for invalid in ["0/60", "5/0", "5", "5/60:user", "five/60"] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Last-known copies of recently fetched channels, served while storage is
+//! down.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::channel::StoredChannel;
+
+struct Cached {
+    record: StoredChannel,
+    bytes: usize,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_id: HashMap<String, Cached>,
+    bytes: usize,
+}
+
+/// Channels as they were last fetched on this instance, still sealed and
+/// password-protected, kept until they would have expired or until this
+/// instance writes, renames or deletes them. Once `max_bytes` is reached the
+/// copies closest to expiry make room for new ones. Channels with a read
+/// budget are never kept, since serving them would bypass it.
+pub struct StaleCache {
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+}
+
+impl StaleCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().expect("stale cache lock poisoned")
+    }
+
+    /// Keeps `record` as the copy of `id` for the next `ttl`.
+    pub fn remember(&self, id: &str, record: &StoredChannel, ttl: Duration) {
+        let mut entries = self.entries();
+        if let Some(old) = entries.by_id.remove(id) {
+            entries.bytes -= old.bytes;
+        }
+        let bytes = record_bytes(record);
+        if record.max_reads.is_some()
+            || record.burn_after_read
+            || ttl.is_zero()
+            || bytes > self.max_bytes
+        {
+            return;
+        }
+
+        let now = Instant::now();
+        entries.by_id.retain(|_, cached| cached.expires_at > now);
+        entries.bytes = entries.by_id.values().map(|cached| cached.bytes).sum();
+        while entries.bytes + bytes > self.max_bytes {
+            let Some(soonest) = entries
+                .by_id
+                .iter()
+                .min_by_key(|(_, cached)| cached.expires_at)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = entries.by_id.remove(&soonest) {
+                entries.bytes -= evicted.bytes;
+            }
+        }
+
+        entries.bytes += bytes;
+        entries.by_id.insert(
+            id.to_owned(),
+            Cached {
+                record: record.clone(),
+                bytes,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    /// Drops the copy of `id`, for channels that were deleted or changed, so an
+    /// outage never brings back content that is gone.
+    pub fn forget(&self, id: &str) {
+        let mut entries = self.entries();
+        if let Some(old) = entries.by_id.remove(id) {
+            entries.bytes -= old.bytes;
+        }
+    }
+
+    /// The kept copy of `id` with the time it has left, unless it has expired.
+    pub fn recall(&self, id: &str) -> Option<(StoredChannel, Duration)> {
+        let entries = self.entries();
+        let cached = entries.by_id.get(id)?;
+        let remaining = cached.expires_at.checked_duration_since(Instant::now())?;
+        (!remaining.is_zero()).then(|| (cached.record.clone(), remaining))
+    }
+}
+
+/// Rough size of a record: its content, which dwarfs everything else.
+fn record_bytes(record: &StoredChannel) -> usize {
+    let sealed = record
+        .sealed
+        .as_ref()
+        .map_or(0, |sealed| sealed.ciphertext.len());
+    let files: usize = record
+        .data
+        .files
+        .iter()
+        .map(|file| file.data_base64.len())
+        .sum();
+    sealed + record.data.text.len() + files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::StaleCache;
+    use crate::channel::{ChannelData, StoredChannel};
+
+    fn record(text: &str) -> StoredChannel {
+        StoredChannel {
+            data: ChannelData {
+                text: text.into(),
+                files: Vec::new(),
+            },
+            ..StoredChannel::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn copies_expire_with_the_channel() {
+        let cache = StaleCache::new(1024);
+        cache.remember("a", &record("hello"), Duration::from_secs(10));
+        let (copy, remaining) = cache.recall("a").unwrap();
+        assert_eq!(copy.data.text, "hello");
+        assert_eq!(remaining, Duration::from_secs(10));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(cache.recall("a").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forgotten_copies_are_gone() {
+        let cache = StaleCache::new(1024);
+        cache.remember("a", &record("hello"), Duration::from_secs(10));
+        cache.forget("a");
+        assert!(cache.recall("a").is_none());
+        cache.forget("never-kept");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn copies_closest_to_expiry_make_room_and_budgets_are_never_kept() {
+        let cache = StaleCache::new(10);
+        cache.remember("short", &record("aaaa"), Duration::from_secs(5));
+        cache.remember("long", &record("bbbb"), Duration::from_secs(50));
+        cache.remember("new", &record("cccc"), Duration::from_secs(20));
+        assert!(cache.recall("short").is_none());
+        assert!(cache.recall("long").is_some());
+        assert!(cache.recall("new").is_some());
+
+        let limited = StoredChannel {
+            max_reads: Some(3),
+            ..record("d")
+        };
+        cache.remember("limited", &limited, Duration::from_secs(20));
+        assert!(cache.recall("limited").is_none());
+        cache.remember("huge", &record(&"x".repeat(11)), Duration::from_secs(20));
+        assert!(cache.recall("huge").is_none());
+    }
+}
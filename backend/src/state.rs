@@ -1,40 +1,264 @@
-use std::{sync::Arc, time::Duration};
+use std::{future::Future, sync::Arc, time::Duration};
 
-use redis::{AsyncCommands, aio::ConnectionManager};
+use tokio::time::Instant;
 
-use crate::{config::AppConfig, error::AppError};
+use crate::{
+    access_log::AccessLog,
+    channel::{HistoryEntry, StoredChannel, unix_now},
+    config::{AppConfig, StorageBackend},
+    error::AppError,
+    events::{ChannelEventCounts, EventSink, EventSinks},
+    hub::ChannelHub,
+    rate_limit::{GlobalLimiter, RateLimiter},
+    stale::StaleCache,
+    storage::{
+        CircuitBreaker, CircuitState, GuardedStorage, LatencyEstimate, MemoryStorage, RedisStorage,
+        Storage,
+    },
+    tasks::BackgroundTasks,
+};
+
+pub use crate::storage::RenameOutcome;
 
-#[derive(Clone)]
 pub struct AppState {
-    redis: ConnectionManager,
-    channel_ttl: Duration,
+    storage: GuardedStorage,
+    config: AppConfig,
+    readiness: ReadinessCache,
+    hub: ChannelHub,
+    stale: StaleCache,
+    access_log: AccessLog,
+    events: EventSinks,
+    event_counts: Option<Arc<ChannelEventCounts>>,
+    tasks: BackgroundTasks,
+    limiter: RateLimiter,
+    global_limiter: Option<GlobalLimiter>,
 }
 
 impl AppState {
     pub async fn initialise(config: &AppConfig) -> Result<Self, AppError> {
-        let client = redis::Client::open(config.redis_url.clone())?;
-        let manager = ConnectionManager::new(client).await?;
+        let storage: Box<dyn Storage> = match config.storage_backend {
+            StorageBackend::Redis => Box::new(
+                RedisStorage::connect(
+                    &config.redis_urls,
+                    &config.redis_replica_urls,
+                    config.redis_username.as_deref(),
+                    config.redis_password.as_deref(),
+                )
+                .await?,
+            ),
+            StorageBackend::Memory => Box::new(MemoryStorage::new()),
+        };
+        Ok(Self::new(config.clone(), storage))
+    }
 
-        Ok(Self {
-            redis: manager,
-            channel_ttl: config.channel_ttl,
-        })
+    pub fn new(config: AppConfig, storage: Box<dyn Storage>) -> Self {
+        let breaker = CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_cooldown,
+        );
+        let (events, event_counts) = EventSinks::configured(&config);
+        Self {
+            storage: GuardedStorage::new(storage, breaker),
+            stale: StaleCache::new(config.stale_cache_max_bytes),
+            access_log: AccessLog::new(config.access_log_sink.clone(), config.access_log_buffer),
+            tasks: BackgroundTasks::new(config.max_background_tasks),
+            limiter: RateLimiter::new(config.rate_limits.clone(), &config.key_prefix),
+            global_limiter: config.global_rate_limit.map(GlobalLimiter::new),
+            config,
+            readiness: ReadinessCache::default(),
+            hub: ChannelHub::default(),
+            events,
+            event_counts,
+        }
+    }
+
+    /// Installs further sinks told about channel lifecycle events, next to the
+    /// ones the config turns on.
+    pub fn with_event_sinks(mut self, events: EventSinks) -> Self {
+        self.events.extend(events);
+        self
+    }
+
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        &self.storage
+    }
+
+    pub fn hub(&self) -> &ChannelHub {
+        &self.hub
+    }
+
+    pub fn stale_cache(&self) -> &StaleCache {
+        &self.stale
+    }
+
+    pub fn access_log(&self) -> &AccessLog {
+        &self.access_log
+    }
+
+    pub fn events(&self) -> &dyn EventSink {
+        &self.events
+    }
+
+    /// Set with `COUNT_CHANNEL_EVENTS`.
+    pub fn event_counts(&self) -> Option<&ChannelEventCounts> {
+        self.event_counts.as_deref()
+    }
+
+    pub fn tasks(&self) -> &BackgroundTasks {
+        &self.tasks
     }
 
-    pub fn redis(&self) -> ConnectionManager {
-        self.redis.clone()
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    /// Set with `GLOBAL_RATE_LIMIT`.
+    pub fn global_limiter(&self) -> Option<&GlobalLimiter> {
+        self.global_limiter.as_ref()
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.storage.circuit_state()
+    }
+
+    /// Rolling latency of storage calls, updated by every call that reaches
+    /// the backend.
+    pub fn storage_latency(&self) -> &LatencyEstimate {
+        self.storage.latency()
+    }
+
+    /// Refuses new channels while storage is slower than
+    /// `SHED_CREATE_LATENCY_MS`, instead of piling writes onto it.
+    pub fn shed_create_if_slow(&self) -> Result<(), AppError> {
+        let (Some(limit), Some(latency)) = (
+            self.config.shed_create_latency,
+            self.storage_latency().current(),
+        ) else {
+            return Ok(());
+        };
+        if latency > limit {
+            tracing::warn!(?latency, ?limit, "shedding channel creation");
+            return Err(AppError::StorageOverloaded {
+                retry_after: self.config.retry_after,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the storage backend answers, reusing a recent success for
+    /// `READY_CACHE_MS` so aggressive probes do not turn into load.
+    pub async fn ready(&self) -> bool {
+        self.readiness
+            .check(self.config.ready_cache, || self.storage.ping())
+            .await
+    }
+
+    pub fn shard_for(&self, id: &str) -> usize {
+        self.storage.shard_of(id)
+    }
+
+    pub fn channel_key_prefix(&self) -> String {
+        format!("{}channel:", self.config.key_prefix)
     }
 
     pub fn channel_key(&self, id: &str) -> String {
-        format!("channel:{id}")
+        format!("{}{id}", self.channel_key_prefix())
+    }
+
+    pub fn read_count_key(&self, id: &str) -> String {
+        format!("{}reads:{id}", self.config.key_prefix)
     }
 
-    pub fn ttl_seconds(&self) -> usize {
-        self.channel_ttl.as_secs() as usize
+    /// Per-file raw download counts, a hash keyed by file id.
+    pub fn download_count_key(&self, id: &str) -> String {
+        format!("{}downloads:{id}", self.config.key_prefix)
+    }
+
+    pub fn tag_key_prefix(&self) -> String {
+        format!("{}tag:", self.config.key_prefix)
+    }
+
+    /// Set of the ids of channels carrying `tag`.
+    pub fn tag_key(&self, tag: &str) -> String {
+        format!("{}{tag}", self.tag_key_prefix())
+    }
+
+    /// Sorted set of channel ids scored by the unix time they expire at, which
+    /// the orphan sweep drains to report expiries.
+    pub fn expiry_index_key(&self) -> String {
+        format!("{}expiries", self.config.key_prefix)
+    }
+
+    /// Copy of a channel kept for `EXPIRED_GRACE_SECONDS` past its expiry.
+    pub fn grace_key(&self, id: &str) -> String {
+        format!("{}grace:{id}", self.config.key_prefix)
+    }
+
+    /// The channel's text before its latest edit, for `/diff`.
+    pub fn previous_text_key(&self, id: &str) -> String {
+        format!("{}prev:{id}", self.config.key_prefix)
+    }
+
+    /// Earlier versions of the channel's text, newest first.
+    pub fn history_key(&self, id: &str) -> String {
+        format!("{}history:{id}", self.config.key_prefix)
+    }
+
+    /// A created channel's password waiting to be fetched with its token.
+    pub fn password_token_key(&self, id: &str) -> String {
+        format!("{}pwtoken:{id}", self.config.key_prefix)
+    }
+
+    pub fn tombstone_key(&self, id: &str) -> String {
+        format!("{}gone:{id}", self.config.key_prefix)
+    }
+
+    pub fn write_lock_key(&self, id: &str) -> String {
+        format!("{}lock:{id}", self.config.key_prefix)
+    }
+
+    pub fn subscriber_count_key(&self, id: &str) -> String {
+        format!("{}subs:{id}", self.config.key_prefix)
     }
 
     pub fn channel_ttl(&self) -> Duration {
-        self.channel_ttl
+        self.config.channel_ttl
+    }
+}
+
+/// Remembers when the backend last answered. Failures are never cached, so an
+/// outage shows up on the very next probe; the lock is held across the probe so
+/// concurrent callers share one round-trip.
+#[derive(Default)]
+struct ReadinessCache {
+    last_ok: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl ReadinessCache {
+    async fn check<F, Fut>(&self, window: Duration, probe: F) -> bool
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(), AppError>>,
+    {
+        let mut last_ok = self.last_ok.lock().await;
+        if last_ok.is_some_and(|at| at.elapsed() < window) {
+            return true;
+        }
+        match probe().await {
+            Ok(()) => {
+                *last_ok = Some(Instant::now());
+                true
+            }
+            Err(err) => {
+                *last_ok = None;
+                tracing::warn!(error = ?err, "readiness probe failed");
+                false
+            }
+        }
     }
 }
 
@@ -44,8 +268,754 @@ pub fn shared(state: AppState) -> SharedState {
     Arc::new(state)
 }
 
-pub async fn refresh_ttl(state: &SharedState, key: &str) -> Result<(), AppError> {
-    let mut conn = state.redis();
-    let _: () = conn.expire(key, state.ttl_seconds()).await?;
+/// Resets a channel's TTL after activity. With `MAX_LIFETIME_SECONDS` set, the
+/// refresh never pushes expiry past `created_at + max_lifetime`, and once that
+/// point has passed it becomes a no-op so the channel expires naturally.
+/// Channels created with `sticky_ttl` are never refreshed, and burned ones are
+/// already gone.
+pub async fn refresh_ttl(
+    state: &SharedState,
+    id: &str,
+    record: &StoredChannel,
+) -> Result<(), AppError> {
+    if record.sticky_ttl || record.burn_after_read {
+        return Ok(());
+    }
+    let Some(ttl) = refresh_target(
+        unix_now(),
+        record.created_at,
+        record.ttl(state.channel_ttl()).as_secs(),
+        state
+            .config()
+            .max_lifetime
+            .map(|lifetime| lifetime.as_secs()),
+    ) else {
+        return Ok(());
+    };
+    let ttl = Duration::from_secs(ttl);
+    state.storage().expire(&state.channel_key(id), ttl).await?;
+    if let Some(grace_ttl) = grace_ttl(state, record, ttl) {
+        state
+            .storage()
+            .expire(&state.grace_key(id), grace_ttl)
+            .await?;
+    }
+    for key in [state.previous_text_key(id), state.history_key(id)] {
+        state.storage().expire(&key, ttl).await?;
+    }
+    track_expiry(state, id, ttl).await?;
+    index_tags(state, id, &record.tags, ttl).await
+}
+
+/// `refresh_ttl` after a read of the content, unless `REFRESH_TTL_ON_READ` is
+/// off, in which case only writes and explicit touches extend a channel.
+pub async fn refresh_ttl_on_read(
+    state: &SharedState,
+    id: &str,
+    record: &StoredChannel,
+) -> Result<(), AppError> {
+    if !state.config().refresh_ttl_on_read {
+        return Ok(());
+    }
+    refresh_ttl(state, id, record).await
+}
+
+/// How long the grace copy of `record`, living `ttl`, is kept; `None` when
+/// there should be none. Channels with `max_reads` or `burn_after_read` never
+/// get one, so their last read stays the last.
+pub fn grace_ttl(state: &SharedState, record: &StoredChannel, ttl: Duration) -> Option<Duration> {
+    let grace = state.config().expired_grace?;
+    (record.max_reads.is_none() && !record.burn_after_read).then_some(ttl + grace)
+}
+
+/// Stores `serialized` as the grace copy of channel `id`, or does nothing
+/// without a `grace_ttl`. Call it with every write of the channel itself.
+pub async fn keep_grace_copy(
+    state: &SharedState,
+    id: &str,
+    serialized: &str,
+    grace_ttl: Option<Duration>,
+) -> Result<(), AppError> {
+    let Some(ttl) = grace_ttl else {
+        return Ok(());
+    };
+    state
+        .storage()
+        .set_ex(&state.grace_key(id), serialized, ttl)
+        .await
+}
+
+/// Keeps the text a channel had at `version` before an edit replaced it, for
+/// as long as the channel lives: as the previous text for `/diff`, and with
+/// `HISTORY_VERSIONS` at the front of its history. `None`, for an encrypted
+/// channel whose old text must not sit in storage in the clear, drops both.
+pub async fn keep_previous_text(
+    state: &SharedState,
+    id: &str,
+    previous: Option<(u64, &str)>,
+    ttl: Duration,
+) -> Result<(), AppError> {
+    let Some((version, text)) = previous else {
+        return state
+            .storage()
+            .del(&[state.previous_text_key(id), state.history_key(id)])
+            .await;
+    };
+    state
+        .storage()
+        .set_ex(&state.previous_text_key(id), text, ttl)
+        .await?;
+    let config = state.config();
+    if config.history_versions == 0 {
+        return Ok(());
+    }
+    let entry = serde_json::to_string(&HistoryEntry {
+        version,
+        replaced_at: unix_now(),
+        text: text.to_owned(),
+    })?;
+    state
+        .storage()
+        .lpush_capped(
+            &state.history_key(id),
+            &entry,
+            config.history_versions,
+            config.history_max_bytes,
+            ttl,
+        )
+        .await
+}
+
+/// Adds `id` to the set of each of its tags, keeping each set alive at least
+/// as long as the channel.
+pub async fn index_tags(
+    state: &SharedState,
+    id: &str,
+    tags: &[String],
+    ttl: Duration,
+) -> Result<(), AppError> {
+    for tag in tags {
+        state
+            .storage()
+            .sadd_ex(&state.tag_key(tag), id, ttl)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Records that channel `id` expires `ttl` from now, so the orphan sweep can
+/// report it once it has. The index is only kept while the sweep runs
+/// (`ORPHAN_SWEEP_INTERVAL_SECONDS`), since nothing else drains it. Extending a
+/// channel without calling this only delays the report: the sweep looks again
+/// at the new expiry.
+pub async fn track_expiry(state: &SharedState, id: &str, ttl: Duration) -> Result<(), AppError> {
+    if state.config().orphan_sweep_interval.is_none() {
+        return Ok(());
+    }
+    state
+        .storage()
+        .zadd(&state.expiry_index_key(), id, unix_now() + ttl.as_secs())
+        .await
+}
+
+/// Drops channel `id` from the expiry index and from the set of each of its
+/// tags, for a channel that goes away before it expires.
+async fn unindex_channel(state: &SharedState, id: &str, tags: &[String]) -> Result<(), AppError> {
+    let ids = [id.to_owned()];
+    state
+        .storage()
+        .zrem(&state.expiry_index_key(), &ids)
+        .await?;
+    for tag in tags {
+        state.storage().srem(&state.tag_key(tag), &ids).await?;
+    }
     Ok(())
 }
+
+fn refresh_target(
+    now: u64,
+    created_at: Option<u64>,
+    ttl: u64,
+    max_lifetime: Option<u64>,
+) -> Option<u64> {
+    let (Some(created_at), Some(max_lifetime)) = (created_at, max_lifetime) else {
+        return Some(ttl);
+    };
+    let deadline = created_at.saturating_add(max_lifetime);
+    if now >= deadline {
+        return None;
+    }
+    Some(ttl.min(deadline - now))
+}
+
+/// Stores a brand-new channel only if nothing lives at `id` yet. Returns
+/// `false` when another writer got there first.
+pub async fn store_if_absent(
+    state: &SharedState,
+    id: &str,
+    serialized: String,
+) -> Result<bool, AppError> {
+    let stored = state
+        .storage()
+        .set_nx_ex(&state.channel_key(id), &serialized, state.channel_ttl())
+        .await?;
+    if stored {
+        state.stale_cache().forget(id);
+        track_expiry(state, id, state.channel_ttl()).await?;
+    }
+    Ok(stored)
+}
+
+/// Atomically counts one read against a channel's `max_reads` budget and
+/// returns the payload that was read. The read that exhausts the budget deletes
+/// the channel and leaves a tombstone so later fetches can report `410 Gone`.
+pub async fn consume_read(
+    state: &SharedState,
+    id: &str,
+    max_reads: u32,
+) -> Result<Option<String>, AppError> {
+    state
+        .storage()
+        .consume_read(
+            &state.channel_key(id),
+            &state.read_count_key(id),
+            &state.tombstone_key(id),
+            max_reads,
+            state.channel_ttl(),
+        )
+        .await
+}
+
+pub async fn missing_channel_error(state: &SharedState, id: &str) -> Result<AppError, AppError> {
+    if state.config().hide_channel_existence {
+        return Ok(AppError::ChannelNotFound);
+    }
+    let gone = state.storage().exists(&state.tombstone_key(id)).await?;
+    Ok(if gone {
+        AppError::ChannelGone
+    } else {
+        AppError::ChannelNotFound
+    })
+}
+
+/// A claimed WebSocket/SSE subscriber slot on `subs:{id}`. The counter gets
+/// the channel TTL when it is created, so slots leaked by a crashed instance
+/// age out, and the slot is given back when the guard is released or dropped,
+/// so a disconnect always frees it.
+pub struct SubscriberSlot {
+    state: SharedState,
+    key: Option<String>,
+}
+
+impl SubscriberSlot {
+    pub async fn acquire(state: &SharedState, id: &str) -> Result<Self, AppError> {
+        let key = state.subscriber_count_key(id);
+        let acquired = state
+            .storage()
+            .acquire_slot(
+                &key,
+                state.config().max_subscribers_per_channel,
+                state.channel_ttl(),
+            )
+            .await?;
+        if !acquired {
+            return Err(AppError::TooManySubscribers {
+                retry_after: state.config().retry_after,
+            });
+        }
+
+        Ok(Self {
+            state: state.clone(),
+            key: Some(key),
+        })
+    }
+
+    pub async fn release(mut self) -> Result<(), AppError> {
+        if let Some(key) = self.key.take() {
+            self.state.storage().release_slot(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SubscriberSlot {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let state = self.state.clone();
+        // Not `BackgroundTasks`: a release shed at capacity would keep the
+        // slot counted until the counter expires, so it always runs.
+        tokio::spawn(async move {
+            if let Err(err) = state.storage().release_slot(&key).await {
+                tracing::warn!(error = ?err, key, "failed to release subscriber slot");
+            }
+        });
+    }
+}
+
+/// Runs `work` while holding the channel's write lock when `CHANNEL_WRITE_LOCK`
+/// is enabled. A concurrent writer gets `409 Conflict`; the lock is released on
+/// every exit path, and its short TTL covers a crash mid-write.
+pub async fn with_write_lock<T>(
+    state: &SharedState,
+    id: &str,
+    work: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    if !state.config().write_lock {
+        return work.await;
+    }
+
+    let key = state.write_lock_key(id);
+    let token = uuid::Uuid::new_v4().to_string();
+    let acquired = state
+        .storage()
+        .set_nx_ex(&key, &token, state.config().write_lock_ttl)
+        .await?;
+    if !acquired {
+        return Err(AppError::ChannelLocked);
+    }
+
+    let result = work.await;
+
+    // Compare-and-delete, so a lock that expired and was re-acquired by
+    // another writer is never released by mistake.
+    if let Err(err) = state.storage().delete_if_equals(&key, &token).await {
+        tracing::warn!(error = ?err, key, "failed to release channel write lock");
+    }
+
+    result
+}
+
+/// Counts a raw download of `file_id` in the background, so the download never
+/// waits on it. The counters expire together with the channel; a failed
+/// increment is only logged, and one dropped at `MAX_BACKGROUND_TASKS` is lost.
+pub fn count_download(state: &SharedState, id: &str, file_id: &str) {
+    let tasks = state.tasks();
+    let state = state.clone();
+    let id = id.to_owned();
+    let file_id = file_id.to_owned();
+    tasks.spawn(async move {
+        let counted = async {
+            let remaining = state.storage().ttl(&state.channel_key(&id)).await?;
+            if remaining <= 0 {
+                return Ok(());
+            }
+            state
+                .storage()
+                .hincr_ex(
+                    &state.download_count_key(&id),
+                    &file_id,
+                    Duration::from_secs(remaining as u64),
+                )
+                .await
+        };
+        if let Err(err) = counted.await {
+            tracing::warn!(%id, %file_id, error = ?err, "failed to count file download");
+        }
+    });
+}
+
+/// Keys scanned per storage round-trip by the orphan sweep.
+const ORPHAN_SWEEP_PAGE_KEYS: usize = 500;
+
+/// Reports the channels that have expired (see `report_expiries`), deletes
+/// read and download counters whose channel no longer exists, which is
+/// possible when a channel goes away without its counters (e.g. deleted by
+/// hand), and reconciles the tag sets (see `reconcile_tag_sets`). Every step
+/// is safe to run on all instances at once. Returns how many keys and tag set
+/// members were removed.
+pub async fn sweep_orphaned_keys(state: &SharedState) -> Result<usize, AppError> {
+    report_expiries(state).await?;
+    let prefix = &state.config.key_prefix;
+    let mut removed = 0;
+    for kind in ["reads:", "downloads:"] {
+        let kind_prefix = format!("{prefix}{kind}");
+        let mut cursor = None;
+        loop {
+            let page = state
+                .storage()
+                .scan_keys(&kind_prefix, cursor.as_deref(), ORPHAN_SWEEP_PAGE_KEYS)
+                .await?;
+            for key in page.keys {
+                let Some(id) = key.strip_prefix(&kind_prefix) else {
+                    continue;
+                };
+                if !state.storage().exists(&state.channel_key(id)).await? {
+                    state.storage().del(std::slice::from_ref(&key)).await?;
+                    removed += 1;
+                }
+            }
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+    }
+    removed += reconcile_tag_sets(state).await?;
+    Ok(removed)
+}
+
+/// Takes the channels whose tracked expiry has passed off the expiry index and
+/// reports those now gone to the event sinks. Each id is taken by one instance
+/// only, so no expiry is reported twice. A channel exhausted by `max_reads`
+/// did not expire and is skipped; one extended since it was tracked is put
+/// back with its new expiry.
+async fn report_expiries(state: &SharedState) -> Result<(), AppError> {
+    let key = state.expiry_index_key();
+    loop {
+        let due = state
+            .storage()
+            .zpop_due(&key, unix_now(), ORPHAN_SWEEP_PAGE_KEYS)
+            .await?;
+        for id in &due {
+            match state.storage().ttl(&state.channel_key(id)).await? {
+                -2 if state.storage().exists(&state.tombstone_key(id)).await? => {}
+                -2 => state.events().on_expired(id),
+                // At least a second out, so this sweep does not take it again.
+                ttl => track_expiry(state, id, Duration::from_secs(ttl.max(1) as u64)).await?,
+            }
+        }
+        if due.len() < ORPHAN_SWEEP_PAGE_KEYS {
+            return Ok(());
+        }
+    }
+}
+
+/// Drops ids of vanished channels from every tag set and brings each set's
+/// expiry in line with its longest-lived channel, so sets that indexing kept
+/// alive for since-expired channels age out too. Returns the members removed.
+async fn reconcile_tag_sets(state: &SharedState) -> Result<usize, AppError> {
+    let prefix = state.tag_key_prefix();
+    let mut removed = 0;
+    let mut cursor = None;
+    loop {
+        let page = state
+            .storage()
+            .scan_keys(&prefix, cursor.as_deref(), ORPHAN_SWEEP_PAGE_KEYS)
+            .await?;
+        for key in page.keys {
+            let mut gone = Vec::new();
+            let mut longest = 0;
+            for id in state.storage().smembers(&key).await? {
+                match state.storage().ttl(&state.channel_key(&id)).await? {
+                    -2 => gone.push(id),
+                    ttl => longest = longest.max(ttl),
+                }
+            }
+            removed += gone.len();
+            state.storage().srem(&key, &gone).await?;
+            if longest > 0 {
+                // TTLs are whole seconds rounded down; one more keeps the
+                // set from expiring just before its channel.
+                let ttl = Duration::from_secs(longest as u64 + 1);
+                state.storage().expire(&key, ttl).await?;
+            }
+        }
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs `sweep_orphaned_keys` every `period` for as long as the server runs.
+/// A failed sweep is logged and retried on the next tick.
+pub async fn sweep_orphaned_keys_every(state: SharedState, period: Duration) {
+    let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match sweep_orphaned_keys(&state).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(removed, "swept orphaned channel keys"),
+            Err(err) => tracing::warn!(error = ?err, "orphan sweep failed"),
+        }
+    }
+}
+
+/// Moves a channel (with its read and download counters) to a new id without
+/// touching its TTL. Both ids must live on the same shard. The old id's stale
+/// copy is dropped, so an outage cannot serve it under the id given up, and
+/// its tracked expiry moves to the new id.
+pub async fn rename_channel(
+    state: &SharedState,
+    from: &str,
+    to: &str,
+) -> Result<RenameOutcome, AppError> {
+    debug_assert_eq!(state.shard_for(from), state.shard_for(to));
+    let outcome = state
+        .storage()
+        .rename_nx(&[
+            (state.channel_key(from), state.channel_key(to)),
+            (state.read_count_key(from), state.read_count_key(to)),
+            (state.download_count_key(from), state.download_count_key(to)),
+            (state.grace_key(from), state.grace_key(to)),
+            (state.previous_text_key(from), state.previous_text_key(to)),
+            (state.history_key(from), state.history_key(to)),
+        ])
+        .await?;
+    state.stale_cache().forget(from);
+    if outcome == RenameOutcome::Renamed {
+        let ids = [from.to_owned()];
+        state
+            .storage()
+            .zrem(&state.expiry_index_key(), &ids)
+            .await?;
+        let remaining = state.storage().ttl(&state.channel_key(to)).await?;
+        track_expiry(state, to, Duration::from_secs(remaining.max(0) as u64)).await?;
+    }
+    Ok(outcome)
+}
+
+/// Removes channel `id` along with everything kept beside it: counters, the
+/// grace copy, text history, any unredeemed password token, its entries in the
+/// expiry and `tags` indexes and this instance's stale copy.
+pub async fn delete_channel_keys(
+    state: &SharedState,
+    id: &str,
+    tags: &[String],
+) -> Result<(), AppError> {
+    state.stale_cache().forget(id);
+    state
+        .storage()
+        .del(&[
+            state.channel_key(id),
+            state.read_count_key(id),
+            state.download_count_key(id),
+            state.grace_key(id),
+            state.previous_text_key(id),
+            state.history_key(id),
+            state.password_token_key(id),
+        ])
+        .await?;
+    unindex_channel(state, id, tags).await
+}
+
+/// Looks for `id` under each of `LEGACY_KEY_PREFIXES` and moves the first hit
+/// (payload and read counter) to the current prefix. Renaming keeps the
+/// remaining TTL. Returns whether a channel now sits under the current key.
+pub async fn migrate_legacy_channel(state: &SharedState, id: &str) -> Result<bool, AppError> {
+    for legacy in &state.config().legacy_key_prefixes {
+        let outcome = state
+            .storage()
+            .rename_nx(&[
+                (format!("{legacy}channel:{id}"), state.channel_key(id)),
+                (format!("{legacy}reads:{id}"), state.read_count_key(id)),
+            ])
+            .await?;
+        match outcome {
+            RenameOutcome::Renamed => {
+                tracing::info!(%id, legacy_prefix = %legacy, "migrated legacy channel");
+                return Ok(true);
+            }
+            // Another request migrated (or recreated) it first.
+            RenameOutcome::TargetTaken => return Ok(true),
+            RenameOutcome::SourceMissing => {}
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::{
+        AppState, ReadinessCache, SubscriberSlot, delete_channel_keys, refresh_target, shared,
+        sweep_orphaned_keys, track_expiry,
+    };
+    use crate::{config::AppConfig, error::AppError, storage::MemoryStorage};
+
+    #[tokio::test(start_paused = true)]
+    async fn readiness_is_probed_once_per_cache_window() {
+        let cache = ReadinessCache::default();
+        let pings = AtomicUsize::new(0);
+        let window = Duration::from_millis(500);
+        let probe = || async {
+            pings.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        };
+
+        for _ in 0..5 {
+            assert!(cache.check(window, probe).await);
+        }
+        assert_eq!(pings.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(window).await;
+        assert!(cache.check(window, probe).await);
+        assert_eq!(pings.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_probes_are_not_cached() {
+        let cache = ReadinessCache::default();
+        let window = Duration::from_secs(60);
+        assert!(
+            !cache
+                .check(window, || async { Err(AppError::ChannelNotFound) })
+                .await
+        );
+        assert!(cache.check(window, || async { Ok(()) }).await);
+    }
+
+    #[test]
+    fn refresh_uses_full_ttl_without_lifetime_cap() {
+        assert_eq!(refresh_target(1_000, Some(0), 900, None), Some(900));
+        assert_eq!(refresh_target(1_000, None, 900, Some(60)), Some(900));
+    }
+
+    #[test]
+    fn refresh_is_clamped_near_the_lifetime_boundary() {
+        // created at 0, lifetime 3600: plenty of room, then 100s left.
+        assert_eq!(refresh_target(1_000, Some(0), 900, Some(3_600)), Some(900));
+        assert_eq!(refresh_target(3_500, Some(0), 900, Some(3_600)), Some(100));
+        assert_eq!(refresh_target(3_599, Some(0), 900, Some(3_600)), Some(1));
+    }
+
+    #[test]
+    fn refresh_is_a_no_op_once_lifetime_has_passed() {
+        assert_eq!(refresh_target(3_600, Some(0), 900, Some(3_600)), None);
+        assert_eq!(refresh_target(9_999, Some(0), 900, Some(3_600)), None);
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_only_counters_of_vanished_channels() {
+        let state = shared(AppState::new(
+            AppConfig::default(),
+            Box::new(MemoryStorage::new()),
+        ));
+        let ttl = Duration::from_secs(60);
+        let storage = state.storage();
+        storage
+            .set_ex(&state.channel_key("live"), "{}", ttl)
+            .await
+            .unwrap();
+        for key in [
+            state.read_count_key("live"),
+            state.read_count_key("gone"),
+            state.download_count_key("gone"),
+            state.tombstone_key("gone"),
+        ] {
+            storage.set_ex(&key, "1", ttl).await.unwrap();
+        }
+
+        assert_eq!(sweep_orphaned_keys(&state).await.unwrap(), 2);
+        assert!(storage.exists(&state.read_count_key("live")).await.unwrap());
+        assert!(!storage.exists(&state.read_count_key("gone")).await.unwrap());
+        assert!(
+            !storage
+                .exists(&state.download_count_key("gone"))
+                .await
+                .unwrap()
+        );
+        // Tombstones outlive their channel on purpose.
+        assert!(storage.exists(&state.tombstone_key("gone")).await.unwrap());
+
+        assert_eq!(sweep_orphaned_keys(&state).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sweep_prunes_tag_sets_and_matches_their_expiry_to_the_channels() {
+        let state = shared(AppState::new(
+            AppConfig::default(),
+            Box::new(MemoryStorage::new()),
+        ));
+        let storage = state.storage();
+        storage
+            .set_ex(&state.channel_key("live"), "{}", Duration::from_secs(60))
+            .await
+            .unwrap();
+        let tag = state.tag_key("team");
+        for id in ["live", "gone"] {
+            storage
+                .sadd_ex(&tag, id, Duration::from_secs(600))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(sweep_orphaned_keys(&state).await.unwrap(), 1);
+        assert_eq!(storage.smembers(&tag).await.unwrap(), ["live"]);
+        assert!((60..=61).contains(&storage.ttl(&tag).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn sweep_reports_expired_channels_but_not_deleted_or_extended_ones() {
+        let state = shared(AppState::new(
+            AppConfig {
+                orphan_sweep_interval: Some(Duration::from_secs(60)),
+                count_channel_events: true,
+                ..AppConfig::default()
+            },
+            Box::new(MemoryStorage::new()),
+        ));
+        let storage = state.storage();
+        let ttl = Duration::from_secs(1);
+        for id in ["plain", "deleted", "exhausted", "extended"] {
+            storage
+                .set_ex(&state.channel_key(id), "{}", ttl)
+                .await
+                .unwrap();
+            track_expiry(&state, id, ttl).await.unwrap();
+        }
+        let tags = ["team".to_owned()];
+        storage
+            .sadd_ex(&state.tag_key("team"), "deleted", ttl)
+            .await
+            .unwrap();
+        delete_channel_keys(&state, "deleted", &tags).await.unwrap();
+        assert!(!storage.exists(&state.tag_key("team")).await.unwrap());
+        storage
+            .consume_read(
+                &state.channel_key("exhausted"),
+                &state.read_count_key("exhausted"),
+                &state.tombstone_key("exhausted"),
+                1,
+                Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+        storage
+            .expire(&state.channel_key("extended"), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        sweep_orphaned_keys(&state).await.unwrap();
+        sweep_orphaned_keys(&state).await.unwrap();
+        assert_eq!(state.event_counts().unwrap().totals().expired, 1);
+        assert_eq!(
+            storage
+                .zpop_due(&state.expiry_index_key(), u64::MAX, 10)
+                .await
+                .unwrap(),
+            ["extended"]
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_slots_are_released_with_background_tasks_saturated() {
+        let state = shared(AppState::new(
+            AppConfig {
+                max_background_tasks: 1,
+                max_subscribers_per_channel: 1,
+                ..AppConfig::default()
+            },
+            Box::new(MemoryStorage::new()),
+        ));
+        let (_hold, held) = tokio::sync::oneshot::channel::<()>();
+        assert!(state.tasks().spawn(async move {
+            let _ = held.await;
+        }));
+
+        drop(SubscriberSlot::acquire(&state, "a").await.unwrap());
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(SubscriberSlot::acquire(&state, "a").await.is_ok());
+    }
+}
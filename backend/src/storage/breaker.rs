@@ -0,0 +1,392 @@
+use std::{collections::HashMap, future::Future, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::time::Instant;
+
+use super::{KeyPage, LatencyEstimate, RenameOutcome, Storage};
+use crate::error::AppError;
+
+/// Where the breaker stands, as reported by `/ready`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+enum Phase {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// One trial call is let through; if it never reports back (the request
+    /// was cancelled), another is allowed after a further cooldown.
+    HalfOpen {
+        trial_started: Instant,
+    },
+}
+
+/// Consecutive-failure circuit breaker. After `threshold` backend failures in
+/// a row it opens and fast-fails every call for `cooldown`, then lets a single
+/// trial through: success closes it again, failure re-opens it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    phase: Mutex<Phase>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of zero disables the breaker.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            phase: Mutex::new(Phase::Closed { failures: 0 }),
+        }
+    }
+
+    fn phase(&self) -> std::sync::MutexGuard<'_, Phase> {
+        self.phase.lock().expect("circuit breaker lock poisoned")
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.phase() {
+            Phase::Closed { .. } => CircuitState::Closed,
+            Phase::Open { until } if Instant::now() < until => CircuitState::Open,
+            Phase::Open { .. } | Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may go to the backend right now.
+    pub fn admit(&self) -> Result<(), AppError> {
+        if self.threshold == 0 {
+            return Ok(());
+        }
+        let mut phase = self.phase();
+        let now = Instant::now();
+        let next_trial = match *phase {
+            Phase::Closed { .. } => return Ok(()),
+            Phase::Open { until } => until,
+            Phase::HalfOpen { trial_started } => trial_started + self.cooldown,
+        };
+        if now < next_trial {
+            return Err(AppError::StorageUnavailable {
+                retry_after: next_trial - now,
+            });
+        }
+        *phase = Phase::HalfOpen { trial_started: now };
+        Ok(())
+    }
+
+    pub fn record_success(&self) {
+        *self.phase() = Phase::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut phase = self.phase();
+        let failures = match *phase {
+            Phase::Closed { failures } => failures + 1,
+            Phase::Open { .. } | Phase::HalfOpen { .. } => self.threshold,
+        };
+        *phase = if failures >= self.threshold {
+            tracing::warn!(failures, "storage circuit opened");
+            Phase::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            Phase::Closed { failures }
+        };
+    }
+}
+
+/// Runs every storage call through a `CircuitBreaker`. Only backend failures
+/// (connection and protocol errors) count against it. Calls that reach the
+/// backend also feed a rolling latency estimate.
+pub struct GuardedStorage {
+    inner: Box<dyn Storage>,
+    breaker: CircuitBreaker,
+    latency: LatencyEstimate,
+}
+
+impl GuardedStorage {
+    pub fn new(inner: Box<dyn Storage>, breaker: CircuitBreaker) -> Self {
+        Self {
+            inner,
+            breaker,
+            latency: LatencyEstimate::default(),
+        }
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
+    pub fn latency(&self) -> &LatencyEstimate {
+        &self.latency
+    }
+
+    async fn guard<T>(
+        &self,
+        call: impl Future<Output = Result<T, AppError>> + Send,
+    ) -> Result<T, AppError> {
+        self.breaker.admit()?;
+        let started = Instant::now();
+        let result = call.await;
+        self.latency.record(started.elapsed());
+        match &result {
+            Err(AppError::Redis(_) | AppError::Io(_)) => self.breaker.record_failure(),
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for GuardedStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError> {
+        self.guard(self.inner.get(key)).await
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>, AppError> {
+        self.guard(self.inner.get_del(key)).await
+    }
+
+    async fn get_replica(&self, key: &str) -> Result<Option<String>, AppError> {
+        self.guard(self.inner.get_replica(key)).await
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<(), AppError> {
+        self.guard(self.inner.set_ex(key, value, ttl)).await
+    }
+
+    async fn set_many_ex(&self, entries: &[(String, String, Duration)]) -> Result<(), AppError> {
+        self.guard(self.inner.set_many_ex(entries)).await
+    }
+
+    async fn set_nx_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, AppError> {
+        self.guard(self.inner.set_nx_ex(key, value, ttl)).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<i64, AppError> {
+        self.guard(self.inner.ttl(key)).await
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), AppError> {
+        self.guard(self.inner.expire(key, ttl)).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        self.guard(self.inner.exists(key)).await
+    }
+
+    async fn del(&self, keys: &[String]) -> Result<(), AppError> {
+        self.guard(self.inner.del(keys)).await
+    }
+
+    async fn hincr_ex(&self, key: &str, field: &str, ttl: Duration) -> Result<(), AppError> {
+        self.guard(self.inner.hincr_ex(key, field, ttl)).await
+    }
+
+    async fn hget_counts(&self, key: &str) -> Result<HashMap<String, u64>, AppError> {
+        self.guard(self.inner.hget_counts(key)).await
+    }
+
+    async fn sadd_ex(&self, key: &str, member: &str, ttl: Duration) -> Result<(), AppError> {
+        self.guard(self.inner.sadd_ex(key, member, ttl)).await
+    }
+
+    async fn smembers(&self, key: &str) -> Result<Vec<String>, AppError> {
+        self.guard(self.inner.smembers(key)).await
+    }
+
+    async fn srem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        self.guard(self.inner.srem(key, members)).await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: u64) -> Result<(), AppError> {
+        self.guard(self.inner.zadd(key, member, score)).await
+    }
+
+    async fn zrem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        self.guard(self.inner.zrem(key, members)).await
+    }
+
+    async fn zpop_due(
+        &self,
+        key: &str,
+        max_score: u64,
+        count: usize,
+    ) -> Result<Vec<String>, AppError> {
+        self.guard(self.inner.zpop_due(key, max_score, count)).await
+    }
+
+    async fn lpush_capped(
+        &self,
+        key: &str,
+        value: &str,
+        max_len: usize,
+        max_bytes: usize,
+        ttl: Duration,
+    ) -> Result<(), AppError> {
+        self.guard(self.inner.lpush_capped(key, value, max_len, max_bytes, ttl))
+            .await
+    }
+
+    async fn lrange_all(&self, key: &str) -> Result<Vec<String>, AppError> {
+        self.guard(self.inner.lrange_all(key)).await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.guard(self.inner.ping()).await
+    }
+
+    async fn consume_read(
+        &self,
+        channel: &str,
+        counter: &str,
+        tombstone: &str,
+        max_reads: u32,
+        ttl: Duration,
+    ) -> Result<Option<String>, AppError> {
+        self.guard(
+            self.inner
+                .consume_read(channel, counter, tombstone, max_reads, ttl),
+        )
+        .await
+    }
+
+    async fn acquire_slot(&self, key: &str, cap: u32, ttl: Duration) -> Result<bool, AppError> {
+        self.guard(self.inner.acquire_slot(key, cap, ttl)).await
+    }
+
+    async fn release_slot(&self, key: &str) -> Result<(), AppError> {
+        self.guard(self.inner.release_slot(key)).await
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, AppError> {
+        self.guard(self.inner.take_token(key, capacity, refill))
+            .await
+    }
+
+    async fn delete_if_equals(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        self.guard(self.inner.delete_if_equals(key, value)).await
+    }
+
+    async fn replace_if_equals(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+    ) -> Result<bool, AppError> {
+        self.guard(self.inner.replace_if_equals(key, expected, value))
+            .await
+    }
+
+    async fn rename_nx(&self, renames: &[(String, String)]) -> Result<RenameOutcome, AppError> {
+        self.guard(self.inner.rename_nx(renames)).await
+    }
+
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<KeyPage, AppError> {
+        self.guard(self.inner.scan_keys(prefix, cursor, count))
+            .await
+    }
+
+    async fn scan_sizes(
+        &self,
+        prefix: &str,
+        visit: &mut (dyn FnMut(String, u64) + Send),
+    ) -> Result<(), AppError> {
+        self.guard(self.inner.scan_sizes(prefix, visit)).await
+    }
+
+    fn shard_of(&self, id: &str) -> usize {
+        self.inner.shard_of(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CircuitBreaker, CircuitState};
+    use crate::error::AppError;
+
+    const COOLDOWN: Duration = Duration::from_secs(5);
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_after_consecutive_failures_and_fast_fails() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.admit().is_ok());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(
+            breaker.admit(),
+            Err(AppError::StorageUnavailable { retry_after }) if retry_after == COOLDOWN
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_opens_after_cooldown_and_recovers_on_success() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        tokio::time::advance(COOLDOWN).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        assert!(breaker.admit().is_ok());
+        // Only one trial at a time while half-open.
+        assert!(breaker.admit().is_err());
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.admit().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_trial_reopens_for_another_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        tokio::time::advance(COOLDOWN).await;
+        assert!(breaker.admit().is_ok());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        tokio::time::advance(COOLDOWN / 2).await;
+        assert!(breaker.admit().is_err());
+        tokio::time::advance(COOLDOWN / 2).await;
+        assert!(breaker.admit().is_ok());
+    }
+
+    #[test]
+    fn zero_threshold_disables_the_breaker() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.admit().is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
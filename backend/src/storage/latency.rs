@@ -0,0 +1,65 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Weight of the newest sample, as a shift: each one moves the estimate an
+/// eighth of the way towards it, like TCP's smoothed RTT.
+const SMOOTHING_SHIFT: u32 = 3;
+
+/// Exponentially weighted moving average of storage call latency, in
+/// microseconds. Zero means no call has completed yet.
+#[derive(Default)]
+pub struct LatencyEstimate {
+    micros: AtomicU64,
+}
+
+impl LatencyEstimate {
+    /// Folds one completed call into the estimate. The first sample is taken
+    /// as is, so a cold start is not mistaken for a fast backend.
+    pub fn record(&self, sample: Duration) {
+        let sample = u64::try_from(sample.as_micros()).unwrap_or(u64::MAX).max(1);
+        let _ = self
+            .micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                Some(if current == 0 {
+                    sample
+                } else {
+                    let delta = (sample as i128 - current as i128) >> SMOOTHING_SHIFT;
+                    (current as i128 + delta).max(1) as u64
+                })
+            });
+    }
+
+    /// The current estimate, `None` before the first sample.
+    pub fn current(&self) -> Option<Duration> {
+        match self.micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LatencyEstimate;
+
+    #[test]
+    fn first_sample_seeds_the_estimate_and_later_ones_are_smoothed() {
+        let latency = LatencyEstimate::default();
+        assert_eq!(latency.current(), None);
+
+        latency.record(Duration::from_millis(8));
+        assert_eq!(latency.current(), Some(Duration::from_millis(8)));
+
+        latency.record(Duration::from_millis(16));
+        assert_eq!(latency.current(), Some(Duration::from_millis(9)));
+
+        for _ in 0..100 {
+            latency.record(Duration::from_millis(1));
+        }
+        assert!(latency.current().unwrap() < Duration::from_millis(2));
+    }
+}
@@ -0,0 +1,693 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use super::{KeyPage, RenameOutcome, Storage};
+use crate::error::AppError;
+
+struct Entry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|deadline| deadline > now)
+    }
+}
+
+/// Single-process stand-in for Redis, for local dev and hermetic tests.
+/// Expiry follows tokio's clock, so tests can pause and advance time. Clones
+/// share the same entries, so a test can keep a handle on the store it gave
+/// to `AppState`.
+#[derive(Default, Clone)]
+pub struct MemoryStorage {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    offline: Arc<AtomicBool>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// While offline every call fails the way an unreachable Redis does, for
+    /// exercising outage handling. Entries are kept for when it comes back.
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::Relaxed);
+    }
+
+    /// Locks the map with expired entries already dropped, so every operation
+    /// sees the same view Redis would.
+    fn entries(&self) -> Result<MutexGuard<'_, HashMap<String, Entry>>, AppError> {
+        if self.offline.load(Ordering::Relaxed) {
+            return Err(AppError::Io(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "memory storage is offline",
+            )));
+        }
+        let mut entries = self.entries.lock().expect("memory storage lock poisoned");
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.live(now));
+        Ok(entries)
+    }
+}
+
+fn expiring(value: String, ttl: Duration) -> Entry {
+    Entry {
+        value,
+        expires_at: Some(Instant::now() + ttl),
+    }
+}
+
+/// INCRBY on a string value, keeping the existing expiry like Redis does.
+fn increment(entries: &mut HashMap<String, Entry>, key: &str, by: i64) -> i64 {
+    let entry = entries.entry(key.to_owned()).or_insert_with(|| Entry {
+        value: "0".into(),
+        expires_at: None,
+    });
+    let count = entry.value.parse::<i64>().unwrap_or(0) + by;
+    entry.value = count.to_string();
+    count
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self.entries()?.get(key).map(|entry| entry.value.clone()))
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self.entries()?.remove(key).map(|entry| entry.value))
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<(), AppError> {
+        self.entries()?
+            .insert(key.to_owned(), expiring(value.to_owned(), ttl));
+        Ok(())
+    }
+
+    async fn set_many_ex(&self, entries: &[(String, String, Duration)]) -> Result<(), AppError> {
+        let mut map = self.entries()?;
+        for (key, value, ttl) in entries {
+            map.insert(key.clone(), expiring(value.clone(), *ttl));
+        }
+        Ok(())
+    }
+
+    async fn set_nx_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, AppError> {
+        let mut entries = self.entries()?;
+        if entries.contains_key(key) {
+            return Ok(false);
+        }
+        entries.insert(key.to_owned(), expiring(value.to_owned(), ttl));
+        Ok(true)
+    }
+
+    async fn ttl(&self, key: &str) -> Result<i64, AppError> {
+        Ok(match self.entries()?.get(key) {
+            None => -2,
+            Some(Entry {
+                expires_at: None, ..
+            }) => -1,
+            Some(Entry {
+                expires_at: Some(deadline),
+                ..
+            }) => deadline.saturating_duration_since(Instant::now()).as_secs() as i64,
+        })
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), AppError> {
+        if let Some(entry) = self.entries()?.get_mut(key) {
+            entry.expires_at = Some(Instant::now() + ttl);
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(self.entries()?.contains_key(key))
+    }
+
+    async fn del(&self, keys: &[String]) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        for key in keys {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    /// The hash is kept as a JSON object in an ordinary entry.
+    async fn hincr_ex(&self, key: &str, field: &str, ttl: Duration) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let mut counts: HashMap<String, u64> = match entries.get(key) {
+            Some(entry) => serde_json::from_str(&entry.value)?,
+            None => HashMap::new(),
+        };
+        *counts.entry(field.to_owned()).or_default() += 1;
+        entries.insert(
+            key.to_owned(),
+            expiring(serde_json::to_string(&counts)?, ttl),
+        );
+        Ok(())
+    }
+
+    async fn hget_counts(&self, key: &str) -> Result<HashMap<String, u64>, AppError> {
+        match self.entries()?.get(key) {
+            Some(entry) => Ok(serde_json::from_str(&entry.value)?),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    async fn sadd_ex(&self, key: &str, member: &str, ttl: Duration) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let expires_at = Instant::now() + ttl;
+        let (mut members, expires_at) = match entries.get(key) {
+            Some(entry) => (
+                serde_json::from_str::<BTreeSet<String>>(&entry.value)?,
+                entry.expires_at.map(|current| current.max(expires_at)),
+            ),
+            None => (BTreeSet::new(), Some(expires_at)),
+        };
+        members.insert(member.to_owned());
+        entries.insert(
+            key.to_owned(),
+            Entry {
+                value: serde_json::to_string(&members)?,
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    async fn lpush_capped(
+        &self,
+        key: &str,
+        value: &str,
+        max_len: usize,
+        max_bytes: usize,
+        ttl: Duration,
+    ) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let mut list = match entries.get(key) {
+            Some(entry) => serde_json::from_str::<Vec<String>>(&entry.value)?,
+            None => Vec::new(),
+        };
+        list.insert(0, value.to_owned());
+        list.truncate(max_len);
+        let mut total = 0;
+        if let Some(over) = list.iter().position(|entry| {
+            total += entry.len();
+            total > max_bytes
+        }) {
+            list.truncate(over);
+        }
+        if list.is_empty() {
+            entries.remove(key);
+            return Ok(());
+        }
+        entries.insert(key.to_owned(), expiring(serde_json::to_string(&list)?, ttl));
+        Ok(())
+    }
+
+    async fn lrange_all(&self, key: &str) -> Result<Vec<String>, AppError> {
+        match self.entries()?.get(key) {
+            Some(entry) => Ok(serde_json::from_str(&entry.value)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn smembers(&self, key: &str) -> Result<Vec<String>, AppError> {
+        match self.entries()?.get(key) {
+            Some(entry) => Ok(serde_json::from_str(&entry.value)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn srem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let Some(entry) = entries.get_mut(key) else {
+            return Ok(());
+        };
+        let mut remaining: BTreeSet<String> = serde_json::from_str(&entry.value)?;
+        for member in members {
+            remaining.remove(member);
+        }
+        if remaining.is_empty() {
+            entries.remove(key);
+        } else {
+            entry.value = serde_json::to_string(&remaining)?;
+        }
+        Ok(())
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: u64) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let entry = entries.entry(key.to_owned()).or_insert_with(|| Entry {
+            value: "{}".into(),
+            expires_at: None,
+        });
+        let mut scores: BTreeMap<String, u64> = serde_json::from_str(&entry.value)?;
+        scores.insert(member.to_owned(), score);
+        entry.value = serde_json::to_string(&scores)?;
+        Ok(())
+    }
+
+    async fn zrem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let Some(entry) = entries.get_mut(key) else {
+            return Ok(());
+        };
+        let mut scores: BTreeMap<String, u64> = serde_json::from_str(&entry.value)?;
+        for member in members {
+            scores.remove(member);
+        }
+        if scores.is_empty() {
+            entries.remove(key);
+        } else {
+            entry.value = serde_json::to_string(&scores)?;
+        }
+        Ok(())
+    }
+
+    async fn zpop_due(
+        &self,
+        key: &str,
+        max_score: u64,
+        count: usize,
+    ) -> Result<Vec<String>, AppError> {
+        let mut entries = self.entries()?;
+        let Some(entry) = entries.get_mut(key) else {
+            return Ok(Vec::new());
+        };
+        let mut scores: BTreeMap<String, u64> = serde_json::from_str(&entry.value)?;
+        let mut due: Vec<_> = scores
+            .iter()
+            .filter(|(_, score)| **score <= max_score)
+            .map(|(member, score)| (*score, member.clone()))
+            .collect();
+        due.sort();
+        due.truncate(count);
+        for (_, member) in &due {
+            scores.remove(member);
+        }
+        if scores.is_empty() {
+            entries.remove(key);
+        } else {
+            entry.value = serde_json::to_string(&scores)?;
+        }
+        Ok(due.into_iter().map(|(_, member)| member).collect())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.entries().map(drop)
+    }
+
+    async fn consume_read(
+        &self,
+        channel: &str,
+        counter: &str,
+        tombstone: &str,
+        max_reads: u32,
+        ttl: Duration,
+    ) -> Result<Option<String>, AppError> {
+        let mut entries = self.entries()?;
+        let Some(raw) = entries.get(channel).map(|entry| entry.value.clone()) else {
+            return Ok(None);
+        };
+        let count = increment(&mut entries, counter, 1);
+        if let Some(entry) = entries.get_mut(counter) {
+            entry.expires_at = Some(Instant::now() + ttl);
+        }
+        if count >= i64::from(max_reads) {
+            entries.remove(channel);
+            entries.remove(counter);
+            entries.insert(tombstone.to_owned(), expiring("1".into(), ttl));
+        }
+        Ok(Some(raw))
+    }
+
+    async fn acquire_slot(&self, key: &str, cap: u32, ttl: Duration) -> Result<bool, AppError> {
+        let mut entries = self.entries()?;
+        let count = increment(&mut entries, key, 1);
+        if let Some(entry) = entries.get_mut(key) {
+            entry.expires_at.get_or_insert_with(|| Instant::now() + ttl);
+        }
+        if count > i64::from(cap) {
+            increment(&mut entries, key, -1);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    async fn release_slot(&self, key: &str) -> Result<(), AppError> {
+        let mut entries = self.entries()?;
+        let held = entries
+            .get(key)
+            .and_then(|entry| entry.value.parse::<i64>().ok())
+            .is_some_and(|count| count > 0);
+        if held {
+            increment(&mut entries, key, -1);
+        }
+        Ok(())
+    }
+
+    /// The bucket is kept as the instant it will be full again, which is also
+    /// when its entry expires; a missing entry is a full bucket.
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, AppError> {
+        let mut entries = self.entries()?;
+        let now = Instant::now();
+        let full_at = entries
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .map_or(now, |full_at| full_at.max(now));
+        let burst = refill * capacity.saturating_sub(1);
+        let wait = full_at.saturating_duration_since(now).saturating_sub(burst);
+        if !wait.is_zero() {
+            return Ok(Some(wait));
+        }
+        entries.insert(
+            key.to_owned(),
+            Entry {
+                value: String::new(),
+                expires_at: Some(full_at + refill),
+            },
+        );
+        Ok(None)
+    }
+
+    async fn delete_if_equals(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        let mut entries = self.entries()?;
+        if entries.get(key).is_some_and(|entry| entry.value == value) {
+            entries.remove(key);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn replace_if_equals(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+    ) -> Result<bool, AppError> {
+        let mut entries = self.entries()?;
+        match entries.get_mut(key) {
+            Some(entry) if entry.value == expected => {
+                entry.value = value.to_owned();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn rename_nx(&self, renames: &[(String, String)]) -> Result<RenameOutcome, AppError> {
+        let mut entries = self.entries()?;
+        let Some((from, to)) = renames.first() else {
+            return Ok(RenameOutcome::SourceMissing);
+        };
+        if !entries.contains_key(from) {
+            return Ok(RenameOutcome::SourceMissing);
+        }
+        if entries.contains_key(to) {
+            return Ok(RenameOutcome::TargetTaken);
+        }
+        for (from, to) in renames {
+            if let Some(entry) = entries.remove(from) {
+                entries.insert(to.clone(), entry);
+            }
+        }
+        Ok(RenameOutcome::Renamed)
+    }
+
+    /// The cursor is the last key returned; pages follow key order.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<KeyPage, AppError> {
+        let mut keys: Vec<String> = self
+            .entries()?
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .filter(|key| cursor.is_none_or(|after| key.as_str() > after))
+            .cloned()
+            .collect();
+        keys.sort_unstable();
+        let more = keys.len() > count.max(1);
+        keys.truncate(count.max(1));
+        let next = more.then(|| keys.last().cloned()).flatten();
+        Ok(KeyPage { keys, next })
+    }
+
+    async fn scan_sizes(
+        &self,
+        prefix: &str,
+        visit: &mut (dyn FnMut(String, u64) + Send),
+    ) -> Result<(), AppError> {
+        let sizes: Vec<(String, u64)> = self
+            .entries()?
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, entry)| (key.clone(), entry.value.len() as u64))
+            .collect();
+        for (key, bytes) in sizes {
+            visit(key, bytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::MemoryStorage;
+    use crate::storage::{RenameOutcome, Storage};
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_with_the_clock() {
+        let storage = MemoryStorage::new();
+        storage
+            .set_ex("channel:a", "x", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(storage.ttl("channel:a").await.unwrap(), 10);
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert_eq!(storage.get("channel:a").await.unwrap(), None);
+        assert_eq!(storage.ttl("channel:a").await.unwrap(), -2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replace_only_swaps_the_expected_value_and_keeps_the_ttl() {
+        let storage = MemoryStorage::new();
+        storage
+            .set_ex("channel:a", "v1", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(
+            !storage
+                .replace_if_equals("channel:a", "v0", "v2")
+                .await
+                .unwrap()
+        );
+        assert!(
+            storage
+                .replace_if_equals("channel:a", "v1", "v2")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            storage.get("channel:a").await.unwrap().as_deref(),
+            Some("v2")
+        );
+        assert_eq!(storage.ttl("channel:a").await.unwrap(), 10);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leaked_slots_age_out_despite_retries() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(10);
+        assert!(storage.acquire_slot("subs:a", 1, ttl).await.unwrap());
+        for _ in 0..5 {
+            tokio::time::advance(Duration::from_secs(3)).await;
+            if storage.acquire_slot("subs:a", 1, ttl).await.unwrap() {
+                return;
+            }
+        }
+        panic!("a refused claim must not keep the leaked slot alive");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn releasing_an_expired_slot_leaves_no_counter() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(10);
+        assert!(storage.acquire_slot("subs:a", 1, ttl).await.unwrap());
+        tokio::time::advance(Duration::from_secs(11)).await;
+        storage.release_slot("subs:a").await.unwrap();
+        assert!(!storage.exists("subs:a").await.unwrap());
+
+        assert!(storage.acquire_slot("subs:a", 1, ttl).await.unwrap());
+        assert!(!storage.acquire_slot("subs:a", 1, ttl).await.unwrap());
+        storage.release_slot("subs:a").await.unwrap();
+        storage.release_slot("subs:a").await.unwrap();
+        assert_eq!(storage.get("subs:a").await.unwrap().as_deref(), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn set_nx_does_not_overwrite() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(10);
+        assert!(storage.set_nx_ex("lock:a", "one", ttl).await.unwrap());
+        assert!(!storage.set_nx_ex("lock:a", "two", ttl).await.unwrap());
+        assert!(!storage.delete_if_equals("lock:a", "two").await.unwrap());
+        assert!(storage.delete_if_equals("lock:a", "one").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn due_members_are_popped_once_lowest_first() {
+        let storage = MemoryStorage::new();
+        for (member, score) in [("c", 30), ("a", 10), ("b", 20), ("later", 99)] {
+            storage.zadd("expiries", member, score).await.unwrap();
+        }
+        storage.zadd("expiries", "c", 15).await.unwrap();
+
+        assert_eq!(
+            storage.zpop_due("expiries", 50, 2).await.unwrap(),
+            ["a", "c"]
+        );
+        assert_eq!(storage.zpop_due("expiries", 50, 2).await.unwrap(), ["b"]);
+        assert!(
+            storage
+                .zpop_due("expiries", 50, 2)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        storage.zrem("expiries", &["later".into()]).await.unwrap();
+        assert!(!storage.exists("expiries").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn consume_read_leaves_a_tombstone_at_the_limit() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(10);
+        storage.set_ex("channel:a", "x", ttl).await.unwrap();
+
+        let read = || storage.consume_read("channel:a", "reads:a", "gone:a", 2, ttl);
+        assert_eq!(read().await.unwrap().as_deref(), Some("x"));
+        assert_eq!(read().await.unwrap().as_deref(), Some("x"));
+        assert_eq!(read().await.unwrap(), None);
+        assert!(storage.exists("gone:a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rename_refuses_taken_targets() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(10);
+        storage.set_ex("channel:a", "x", ttl).await.unwrap();
+        storage.set_ex("channel:b", "y", ttl).await.unwrap();
+
+        let rename = |to: &str| vec![("channel:a".to_owned(), format!("channel:{to}"))];
+        assert_eq!(
+            storage.rename_nx(&rename("b")).await.unwrap(),
+            RenameOutcome::TargetTaken
+        );
+        assert_eq!(
+            storage.rename_nx(&rename("c")).await.unwrap(),
+            RenameOutcome::Renamed
+        );
+        assert_eq!(
+            storage.get("channel:c").await.unwrap().as_deref(),
+            Some("x")
+        );
+        assert_eq!(
+            storage.rename_nx(&rename("d")).await.unwrap(),
+            RenameOutcome::SourceMissing
+        );
+    }
+
+    #[tokio::test]
+    async fn offline_store_fails_every_call_and_keeps_its_entries() {
+        let storage = MemoryStorage::new();
+        let handle = storage.clone();
+        storage
+            .set_ex("channel:a", "x", Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        handle.set_offline(true);
+        assert!(storage.get("channel:a").await.is_err());
+        assert!(storage.ping().await.is_err());
+
+        handle.set_offline(false);
+        assert_eq!(
+            storage.get("channel:a").await.unwrap().as_deref(),
+            Some("x")
+        );
+    }
+
+    #[tokio::test]
+    async fn key_scan_pages_through_matching_keys() {
+        let storage = MemoryStorage::new();
+        for key in ["channel:a", "channel:b", "channel:c", "reads:a"] {
+            storage
+                .set_ex(key, "v", Duration::from_secs(60))
+                .await
+                .unwrap();
+        }
+        let first = storage.scan_keys("channel:", None, 2).await.unwrap();
+        assert_eq!(first.keys, ["channel:a", "channel:b"]);
+        let second = storage
+            .scan_keys("channel:", first.next.as_deref(), 2)
+            .await
+            .unwrap();
+        assert_eq!(second.keys, ["channel:c"]);
+        assert!(second.next.is_none());
+    }
+
+    #[tokio::test]
+    async fn capped_lists_drop_their_oldest_entries() {
+        let storage = MemoryStorage::new();
+        let ttl = Duration::from_secs(60);
+        for value in ["aaaa", "bbbb", "cccc", "dddd"] {
+            storage
+                .lpush_capped("history:a", value, 3, 100, ttl)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            storage.lrange_all("history:a").await.unwrap(),
+            ["dddd", "cccc", "bbbb"]
+        );
+
+        storage
+            .lpush_capped("history:a", "eeeeeeee", 3, 12, ttl)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.lrange_all("history:a").await.unwrap(),
+            ["eeeeeeee", "dddd"]
+        );
+        storage
+            .lpush_capped("history:a", &"f".repeat(13), 3, 12, ttl)
+            .await
+            .unwrap();
+        assert!(storage.lrange_all("history:a").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+mod breaker;
+mod latency;
+mod memory;
+mod redis;
+
+pub use self::redis::RedisStorage;
+pub use breaker::{CircuitBreaker, CircuitState, GuardedStorage};
+pub use latency::LatencyEstimate;
+pub use memory::MemoryStorage;
+
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::error::AppError;
+
+/// A page of `scan_keys`. `next` is an opaque cursor for the following page,
+/// `None` once every key has been visited.
+#[derive(Debug, Default)]
+pub struct KeyPage {
+    pub keys: Vec<String>,
+    pub next: Option<String>,
+}
+
+/// Key-value operations the app needs from its backing store. Keys are the
+/// `{kind}:{id}` strings built by `AppState`; every key of one channel must be
+/// served by the same backend instance so the multi-key operations below stay
+/// atomic.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError>;
+
+    /// `get` and delete in one atomic step, so of several callers racing for
+    /// the same key only one gets the value.
+    async fn get_del(&self, key: &str) -> Result<Option<String>, AppError>;
+
+    /// `get` from a read replica where one is configured, falling back to the
+    /// primary when the replica misses (it may lag behind) or fails. Only for
+    /// reads that never feed a write.
+    async fn get_replica(&self, key: &str) -> Result<Option<String>, AppError> {
+        self.get(key).await
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<(), AppError>;
+
+    /// Sets `key` only if it does not exist yet. Returns `false` when it did.
+    async fn set_nx_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, AppError>;
+
+    /// `set_ex` for many `(key, value, ttl)` entries, batched into as few
+    /// round-trips as the backend allows. Not atomic.
+    async fn set_many_ex(&self, entries: &[(String, String, Duration)]) -> Result<(), AppError>;
+
+    /// Remaining lifetime in whole seconds, using Redis' conventions: `-2` for a
+    /// missing key and `-1` for a key without expiry.
+    async fn ttl(&self, key: &str) -> Result<i64, AppError>;
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), AppError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError>;
+
+    async fn del(&self, keys: &[String]) -> Result<(), AppError>;
+
+    /// Adds one to `field` of the hash at `key`, creating either as needed, and
+    /// sets the hash to expire after `ttl`.
+    async fn hincr_ex(&self, key: &str, field: &str, ttl: Duration) -> Result<(), AppError>;
+
+    /// Every field of the counter hash at `key`; empty when it does not exist.
+    async fn hget_counts(&self, key: &str) -> Result<HashMap<String, u64>, AppError>;
+
+    /// Adds `member` to the set at `key`, creating it as needed, and makes
+    /// the set live at least `ttl` from now. A longer expiry is kept.
+    async fn sadd_ex(&self, key: &str, member: &str, ttl: Duration) -> Result<(), AppError>;
+
+    /// Every member of the set at `key`; empty when it does not exist.
+    async fn smembers(&self, key: &str) -> Result<Vec<String>, AppError>;
+
+    /// Removes `members` from the set at `key`, deleting it once empty.
+    async fn srem(&self, key: &str, members: &[String]) -> Result<(), AppError>;
+
+    /// Sets `member`'s score in the sorted set at `key`, creating either as
+    /// needed. The set itself never expires.
+    async fn zadd(&self, key: &str, member: &str, score: u64) -> Result<(), AppError>;
+
+    /// Removes `members` from the sorted set at `key`, deleting it once empty.
+    async fn zrem(&self, key: &str, members: &[String]) -> Result<(), AppError>;
+
+    /// Removes and returns up to `count` members scored at most `max_score`,
+    /// lowest first, in one atomic step, so of several callers only one gets
+    /// each member.
+    async fn zpop_due(
+        &self,
+        key: &str,
+        max_score: u64,
+        count: usize,
+    ) -> Result<Vec<String>, AppError>;
+
+    /// Pushes `value` onto the front of the list at `key`, then drops the
+    /// oldest entries past `max_len` or past `max_bytes` in total, and makes
+    /// the list expire after `ttl`.
+    async fn lpush_capped(
+        &self,
+        key: &str,
+        value: &str,
+        max_len: usize,
+        max_bytes: usize,
+        ttl: Duration,
+    ) -> Result<(), AppError>;
+
+    /// Every entry of the list at `key`, newest first; empty when it does not
+    /// exist.
+    async fn lrange_all(&self, key: &str) -> Result<Vec<String>, AppError>;
+
+    /// Round-trips to every backend instance, for readiness checks.
+    async fn ping(&self) -> Result<(), AppError>;
+
+    /// Reads `channel` and counts the read on `counter`. The read that reaches
+    /// `max_reads` deletes both and leaves `tombstone` behind for `ttl`.
+    async fn consume_read(
+        &self,
+        channel: &str,
+        counter: &str,
+        tombstone: &str,
+        max_reads: u32,
+        ttl: Duration,
+    ) -> Result<Option<String>, AppError>;
+
+    /// Increments `key` unless that would take it past `cap`. A counter
+    /// without an expiry gets `ttl`; later claims leave it alone, so slots
+    /// leaked by a crashed instance age out even while others keep claiming.
+    /// Returns whether the slot was claimed.
+    async fn acquire_slot(&self, key: &str, cap: u32, ttl: Duration) -> Result<bool, AppError>;
+
+    /// Gives back a slot claimed with `acquire_slot`. A counter that has
+    /// expired in the meantime is left missing, and none goes below zero.
+    async fn release_slot(&self, key: &str) -> Result<(), AppError>;
+
+    /// Takes one token from the bucket at `key`, which holds up to `capacity`
+    /// and regains one every `refill`. Returns how long until a token is
+    /// available when the bucket is empty, taking nothing.
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, AppError>;
+
+    /// Deletes `key` only while it still holds `value`.
+    async fn delete_if_equals(&self, key: &str, value: &str) -> Result<bool, AppError>;
+
+    /// Overwrites `key` with `value` only while it still holds `expected`,
+    /// keeping its expiry. Returns whether the swap happened.
+    async fn replace_if_equals(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+    ) -> Result<bool, AppError>;
+
+    /// Moves the first `(from, to)` pair only if `from` exists and `to` is
+    /// free, then carries the remaining pairs along where their source exists.
+    /// TTLs move with the keys.
+    async fn rename_nx(&self, renames: &[(String, String)]) -> Result<RenameOutcome, AppError>;
+
+    /// One page of the keys starting with `prefix`, resuming from `cursor`
+    /// (`None` to start). `count` is a hint, as with Redis' `SCAN`: a page may
+    /// hold more or fewer keys, even none, without the scan being over.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<KeyPage, AppError>;
+
+    /// Visits every key starting with `prefix` together with its value length.
+    async fn scan_sizes(
+        &self,
+        prefix: &str,
+        visit: &mut (dyn FnMut(String, u64) + Send),
+    ) -> Result<(), AppError>;
+
+    /// Which backend instance owns channel `id`. Single-instance stores only
+    /// have shard 0.
+    fn shard_of(&self, _id: &str) -> usize {
+        0
+    }
+}
+
+/// Outcome of moving a channel to a new id.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameOutcome {
+    Renamed,
+    SourceMissing,
+    TargetTaken,
+}
@@ -0,0 +1,664 @@
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use async_trait::async_trait;
+use redis::{AsyncCommands, IntoConnectionInfo, Script, aio::ConnectionManager};
+use sha2::{Digest, Sha256};
+
+use super::{KeyPage, RenameOutcome, Storage};
+use crate::error::AppError;
+
+const SCAN_BATCH_SIZE: usize = 500;
+
+/// Counts one read and, at the limit, swaps the channel for a tombstone.
+const CONSUME_READ_SCRIPT: &str = r#"
+local raw = redis.call('GET', KEYS[1])
+if not raw then
+    return false
+end
+local count = redis.call('INCR', KEYS[2])
+redis.call('EXPIRE', KEYS[2], ARGV[2])
+if count >= tonumber(ARGV[1]) then
+    redis.call('DEL', KEYS[1], KEYS[2])
+    redis.call('SET', KEYS[3], '1', 'EX', ARGV[2])
+end
+return raw
+"#;
+
+/// Claims a slot only while the counter is below its cap. The TTL is set only
+/// on a counter that has none, so slots leaked by a crashed instance age out
+/// however often the channel is subscribed to.
+const ACQUIRE_SLOT_SCRIPT: &str = r#"
+local count = redis.call('INCR', KEYS[1])
+if redis.call('TTL', KEYS[1]) < 0 then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+if count > tonumber(ARGV[1]) then
+    redis.call('DECR', KEYS[1])
+    return 0
+end
+return 1
+"#;
+
+/// Gives a slot back without recreating an expired counter or going below 0.
+const RELEASE_SLOT_SCRIPT: &str = r#"
+local count = tonumber(redis.call('GET', KEYS[1]))
+if count and count > 0 then
+    redis.call('DECR', KEYS[1])
+end
+return 1
+"#;
+
+/// Adds a set member, only ever pushing the set's expiry further out.
+const SADD_EX_SCRIPT: &str = r#"
+redis.call('SADD', KEYS[1], ARGV[1])
+if redis.call('TTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+/// Takes the lowest-scored members up to a score, so each is handed out once.
+const ZPOP_DUE_SCRIPT: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+if #due > 0 then
+    redis.call('ZREM', KEYS[1], unpack(due))
+end
+return due
+"#;
+
+/// Token bucket kept as the server time (ms) at which it is full again, so
+/// every instance agrees on the clock. Returns the wait in ms, 0 once taken.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local refill = tonumber(ARGV[2])
+local burst = (tonumber(ARGV[1]) - 1) * refill
+local full_at = math.max(tonumber(redis.call('GET', KEYS[1]) or now), now)
+local wait = full_at - burst - now
+if wait > 0 then
+    return wait
+end
+full_at = full_at + refill
+redis.call('SET', KEYS[1], full_at, 'PX', full_at - now)
+return 0
+"#;
+
+/// Pushes onto a list and trims it to a length and then a byte budget,
+/// oldest entries first.
+const LPUSH_CAPPED_SCRIPT: &str = r#"
+redis.call('LPUSH', KEYS[1], ARGV[1])
+redis.call('LTRIM', KEYS[1], 0, tonumber(ARGV[2]) - 1)
+local total = 0
+for i, entry in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+    total = total + #entry
+    if total > tonumber(ARGV[3]) then
+        if i == 1 then
+            redis.call('DEL', KEYS[1])
+        else
+            redis.call('LTRIM', KEYS[1], 0, i - 2)
+        end
+        break
+    end
+end
+redis.call('EXPIRE', KEYS[1], ARGV[4])
+return 1
+"#;
+
+/// Compare-and-delete, so a lock that expired and was re-acquired by another
+/// writer is never released by mistake.
+const DELETE_IF_EQUALS_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+const REPLACE_IF_EQUALS_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+    return 1
+end
+return 0
+"#;
+
+/// Returns -1 when the source is gone and 0 when the target is taken. Keys
+/// come in (from, to) pairs; only the first pair is required to exist.
+const RENAME_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return -1
+end
+if redis.call('RENAMENX', KEYS[1], KEYS[2]) == 0 then
+    return 0
+end
+for i = 3, #KEYS, 2 do
+    if redis.call('EXISTS', KEYS[i]) == 1 then
+        redis.call('RENAME', KEYS[i], KEYS[i + 1])
+    end
+end
+return 1
+"#;
+
+/// Redis, optionally sharded across several instances. Keys are routed by the
+/// channel id in their last `:` segment, so all keys of a channel share a shard.
+/// A shard may have a read replica, which only `get_replica` uses.
+pub struct RedisStorage {
+    shard_urls: Vec<String>,
+    shards: Vec<ConnectionManager>,
+    replicas: Vec<Option<ConnectionManager>>,
+}
+
+impl RedisStorage {
+    /// `username`/`password`, when given, override whatever the URLs carry.
+    /// `replica_urls` is either empty or holds one URL per shard, in the same
+    /// order; an empty entry leaves that shard without a replica.
+    pub async fn connect(
+        shard_urls: &[String],
+        replica_urls: &[String],
+        username: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<Self, AppError> {
+        if !replica_urls.is_empty() && replica_urls.len() != shard_urls.len() {
+            return Err(AppError::ReplicaCountMismatch {
+                shards: shard_urls.len(),
+                replicas: replica_urls.len(),
+            });
+        }
+        let mut shards = Vec::with_capacity(shard_urls.len());
+        for url in shard_urls {
+            shards.push(connect_one(url, username, password).await?);
+        }
+        let mut replicas = vec![None; shard_urls.len()];
+        for (replica, url) in replicas.iter_mut().zip(replica_urls) {
+            if !url.is_empty() {
+                *replica = Some(connect_one(url, username, password).await?);
+            }
+        }
+
+        Ok(Self {
+            shard_urls: shard_urls.to_vec(),
+            shards,
+            replicas,
+        })
+    }
+
+    fn conn(&self, key: &str) -> ConnectionManager {
+        self.shards[self.shard_of_key(key)].clone()
+    }
+
+    fn replica(&self, key: &str) -> Option<ConnectionManager> {
+        self.replicas[self.shard_of_key(key)].clone()
+    }
+
+    fn shard_of_key(&self, key: &str) -> usize {
+        let id = key.rsplit_once(':').map_or(key, |(_, id)| id);
+        self.shard_of(id)
+    }
+}
+
+async fn connect_one(
+    url: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<ConnectionManager, AppError> {
+    let mut info = url.into_connection_info()?;
+    if let Some(username) = username {
+        info.redis.username = Some(username.to_owned());
+    }
+    if let Some(password) = password {
+        info.redis.password = Some(password.to_owned());
+    }
+    let client = redis::Client::open(info)?;
+    Ok(ConnectionManager::new(client).await?)
+}
+
+/// Answers from `replica` when it has the key; on a miss, which may only be
+/// replication lag, or a replica error, asks `primary` instead.
+async fn prefer_replica<R, P>(
+    key: &str,
+    replica: Option<R>,
+    primary: P,
+) -> Result<Option<String>, AppError>
+where
+    R: Future<Output = Result<Option<String>, AppError>>,
+    P: Future<Output = Result<Option<String>, AppError>>,
+{
+    if let Some(replica) = replica {
+        match replica.await {
+            Ok(Some(value)) => return Ok(Some(value)),
+            Ok(None) => tracing::debug!(%key, "replica miss, reading from the primary"),
+            Err(err) => tracing::warn!(%key, error = ?err, "replica read failed"),
+        }
+    }
+    primary.await
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self.conn(key).get(key).await?)
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(redis::cmd("GETDEL")
+            .arg(key)
+            .query_async(&mut self.conn(key))
+            .await?)
+    }
+
+    async fn get_replica(&self, key: &str) -> Result<Option<String>, AppError> {
+        let replica = self
+            .replica(key)
+            .map(|mut replica| async move { Ok::<_, AppError>(replica.get(key).await?) });
+        prefer_replica(key, replica, self.get(key)).await
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<(), AppError> {
+        let _: () = self
+            .conn(key)
+            .set_ex(key, value, ttl.as_secs() as usize)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_many_ex(&self, entries: &[(String, String, Duration)]) -> Result<(), AppError> {
+        // One pipeline per shard.
+        let mut pipes: Vec<Option<redis::Pipeline>> = vec![None; self.shards.len()];
+        for (key, value, ttl) in entries {
+            pipes[self.shard_of_key(key)]
+                .get_or_insert_with(redis::pipe)
+                .set_ex(key, value, ttl.as_secs() as usize)
+                .ignore();
+        }
+        for (shard, pipe) in self.shards.iter().zip(pipes) {
+            if let Some(pipe) = pipe {
+                let _: () = pipe.query_async(&mut shard.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_nx_ex(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, AppError> {
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.conn(key))
+            .await?;
+        Ok(stored.is_some())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<i64, AppError> {
+        Ok(self.conn(key).ttl(key).await?)
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), AppError> {
+        let _: () = self.conn(key).expire(key, ttl.as_secs() as usize).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(self.conn(key).exists(key).await?)
+    }
+
+    async fn del(&self, keys: &[String]) -> Result<(), AppError> {
+        for key in keys {
+            let _: () = self.conn(key).del(key).await?;
+        }
+        Ok(())
+    }
+
+    async fn hincr_ex(&self, key: &str, field: &str, ttl: Duration) -> Result<(), AppError> {
+        let _: () = redis::pipe()
+            .atomic()
+            .hincr(key, field, 1)
+            .ignore()
+            .expire(key, ttl.as_secs() as usize)
+            .ignore()
+            .query_async(&mut self.conn(key))
+            .await?;
+        Ok(())
+    }
+
+    async fn hget_counts(&self, key: &str) -> Result<HashMap<String, u64>, AppError> {
+        Ok(self.conn(key).hgetall(key).await?)
+    }
+
+    async fn sadd_ex(&self, key: &str, member: &str, ttl: Duration) -> Result<(), AppError> {
+        let _: i64 = Script::new(SADD_EX_SCRIPT)
+            .key(key)
+            .arg(member)
+            .arg(ttl.as_secs())
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(())
+    }
+
+    async fn lpush_capped(
+        &self,
+        key: &str,
+        value: &str,
+        max_len: usize,
+        max_bytes: usize,
+        ttl: Duration,
+    ) -> Result<(), AppError> {
+        let _: i64 = Script::new(LPUSH_CAPPED_SCRIPT)
+            .key(key)
+            .arg(value)
+            .arg(max_len)
+            .arg(max_bytes)
+            .arg(ttl.as_secs())
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(())
+    }
+
+    async fn lrange_all(&self, key: &str) -> Result<Vec<String>, AppError> {
+        Ok(self.conn(key).lrange(key, 0, -1).await?)
+    }
+
+    async fn smembers(&self, key: &str) -> Result<Vec<String>, AppError> {
+        Ok(self.conn(key).smembers(key).await?)
+    }
+
+    async fn srem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        let _: () = self.conn(key).srem(key, members).await?;
+        Ok(())
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: u64) -> Result<(), AppError> {
+        let _: () = self.conn(key).zadd(key, member, score).await?;
+        Ok(())
+    }
+
+    async fn zrem(&self, key: &str, members: &[String]) -> Result<(), AppError> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        let _: () = self.conn(key).zrem(key, members).await?;
+        Ok(())
+    }
+
+    async fn zpop_due(
+        &self,
+        key: &str,
+        max_score: u64,
+        count: usize,
+    ) -> Result<Vec<String>, AppError> {
+        Ok(Script::new(ZPOP_DUE_SCRIPT)
+            .key(key)
+            .arg(max_score)
+            .arg(count)
+            .invoke_async(&mut self.conn(key))
+            .await?)
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        for shard in &self.shards {
+            let _: String = redis::cmd("PING").query_async(&mut shard.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn consume_read(
+        &self,
+        channel: &str,
+        counter: &str,
+        tombstone: &str,
+        max_reads: u32,
+        ttl: Duration,
+    ) -> Result<Option<String>, AppError> {
+        Ok(Script::new(CONSUME_READ_SCRIPT)
+            .key(channel)
+            .key(counter)
+            .key(tombstone)
+            .arg(max_reads)
+            .arg(ttl.as_secs())
+            .invoke_async(&mut self.conn(channel))
+            .await?)
+    }
+
+    async fn acquire_slot(&self, key: &str, cap: u32, ttl: Duration) -> Result<bool, AppError> {
+        let acquired: i64 = Script::new(ACQUIRE_SLOT_SCRIPT)
+            .key(key)
+            .arg(cap)
+            .arg(ttl.as_secs())
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(acquired == 1)
+    }
+
+    async fn release_slot(&self, key: &str) -> Result<(), AppError> {
+        let _: i64 = Script::new(RELEASE_SLOT_SCRIPT)
+            .key(key)
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(())
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> Result<Option<Duration>, AppError> {
+        let wait_ms: u64 = Script::new(TAKE_TOKEN_SCRIPT)
+            .key(key)
+            .arg(capacity)
+            .arg(refill.as_millis().max(1) as u64)
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok((wait_ms > 0).then(|| Duration::from_millis(wait_ms)))
+    }
+
+    async fn delete_if_equals(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        let deleted: i64 = Script::new(DELETE_IF_EQUALS_SCRIPT)
+            .key(key)
+            .arg(value)
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(deleted == 1)
+    }
+
+    async fn replace_if_equals(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+    ) -> Result<bool, AppError> {
+        let replaced: i64 = Script::new(REPLACE_IF_EQUALS_SCRIPT)
+            .key(key)
+            .arg(expected)
+            .arg(value)
+            .invoke_async(&mut self.conn(key))
+            .await?;
+        Ok(replaced == 1)
+    }
+
+    async fn rename_nx(&self, renames: &[(String, String)]) -> Result<RenameOutcome, AppError> {
+        let Some((first, _)) = renames.first() else {
+            return Ok(RenameOutcome::SourceMissing);
+        };
+        let script = Script::new(RENAME_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        for (from, to) in renames {
+            invocation.key(from).key(to);
+        }
+        let outcome: i64 = invocation.invoke_async(&mut self.conn(first)).await?;
+        Ok(match outcome {
+            -1 => RenameOutcome::SourceMissing,
+            0 => RenameOutcome::TargetTaken,
+            _ => RenameOutcome::Renamed,
+        })
+    }
+
+    /// The cursor is `{shard}:{scan cursor}`; shards are scanned in turn.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<KeyPage, AppError> {
+        let (shard, position) = match cursor {
+            None => (0, 0),
+            Some(cursor) => cursor
+                .split_once(':')
+                .and_then(|(shard, position)| Some((shard.parse().ok()?, position.parse().ok()?)))
+                .filter(|&(shard, _): &(usize, u64)| shard < self.shards.len())
+                .ok_or(AppError::InvalidScanCursor)?,
+        };
+        let mut conn = self.shards[shard].clone();
+        let (position, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(position)
+            .arg("MATCH")
+            .arg(format!("{prefix}*"))
+            .arg("COUNT")
+            .arg(count)
+            .query_async(&mut conn)
+            .await?;
+
+        let next = if position != 0 {
+            Some(format!("{shard}:{position}"))
+        } else if shard + 1 < self.shards.len() {
+            Some(format!("{}:0", shard + 1))
+        } else {
+            None
+        };
+        Ok(KeyPage { keys, next })
+    }
+
+    async fn scan_sizes(
+        &self,
+        prefix: &str,
+        visit: &mut (dyn FnMut(String, u64) + Send),
+    ) -> Result<(), AppError> {
+        for shard in &self.shards {
+            let mut conn = shard.clone();
+            let mut cursor: u64 = 0;
+            loop {
+                let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(format!("{prefix}*"))
+                    .arg("COUNT")
+                    .arg(SCAN_BATCH_SIZE)
+                    .query_async(&mut conn)
+                    .await?;
+
+                if !keys.is_empty() {
+                    let mut pipe = redis::pipe();
+                    for key in &keys {
+                        pipe.cmd("STRLEN").arg(key);
+                    }
+                    let sizes: Vec<u64> = pipe.query_async(&mut conn).await?;
+                    for (key, bytes) in keys.into_iter().zip(sizes) {
+                        visit(key, bytes);
+                    }
+                }
+
+                cursor = next;
+                if cursor == 0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn shard_of(&self, id: &str) -> usize {
+        shard_index(&self.shard_urls, id)
+    }
+}
+
+/// Rendezvous (highest-random-weight) hashing: each id goes to the shard whose
+/// URL scores highest for it. The choice is stable across instances and list
+/// order, and adding a shard only moves the ids that now score highest on it.
+fn shard_index(shard_urls: &[String], id: &str) -> usize {
+    shard_urls
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, url)| {
+            let digest = Sha256::new()
+                .chain_update(url.as_bytes())
+                .chain_update([0])
+                .chain_update(id.as_bytes())
+                .finalize();
+            u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{Ready, ready},
+        time::Duration,
+    };
+
+    use super::{prefer_replica, shard_index};
+    use crate::error::AppError;
+
+    type Read = Ready<Result<Option<String>, AppError>>;
+
+    fn found(value: &str) -> Read {
+        ready(Ok(Some(value.to_owned())))
+    }
+
+    #[tokio::test]
+    async fn replica_reads_fall_back_to_the_primary() {
+        let hit = prefer_replica("k", Some(found("replica")), found("primary"));
+        assert_eq!(hit.await.unwrap().as_deref(), Some("replica"));
+
+        let lagging = prefer_replica("k", Some(ready(Ok(None))), found("primary"));
+        assert_eq!(lagging.await.unwrap().as_deref(), Some("primary"));
+
+        let failing = prefer_replica(
+            "k",
+            Some(ready(Err(AppError::StorageUnavailable {
+                retry_after: Duration::from_secs(1),
+            }))),
+            found("primary"),
+        );
+        assert_eq!(failing.await.unwrap().as_deref(), Some("primary"));
+
+        let without = prefer_replica("k", None::<Read>, found("primary"));
+        assert_eq!(without.await.unwrap().as_deref(), Some("primary"));
+
+        let missing = prefer_replica("k", Some(ready(Ok(None))), ready(Ok(None)));
+        assert_eq!(missing.await.unwrap(), None);
+    }
+
+    fn shard_urls(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|index| format!("redis://shard-{index}:6379"))
+            .collect()
+    }
+
+    #[test]
+    fn an_id_always_routes_to_the_same_shard() {
+        let urls = shard_urls(4);
+        let first = shard_index(&urls, "abc12345");
+        for _ in 0..10 {
+            assert_eq!(shard_index(&urls, "abc12345"), first);
+        }
+
+        let mut reversed = urls.clone();
+        reversed.reverse();
+        assert_eq!(urls[first], reversed[shard_index(&reversed, "abc12345")]);
+    }
+
+    #[test]
+    fn different_ids_spread_across_shards() {
+        let urls = shard_urls(4);
+        let used: std::collections::HashSet<_> = (0..64)
+            .map(|n| shard_index(&urls, &format!("channel-{n}")))
+            .collect();
+        assert!(used.len() > 1);
+        assert_eq!(shard_index(&shard_urls(1), "anything"), 0);
+    }
+}
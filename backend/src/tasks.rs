@@ -0,0 +1,95 @@
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::sync::Semaphore;
+
+/// Spawns fire-and-forget work that may be lost, such as download counters,
+/// with at most `limit` tasks alive at once. Work offered while all are busy is
+/// dropped and counted rather than queued, so a burst cannot pile up tasks.
+pub struct BackgroundTasks {
+    permits: Arc<Semaphore>,
+    dropped: AtomicU64,
+}
+
+impl BackgroundTasks {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limit.max(1))),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `work` on its own task if a slot is free; returns whether it did.
+    pub fn spawn<F>(&self, work: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(dropped, "background task limit reached, dropping work");
+            return false;
+        };
+        tokio::spawn(async move {
+            work.await;
+            drop(permit);
+        });
+        true
+    }
+
+    /// Work dropped because every slot was busy, since startup.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::sync::Notify;
+
+    use super::BackgroundTasks;
+
+    #[tokio::test]
+    async fn no_more_than_the_limit_run_at_once() {
+        let tasks = BackgroundTasks::new(2);
+        let release = Arc::new(Notify::new());
+        let running = Arc::new(AtomicUsize::new(0));
+        let blocked = || {
+            let release = release.clone();
+            let running = running.clone();
+            async move {
+                running.fetch_add(1, Ordering::SeqCst);
+                release.notified().await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            }
+        };
+
+        assert!(tasks.spawn(blocked()));
+        assert!(tasks.spawn(blocked()));
+        assert!(!tasks.spawn(blocked()));
+        assert_eq!(tasks.dropped(), 1);
+        while running.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        release.notify_waiters();
+        while tasks.permits.available_permits() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert!(tasks.spawn(blocked()));
+        assert_eq!(tasks.dropped(), 1);
+    }
+}